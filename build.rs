@@ -0,0 +1,75 @@
+//! Compress the files embedded by `asset!` at build time, to `$OUT_DIR/assets/{caller}/{path}.{gz,br}`.
+//!
+//! An empty output means the compressed one is not smaller. The text is stripped like `strip_str!` first,
+//! so the outputs match the embedded data.
+
+use std::io::Write as _;
+use std::path::Path;
+
+/// Same as `strip_do` in `utils::str_const_ops_`.
+fn strip(src: &[u8]) -> Vec<u8> {
+    let mut o = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < src.len() && (src[i] == b'\n' || src[i] == b' ') {
+            i += 1;
+        }
+        while i < src.len() && src[i] != b'\n' {
+            o.push(src[i]);
+            i += 1;
+        }
+        if i < src.len() {
+            o.push(b'\n');
+        } else {
+            break;
+        }
+    }
+    o
+}
+
+fn compress(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let level = flate2::Compression::best();
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+    encoder.write_all(data).unwrap();
+    let gzip = encoder.finish().unwrap();
+    let mut br = Vec::new();
+    let params = brotli::enc::BrotliEncoderParams::default(); // quality 11
+    brotli::BrotliCompress(&mut &data[..], &mut br, &params).unwrap();
+    let smaller = |v: Vec<u8>| if v.len() < data.len() { v } else { Vec::new() };
+    (smaller(gzip), smaller(br))
+}
+
+fn visit(dir: &Path, out: &Path, strip_text: bool) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            visit(&path, out, strip_text);
+            continue;
+        }
+        if path.extension().map_or(true, |v| v != "rs") {
+            continue;
+        }
+        let code = std::fs::read_to_string(&path).unwrap();
+        for part in code.split("asset!(\"").skip(1) {
+            let name = part.split('"').next().unwrap();
+            // the usage examples in comments point to nothing
+            let Ok(raw) = std::fs::read(path.parent().unwrap().join(name)) else {
+                continue;
+            };
+            let data = if strip_text { strip(&raw) } else { raw };
+            let (gzip, br) = compress(&data);
+            let target = out.join(&path).join(name);
+            std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+            std::fs::write(format!("{}.gz", target.display()), gzip).unwrap();
+            std::fs::write(format!("{}.br", target.display()), br).unwrap();
+        }
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("assets");
+    // `strip_str!` strips only without debug assertions
+    let strip_text = std::env::var_os("CARGO_CFG_DEBUG_ASSERTIONS").is_none();
+    visit(Path::new("src"), &out, strip_text);
+}
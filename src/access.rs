@@ -0,0 +1,283 @@
+//! Access log middleware, with per-route latency histograms.
+
+use crate::log;
+use crate::utils::LazyLock;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::Request;
+use axum::http::header::{HeaderMap, HeaderName};
+use axum::http::header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE};
+use axum::http::header::{REFERER, USER_AGENT};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, UNIX_EPOCH};
+
+/// Rotate when the log file grows larger than this.
+const ROTATE_SIZE: u64 = 1024 * 1024 * 8;
+/// How many rotated files to keep, as `ksite.access.log.1` to `ksite.access.log.N`.
+const ROTATE_KEEP: usize = 3;
+
+/// Never write these headers' values to any log.
+const SENSITIVE_HEADERS: [HeaderName; 4] = [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE];
+
+/// Upper bounds of histogram buckets, in milliseconds. The last implicit bucket is `+Inf`.
+pub const BUCKETS_MS: [u64; 11] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500];
+
+#[derive(Default, Clone)]
+pub struct Histogram {
+    /// Not cumulative, `counts[i]` is the number of samples in `(BUCKETS_MS[i-1], BUCKETS_MS[i]]`.
+    pub counts: [u64; BUCKETS_MS.len() + 1],
+    pub count: u64,
+    pub sum_us: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, us: u64) {
+        let i = BUCKETS_MS.partition_point(|&v| v * 1000 < us);
+        self.counts[i] += 1;
+        self.count += 1;
+        self.sum_us += us;
+    }
+
+    /// Estimate the quantile by bucket upper bound, returns `None` if no samples or in `+Inf`.
+    pub fn quantile_ms(&self, q: f64) -> Option<u64> {
+        let rank = (self.count as f64 * q).ceil().max(1.0) as u64;
+        let mut acc = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            acc += c;
+            if acc >= rank {
+                return BUCKETS_MS.get(i).copied();
+            }
+        }
+        None
+    }
+}
+
+/// Latency histograms, key is `"{method} /{unit}"` by `route_label`.
+pub static HISTOGRAMS: Mutex<BTreeMap<String, Histogram>> = Mutex::new(BTreeMap::new());
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+static LOG_FILE: LazyLock<Mutex<LogFile>> = LazyLock::new(|| {
+    let path = std::env::current_exe()
+        .unwrap()
+        .with_extension("access.log");
+    let file = open_append(&path);
+    let len = file.metadata().unwrap().len();
+    Mutex::new(LogFile { path, file, len })
+});
+
+fn open_append(path: &PathBuf) -> File {
+    File::options()
+        .append(true)
+        .create(true)
+        .read(true) // allow admin unit to read
+        .open(path)
+        .unwrap()
+}
+
+fn write_record(line: &[u8]) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    if log_file.len + line.len() as u64 > ROTATE_SIZE {
+        let rotated = |i: usize| {
+            let mut p = log_file.path.clone().into_os_string();
+            p.push(format!(".{i}"));
+            PathBuf::from(p)
+        };
+        for i in (1..ROTATE_KEEP).rev() {
+            std::fs::rename(rotated(i), rotated(i + 1)).ok();
+        }
+        std::fs::rename(&log_file.path, rotated(1)).ok();
+        log_file.file = open_append(&log_file.path);
+        log_file.len = 0;
+    }
+    if log_file.file.write_all(line).is_ok() {
+        log_file.len += line.len() as u64;
+    }
+}
+
+/// Format headers for debug logging, with sensitive values replaced.
+pub fn redacted(headers: &HeaderMap) -> String {
+    let mut ret = String::from("{");
+    for (k, v) in headers {
+        if ret.len() > 1 {
+            ret += ", ";
+        }
+        ret += k.as_str();
+        ret += ": ";
+        if SENSITIVE_HEADERS.contains(k) {
+            ret += "<redacted>";
+        } else {
+            ret += &format!("{v:?}");
+        }
+    }
+    ret + "}"
+}
+
+/// The unit name is the first segment of path, like `"dav"` for `"/dav/a/b.txt"`.
+pub fn unit_name(path: &str) -> &str {
+    let path = path.trim_start_matches('/');
+    match path.split_once('/') {
+        Some((v, _)) => v,
+        None => path,
+    }
+}
+
+/// The methods kept in the route labels, the dav ones included.
+const KNOWN_METHODS: [&str; 18] = [
+    "GET",
+    "HEAD",
+    "POST",
+    "PUT",
+    "DELETE",
+    "OPTIONS",
+    "PATCH",
+    "CONNECT",
+    "TRACE",
+    "PROPFIND",
+    "PROPPATCH",
+    "MKCOL",
+    "MKCALENDAR",
+    "COPY",
+    "MOVE",
+    "LOCK",
+    "UNLOCK",
+    "REPORT",
+];
+
+/// The histogram key, unknown methods and units are `"other"` so the count of keys is bounded.
+fn route_label(method: &str, unit: &str) -> String {
+    let method = match KNOWN_METHODS.contains(&method) {
+        true => method,
+        false => "other",
+    };
+    let unit = match crate::units::ALL.iter().any(|u| u.name() == unit) {
+        true => unit,
+        false => "other",
+    };
+    format!("{method} /{unit}")
+}
+
+/// Count the bytes passed through, and call the callback on drop.
+struct Counted<F: FnOnce(u64) + Send> {
+    inner: Body,
+    bytes: u64,
+    on_drop: Option<F>,
+}
+
+impl<F: FnOnce(u64) + Send + Unpin> HttpBody for Counted<F> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let ret = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &ret {
+            if let Some(data) = frame.data_ref() {
+                self.bytes += data.len() as u64;
+            }
+        }
+        ret
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<F: FnOnce(u64) + Send> Drop for Counted<F> {
+    fn drop(&mut self) {
+        if let Some(f) = self.on_drop.take() {
+            f(self.bytes);
+        }
+    }
+}
+
+pub async fn access_layer(req: Request, next: Next) -> Response {
+    let instant = Instant::now();
+    let time = UNIX_EPOCH.elapsed().unwrap().as_millis() as u64;
+    let peer = req
+        .extensions()
+        .get::<SocketAddr>()
+        .map(|v| v.ip().to_string());
+    let method = req.method().as_str().to_owned();
+    let path = req.uri().path().to_owned(); // without query, which may contain secrets
+    let version = format!("{:?}", req.version());
+    let unit = unit_name(&path).to_owned();
+    let header_str = |headers: &HeaderMap, k| {
+        let v = headers.get(k)?.to_str().ok()?;
+        Some(v.to_owned())
+    };
+    let referer = header_str(req.headers(), REFERER);
+    let user_agent = header_str(req.headers(), USER_AGENT);
+    log!(trac: "request {version} {method} {} {}", req.uri(), redacted(req.headers()));
+
+    let bytes_in = Arc::new(AtomicU64::new(0));
+    let req = {
+        let bytes_in = bytes_in.clone();
+        req.map(|inner| {
+            let on_drop = move |v| bytes_in.store(v, Ordering::SeqCst);
+            Body::new(Counted {
+                inner,
+                bytes: 0,
+                on_drop: Some(on_drop),
+            })
+        })
+    };
+
+    let res = next.run(req).await;
+    let latency_us = instant.elapsed().as_micros() as u64;
+    let status = res.status().as_u16();
+    let route = route_label(&method, &unit);
+    HISTOGRAMS
+        .lock()
+        .unwrap()
+        .entry(route)
+        .or_default()
+        .observe(latency_us);
+
+    res.map(|inner| {
+        let on_drop = move |bytes_out| {
+            let record = serde_json::json!({
+                "time": time,
+                "ip": peer,
+                "method": method,
+                "path": path,
+                "status": status,
+                "bytes_in": bytes_in.load(Ordering::SeqCst),
+                "bytes_out": bytes_out,
+                "latency_us": latency_us,
+                "version": version,
+                "unit": unit,
+                "referer": referer,
+                "user_agent": user_agent,
+            });
+            let mut line = record.to_string().into_bytes();
+            line.push(b'\n');
+            write_record(&line);
+        };
+        Body::new(Counted {
+            inner,
+            bytes: 0,
+            on_drop: Some(on_drop),
+        })
+    })
+}
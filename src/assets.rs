@@ -0,0 +1,315 @@
+//! Embedded static assets, served under content-hashed urls with immutable caching.
+
+use axum::body::Bytes;
+use axum::extract::Path;
+use axum::http::header::*;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{MethodRouter, Router};
+use std::collections::HashMap;
+use std::io::Write as _;
+
+/// Cache for a year and never revalidate, the url changes when the content changes.
+const IMMUTABLE: &str = "public,max-age=31536000,immutable";
+
+/// Always revalidate by `ETag`, for the pages at fixed urls.
+const NO_CACHE: &str = "no-cache";
+
+pub struct Asset {
+    /// File name with extension, like `page.mjs`.
+    pub name: &'static str,
+    pub content_type: &'static str,
+    pub data: Bytes,
+    /// FNV-1a of the data, computed at build time if embedded by `asset!`.
+    pub hash: u64,
+    /// Compressed at build time if embedded by `asset!`, `None` if not smaller.
+    gzip: Option<Bytes>,
+    /// Like `gzip`.
+    br: Option<Bytes>,
+}
+
+pub const fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < data.len() {
+        hash ^= data[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Guess by the file extension, case sensitive.
+pub const fn content_type(name: &str) -> &'static str {
+    let name = name.as_bytes();
+    let types: [(&[u8], &str); 24] = [
+        (b".html", "text/html; charset=utf-8"),
+        (b".js", "text/javascript; charset=utf-8"),
+        (b".mjs", "text/javascript; charset=utf-8"),
+        (b".css", "text/css; charset=utf-8"),
+        (b".txt", "text/plain; charset=utf-8"),
+        (b".md", "text/markdown; charset=utf-8"),
+        (b".ics", "text/calendar; charset=utf-8"),
+        (b".vcf", "text/vcard; charset=utf-8"),
+        (b".xml", "application/xml"),
+        (b".json", "application/json"),
+        (b".wasm", "application/wasm"),
+        (b".pdf", "application/pdf"),
+        (b".zip", "application/zip"),
+        (b".svg", "image/svg+xml"),
+        (b".png", "image/png"),
+        (b".jpg", "image/jpeg"),
+        (b".jpeg", "image/jpeg"),
+        (b".gif", "image/gif"),
+        (b".webp", "image/webp"),
+        (b".ico", "image/x-icon"),
+        (b".mp3", "audio/mpeg"),
+        (b".mp4", "video/mp4"),
+        (b".webm", "video/webm"),
+        (b".woff2", "font/woff2"),
+    ];
+    let mut i = 0;
+    while i < types.len() {
+        let ext = types[i].0;
+        let mut j = 0;
+        while j < ext.len() && ext.len() <= name.len() {
+            if name[name.len() - ext.len() + j] != ext[j] {
+                break;
+            }
+            j += 1;
+        }
+        if j == ext.len() {
+            return types[i].1;
+        }
+        i += 1;
+    }
+    "application/octet-stream"
+}
+
+/// The q-value of the content coding in `Accept-Encoding`, `0` if not acceptable.
+pub fn accept_q(headers: &HeaderMap, coding: &str) -> f32 {
+    let Some(v) = headers.get(ACCEPT_ENCODING).and_then(|v| v.to_str().ok()) else {
+        return 0.0;
+    };
+    let (mut exact, mut wildcard) = (None, None);
+    for item in v.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap();
+        let q = parts.find_map(|v| v.strip_prefix("q="));
+        let q = q.and_then(|v| v.parse().ok()).unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            exact = Some(q);
+        } else if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    exact.or(wildcard).unwrap_or(0.0)
+}
+
+/// Whether the `If-None-Match` matches the etag, with the weak comparison.
+fn none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(v) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    v.split(',').map(str::trim).any(|v| {
+        let v = v.strip_prefix("W/").unwrap_or(v);
+        v == "*" || v == etag
+    })
+}
+
+/// Empty means not smaller, see `build.rs`.
+const fn variant(v: &'static [u8]) -> Option<Bytes> {
+    match v.is_empty() {
+        true => None,
+        false => Some(Bytes::from_static(v)),
+    }
+}
+
+impl Asset {
+    /// The `gzip` and `br` are compressed by `build.rs`.
+    pub const fn new_static(
+        name: &'static str,
+        data: &'static [u8],
+        gzip: &'static [u8],
+        br: &'static [u8],
+    ) -> Self {
+        Self {
+            name,
+            content_type: content_type(name),
+            data: Bytes::from_static(data),
+            hash: fnv1a(data),
+            gzip: variant(gzip),
+            br: variant(br),
+        }
+    }
+
+    /// For the content generated at runtime, like pages with hashed urls inside, compressed now.
+    pub fn new(name: &'static str, data: Vec<u8>) -> Self {
+        let smaller = |v: Vec<u8>| (v.len() < data.len()).then(|| Bytes::from(v));
+        let level = flate2::Compression::best();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+        encoder.write_all(&data).unwrap();
+        let gzip = smaller(encoder.finish().unwrap());
+        let mut br = Vec::new();
+        let params = brotli::enc::BrotliEncoderParams::default();
+        brotli::BrotliCompress(&mut &data[..], &mut br, &params).unwrap();
+        let br = smaller(br);
+        Self {
+            name,
+            content_type: content_type(name),
+            hash: fnv1a(&data),
+            data: Bytes::from(data),
+            gzip,
+            br,
+        }
+    }
+
+    /// Like `/assets/page.0123456789abcdef.mjs`.
+    pub fn url(&self) -> String {
+        let (stem, ext) = self.name.rsplit_once('.').unwrap_or((self.name, ""));
+        format!("/assets/{stem}.{:016x}.{ext}", self.hash)
+    }
+
+    /// Returns `(coding, etag suffix, data)` of the best accepted variant, brotli first on a tie.
+    fn variant(&self, headers: &HeaderMap) -> Option<(&'static str, &'static str, &Bytes)> {
+        let br = self
+            .br
+            .as_ref()
+            .map(|v| (accept_q(headers, "br"), ("br", "-br", v)));
+        let gzip = self
+            .gzip
+            .as_ref()
+            .map(|v| (accept_q(headers, "gzip"), ("gzip", "-gz", v)));
+        let best = [br, gzip].into_iter().flatten().filter(|v| v.0 > 0.0);
+        best.reduce(|a, b| if b.0 > a.0 { b } else { a })
+            .map(|v| v.1)
+    }
+
+    fn respond(&self, headers: &HeaderMap, cache_control: &'static str) -> Response {
+        let variant = self.variant(headers);
+        // each encoding is a different representation, so the etag differs
+        let suffix = variant.map_or("", |v| v.1);
+        let etag = format!("\"{:016x}{suffix}\"", self.hash);
+        let mut res = match none_match(headers, &etag) {
+            true => StatusCode::NOT_MODIFIED.into_response(),
+            false => {
+                let data = variant.map_or(&self.data, |v| v.2);
+                let mut res = data.clone().into_response();
+                let h = res.headers_mut();
+                h.insert(CONTENT_TYPE, HeaderValue::from_static(self.content_type));
+                if let Some((coding, _, _)) = variant {
+                    h.insert(CONTENT_ENCODING, HeaderValue::from_static(coding));
+                }
+                res
+            }
+        };
+        let h = res.headers_mut();
+        h.insert(ETAG, etag.try_into().unwrap());
+        h.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+        h.insert(VARY, HeaderValue::from_static("accept-encoding"));
+        res
+    }
+
+    /// Serve at a fixed url, clients revalidate it every time.
+    pub fn serve(&self, headers: &HeaderMap) -> Response {
+        self.respond(headers, NO_CACHE)
+    }
+}
+
+/// Usage: `static SCRIPT: &Asset = asset!("page.mjs");`, the text is stripped like `include_src!`.
+///
+/// The path must be a string literal, as `build.rs` finds the assets by searching the source.
+#[macro_export]
+macro_rules! asset {
+    ($path:literal) => {{
+        const DATA: &str = $crate::strip_str!(include_str!($path));
+        static ASSET: $crate::assets::Asset = $crate::assets::Asset::new_static(
+            $path,
+            DATA.as_bytes(),
+            include_bytes!(concat!(
+                env!("OUT_DIR"),
+                "/assets/",
+                file!(),
+                "/",
+                $path,
+                ".gz"
+            )),
+            include_bytes!(concat!(
+                env!("OUT_DIR"),
+                "/assets/",
+                file!(),
+                "/",
+                $path,
+                ".br"
+            )),
+        );
+        &ASSET
+    }};
+}
+
+/// Serve the assets of enabled units, must be called after `units::load`.
+pub fn service() -> Router {
+    let units = crate::units::enabled().iter();
+    let assets = units.flat_map(|v| v.assets()).map(|v| (v.url(), v));
+    let assets: HashMap<_, _> = assets.collect();
+    Router::new().route(
+        "/assets/:file",
+        MethodRouter::new().get(move |Path(file): Path<String>, headers: HeaderMap| {
+            let res = match assets.get(&format!("/assets/{file}")) {
+                Some(asset) => asset.respond(&headers, IMMUTABLE),
+                None => StatusCode::NOT_FOUND.into_response(),
+            };
+            std::future::ready(res)
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read as _;
+
+    static SCRIPT: &Asset = crate::asset!("units/dav/page.js");
+
+    fn respond(asset: &Asset, accept: &'static str) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept));
+        asset.respond(&headers, NO_CACHE)
+    }
+
+    fn coding(res: &Response) -> Option<&str> {
+        res.headers()
+            .get(CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn build_variants() {
+        let mut v = Vec::new();
+        let gzip = SCRIPT.gzip.as_ref().unwrap();
+        flate2::read::GzDecoder::new(&gzip[..])
+            .read_to_end(&mut v)
+            .unwrap();
+        assert_eq!(v, SCRIPT.data);
+        v.clear();
+        let br = SCRIPT.br.as_ref().unwrap();
+        brotli::Decompressor::new(&br[..], 4096)
+            .read_to_end(&mut v)
+            .unwrap();
+        assert_eq!(v, SCRIPT.data);
+    }
+
+    #[test]
+    fn negotiate() {
+        let page = Asset::new("page.html", "<p>hello</p>".repeat(100).into_bytes());
+        for asset in [SCRIPT, &page] {
+            assert_eq!(coding(&respond(asset, "gzip, deflate, br")), Some("br"));
+            assert_eq!(coding(&respond(asset, "gzip;q=1, br;q=0.5")), Some("gzip"));
+            assert_eq!(coding(&respond(asset, "gzip, br;q=0")), Some("gzip"));
+            assert_eq!(coding(&respond(asset, "identity")), None);
+            assert_eq!(coding(&respond(asset, "*")), Some("br"));
+        }
+        let tiny = Asset::new("a.txt", b"a".to_vec());
+        assert_eq!(coding(&respond(&tiny, "br, gzip")), None);
+    }
+}
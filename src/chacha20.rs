@@ -0,0 +1,70 @@
+//! ChaCha20. RFC 8439, https://www.rfc-editor.org/rfc/rfc8439
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn block(key: &[u8; 32], nonce: &[u8; 12], counter: u32) -> [u8; 64] {
+    let word = |v: &[u8], i: usize| u32::from_le_bytes(v[i * 4..i * 4 + 4].try_into().unwrap());
+    let mut init = [0; 16];
+    init[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    (0..8).for_each(|i| init[4 + i] = word(key, i));
+    init[12] = counter;
+    (0..3).for_each(|i| init[13 + i] = word(nonce, i));
+    let mut s = init;
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+    let mut o = [0; 64];
+    for i in 0..16 {
+        let v = s[i].wrapping_add(init[i]);
+        o[i * 4..i * 4 + 4].copy_from_slice(&v.to_le_bytes());
+    }
+    o
+}
+
+/// Encrypt or decrypt in place, the blocks are counted from `counter`.
+pub fn chacha20(key: &[u8; 32], nonce: &[u8; 12], counter: u32, data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = block(key, nonce, counter.wrapping_add(i as u32));
+        chunk.iter_mut().zip(stream).for_each(|(v, k)| *v ^= k);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc8439_encryption() {
+        // section 2.4.2
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut data = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".to_vec();
+        chacha20(&key, &nonce, 1, &mut data);
+        let hex: String = data.iter().map(|v| format!("{v:02x}")).collect();
+        let expected = concat!(
+            "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b",
+            "f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8",
+            "07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736",
+            "5af90bbf74a35be6b40b8eedf2785e42874d",
+        );
+        assert_eq!(hex, expected);
+        chacha20(&key, &nonce, 1, &mut data);
+        assert!(data.starts_with(b"Ladies and Gentlemen"));
+    }
+}
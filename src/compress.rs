@@ -0,0 +1,190 @@
+//! Compression middleware for dynamic responses, streaming bodies like SSE included.
+
+use crate::assets::accept_q;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::Request;
+use axum::http::header::*;
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http_body::Frame;
+use std::io::Write as _;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Skip small bodies, the saving can't cover the cost.
+const MIN_SIZE: u64 = 1024;
+
+/// Yield the compressed data once it grows larger than this, even if more input is ready.
+const CHUNK_SIZE: usize = 1024 * 64;
+
+fn compressible(content_type: &str) -> bool {
+    let v = content_type.split(';').next().unwrap().trim();
+    v.starts_with("text/")
+        || v.ends_with("json")
+        || v.ends_with("xml")
+        || v.ends_with("javascript")
+        || v == "image/svg+xml"
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    /// The http `deflate` is zlib format actually. https://www.rfc-editor.org/rfc/rfc9110#name-deflate-coding
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    // writing to `Vec` never fails, so the unwraps are fine
+    fn write(&mut self, data: &[u8]) {
+        match self {
+            Self::Gzip(e) => e.write_all(data).unwrap(),
+            Self::Deflate(e) => e.write_all(data).unwrap(),
+        }
+    }
+
+    fn buffered(&mut self) -> &mut Vec<u8> {
+        match self {
+            Self::Gzip(e) => e.get_mut(),
+            Self::Deflate(e) => e.get_mut(),
+        }
+    }
+
+    /// Sync flush, so the client receives all data written till now.
+    fn flush(&mut self) -> Bytes {
+        match self {
+            Self::Gzip(e) => e.flush().unwrap(),
+            Self::Deflate(e) => e.flush().unwrap(),
+        }
+        Bytes::from(std::mem::take(self.buffered()))
+    }
+
+    fn finish(self) -> Bytes {
+        Bytes::from(match self {
+            Self::Gzip(e) => e.finish().unwrap(),
+            Self::Deflate(e) => e.finish().unwrap(),
+        })
+    }
+}
+
+struct Compressed {
+    inner: Body,
+    /// `None` after finished.
+    encoder: Option<Encoder>,
+    /// Some data has been written but not yielded.
+    dirty: bool,
+    /// Held until the data finished, trailers must be the last frame.
+    trailers: Option<HeaderMap>,
+}
+
+impl HttpBody for Compressed {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        loop {
+            let Some(encoder) = &mut this.encoder else {
+                return Poll::Ready(this.trailers.take().map(|v| Ok(Frame::trailers(v))));
+            };
+            let data = match Pin::new(&mut this.inner).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                    Ok(data) => {
+                        encoder.write(&data);
+                        this.dirty = true;
+                        if encoder.buffered().len() < CHUNK_SIZE {
+                            continue; // collect more if ready, for better ratio
+                        }
+                        Bytes::from(std::mem::take(encoder.buffered()))
+                    }
+                    Err(frame) => match frame.into_trailers() {
+                        Ok(trailers) => {
+                            this.trailers = Some(trailers);
+                            this.encoder.take().unwrap().finish()
+                        }
+                        Err(_) => continue, // unknown frame kind
+                    },
+                },
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => this.encoder.take().unwrap().finish(),
+                // the stream like SSE is waiting for the next event, send what we have now
+                Poll::Pending if this.dirty => {
+                    this.dirty = false;
+                    encoder.flush()
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            if !data.is_empty() {
+                return Poll::Ready(Some(Ok(Frame::data(data))));
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none() && self.trailers.is_none()
+    }
+}
+
+pub async fn compress_layer(req: Request, next: Next) -> Response {
+    let (gzip, deflate) = (
+        accept_q(req.headers(), "gzip"),
+        accept_q(req.headers(), "deflate"),
+    );
+    let coding = if gzip > 0.0 && gzip >= deflate {
+        Some("gzip")
+    } else if deflate > 0.0 {
+        Some("deflate")
+    } else {
+        None
+    };
+    let is_head = req.method() == Method::HEAD;
+    let mut res = next.run(req).await;
+
+    let h = res.headers();
+    let status = res.status();
+    let no_transform = h.get(CACHE_CONTROL).is_some_and(|v| {
+        let v = v.to_str().unwrap_or_default();
+        v.split(',').any(|v| v.trim() == "no-transform")
+    });
+    let content_type = h.get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let skip = is_head
+        || status.is_informational()
+        || matches!(
+            status,
+            StatusCode::NO_CONTENT | StatusCode::PARTIAL_CONTENT | StatusCode::NOT_MODIFIED
+        )
+        || h.contains_key(CONTENT_ENCODING) // like dav entries with `ENTRY_GZIP`
+        || h.contains_key(ETAG) // the assets choose their own variants
+        || no_transform
+        || !content_type.is_some_and(compressible)
+        || res.body().size_hint().upper().is_some_and(|v| v < MIN_SIZE);
+    if skip {
+        return res;
+    }
+    let vary = HeaderValue::from_static("accept-encoding");
+    res.headers_mut().append(VARY, vary);
+    let Some(coding) = coding else {
+        return res;
+    };
+
+    let (mut parts, body) = res.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    let coding_value = HeaderValue::from_static(coding);
+    parts.headers.insert(CONTENT_ENCODING, coding_value);
+    let level = Compression::default();
+    let encoder = match coding {
+        "gzip" => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+        _ => Encoder::Deflate(ZlibEncoder::new(Vec::new(), level)),
+    };
+    let body = Compressed {
+        inner: body,
+        encoder: Some(encoder),
+        dirty: false,
+        trailers: None,
+    };
+    Response::from_parts(parts, Body::new(body))
+}
@@ -5,7 +5,10 @@ use hyper_util::rt::tokio::{TokioExecutor, TokioIo};
 use hyper_util::service::TowerToHyperService;
 use std::convert::Infallible;
 use std::future::poll_fn;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::Duration;
 use tokio::io;
 use tokio::io::AsyncWriteExt;
@@ -156,6 +159,38 @@ impl Client {
 
         panic!("unsupported scheme")
     }
+
+    /// Connect to `addr` and finish the TLS handshake with `host` as server name, then close.
+    pub async fn handshake(&self, host: &str, addr: &str) -> Result<(), ClientError> {
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let tcp_stream = tokio::net::TcpStream::connect(addr).await?;
+        let tls_connector = TlsConnector::from(self.0.clone());
+        tls_connector.connect(server_name, tcp_stream).await?;
+        Ok(())
+    }
+}
+
+/// Number of connections being served, including the ones in TLS handshake.
+pub static ACTIVE_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of failed TLS handshakes since process started.
+pub static TLS_HANDSHAKE_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// Decrease the `ACTIVE_CONNECTIONS` on drop, even if the connection task was cancelled by timeout.
+struct ConnGuard;
+
+impl ConnGuard {
+    fn new() -> Self {
+        ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 pub async fn serve<B, S>(
@@ -173,17 +208,17 @@ pub async fn serve<B, S>(
     S::Future: Send,
 {
     let tls_acceptor = TlsAcceptor::from(Arc::new(tls_config));
-    let service = TowerToHyperService::new(service);
     loop {
-        let (mut tcp_stream, _socket_addr) = match tcp_listener.accept().await {
+        let (mut tcp_stream, socket_addr) = match tcp_listener.accept().await {
             Ok(v) => v,
             _ => continue, // ignore error here?
         };
-        // dbg!(socket_addr);
         let tls_acceptor = tls_acceptor.clone();
-        let service = service.clone();
+        let service = TowerToHyperService::new(WithPeer(service.clone(), socket_addr));
+        let conn_guard = ConnGuard::new();
         // https://github.com/tokio-rs/axum/discussions/2115
         tokio::spawn(tokio::time::timeout(TIMEOUT, async move {
+            let _conn_guard = conn_guard;
             // redirect HTTP to HTTPS
             let mut flag = [0]; // expect 0x16, TLS handshake
             let mut buf = tokio::io::ReadBuf::new(&mut flag);
@@ -195,7 +230,10 @@ pub async fn serve<B, S>(
             }
             let tls_stream = match tls_acceptor.accept(tcp_stream).await {
                 Ok(v) => v,
-                Err(_e) => return,
+                Err(_e) => {
+                    TLS_HANDSHAKE_FAILURES.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
             };
             let io = TokioIo::new(tls_stream);
             hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
@@ -207,6 +245,28 @@ pub async fn serve<B, S>(
     }
 }
 
+/// Insert the peer's `SocketAddr` into request extensions, read it by `req.extensions().get::<SocketAddr>()`.
+#[derive(Clone)]
+struct WithPeer<S>(S, SocketAddr);
+
+impl<S, B> Service<Request<B>> for WithPeer<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        req.extensions_mut().insert(self.1);
+        self.0.call(req)
+    }
+}
+
 /// The default value from nginx https://nginx.org/en/docs/http/ngx_http_core_module.html#keepalive_timeout
 const TIMEOUT: Duration = Duration::from_secs(75);
 
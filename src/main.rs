@@ -1,11 +1,18 @@
+mod access;
+mod assets;
 mod auth;
+mod chacha20;
+mod compress;
 mod database;
 mod launcher;
+mod scheduler;
+mod sha256;
+mod template;
 mod ticker;
+mod tz;
 mod units;
 mod utils;
 use std::net::SocketAddr;
-use std::time::Duration;
 
 // #[global_allocator]
 // static ALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc; // or rpmalloc::RpMalloc
@@ -20,29 +27,25 @@ async fn run() {
 
     // db_upgrade(); // uncomment this if we need to upgrade database
 
+    let units = units::load().await;
+    let mut app = axum::Router::new();
+    let mut jobs = Vec::new();
+    for unit in units {
+        log!(info: "unit {} enabled", unit.name());
+        unit.migrate().await;
+        app = app.merge(unit.routes());
+        jobs.extend(unit.jobs());
+    }
+    app = app.merge(assets::service());
+
     let server = async {
-        let app = axum::Router::new()
-            .merge(units::admin::service())
-            .merge(units::chat::service())
-            .merge(units::copilotgpt::service())
-            .merge(units::dav::service())
-            .merge(units::info::service())
-            .merge(units::magazine::service())
-            .merge(units::meet::service())
-            // .merge(units::mirror::service())
-            // .merge(units::proxy::service())
-            .merge(units::qqbot::service())
+        let app = app
             .route(
                 "/robots.txt",
                 axum::routing::MethodRouter::new().get("User-agent: *\nDisallow: /\n"),
             )
-            .layer(axum::middleware::from_fn(
-                |req: axum::extract::Request, next: axum::middleware::Next| async {
-                    log!(trac: "request {:?} {} {} {:?}", req.version(), req.method(), req.uri(), req.headers());
-                    next.run(req).await
-                },
-            ))
-            ;
+            .layer(axum::middleware::from_fn(compress::compress_layer))
+            .layer(axum::middleware::from_fn(access::access_layer));
         log!(info: "auth key = {}", auth::auth_key());
         let addr = SocketAddr::from(([0, 0, 0, 0], 9304)); // server address here
         log!(info: "server address = {addr}");
@@ -85,27 +88,9 @@ async fn run() {
         // axum::serve(tcp_listener, app).await.unwrap();
     };
 
-    let oscillator = async {
-        const INTERVAL: Duration = Duration::from_secs(60);
-        const TIMEOUT: Duration = Duration::from_secs(45);
-        log!(info: "oscillator interval = {INTERVAL:?}, timeout = {TIMEOUT:?}");
-        async fn tasks() {
-            tokio::join!(
-                units::magazine::tick(),
-                units::qqbot::tick(),
-                units::v2exdaily::tick(),
-            );
-        }
-        let mut interval = tokio::time::interval(INTERVAL);
-        loop {
-            interval.tick().await;
-            care!(tokio::time::timeout(TIMEOUT, tasks()).await).ok();
-            // let stamp = httpdate::fmt_http_date(std::time::SystemTime::now());
-            // log!("oscillator loop bottom, at {stamp}");
-        }
-    };
+    let scheduler = scheduler::run(jobs);
 
-    tokio::join!(server, oscillator);
+    tokio::join!(server, scheduler);
 }
 
 /// Deal with database upgrade.
@@ -0,0 +1,313 @@
+//! Persistent job scheduler, with missed-run catch-up and run history.
+
+use crate::log;
+use crate::ticker::{next_after, Cron, TimeZone};
+use crate::units::metrics::timed;
+use crate::utils::with_retry;
+use anyhow::Result;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Keep the run history in database for 30 days.
+const HISTORY_TTL: u64 = 3600 * 24 * 30;
+
+/// Limit the catch-up runs of `CatchUp::All`, for jobs like every second.
+const MAX_CATCH_UP: u64 = 64;
+
+mod db {
+    use crate::database::DB;
+    use crate::strip_str;
+    pub async fn init() {
+        DB.call(|db| {
+            // scheduler_jobs: next (seconds) = the next scheduled run, jitter included
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS scheduler_jobs (name BLOB PRIMARY KEY, next INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // scheduler_runs: time (seconds), duration (milliseconds), ok = 0 | 1, msg = "" | error
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS scheduler_runs (name BLOB, time INTEGER, duration INTEGER, ok INTEGER, msg BLOB)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            let sql = strip_str! {"
+                CREATE INDEX IF NOT EXISTS scheduler_runs_name_time ON scheduler_runs (name, time)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+        })
+        .await
+    }
+    pub async fn get_next(name: &'static str) -> Option<u64> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT next FROM scheduler_jobs WHERE name = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((name.as_bytes(),), |r| r.get(0)).ok()
+        })
+        .await
+    }
+    pub async fn set_next(name: &'static str, next: u64) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                REPLACE INTO scheduler_jobs VALUES (?, ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((name.as_bytes(), next)).unwrap();
+        })
+        .await
+    }
+    pub async fn add_run(name: &'static str, time: u64, duration: u64, ok: bool, msg: String) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                INSERT INTO scheduler_runs VALUES (?, ?, ?, ?, ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let params = (name.as_bytes(), time, duration, ok, msg.as_bytes());
+            stmd.execute(params).unwrap();
+        })
+        .await
+    }
+    /// Returns `(time, duration, ok, msg)` of recent runs, the newest first.
+    pub async fn list_runs(name: &'static str, limit: u64) -> Vec<(u64, u64, bool, String)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT time, duration, ok, msg FROM scheduler_runs WHERE name = ? ORDER BY time DESC LIMIT ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_map((name.as_bytes(), limit), |r| {
+                let msg = String::from_utf8(r.get(3)?).unwrap();
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, msg))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+        })
+        .await
+    }
+    pub async fn clean_runs(before: u64) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM scheduler_runs WHERE time < ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((before,)).unwrap();
+        })
+        .await
+    }
+}
+
+/// What to do with runs missed during downtime.
+#[derive(Clone, Copy, Debug)]
+pub enum CatchUp {
+    Skip,
+    Once,
+    #[allow(unused)]
+    All,
+}
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+pub struct Job {
+    pub name: &'static str,
+    /// Use `crons!("XX:04:00")` to check patterns at compile time.
+    pub crons: &'static [Cron],
+    pub zone: &'static dyn TimeZone,
+    pub catch_up: CatchUp,
+    /// Delay each run by a random duration up to this, to avoid hitting remote servers on time.
+    pub jitter: Duration,
+    /// Limit each attempt.
+    pub timeout: Duration,
+    /// Extra attempts after a failed one, a panic or timeout also counts as failure.
+    pub retries: usize,
+    pub retry_interval: Duration,
+    pub run: fn() -> JobFuture,
+}
+
+struct Slot {
+    job: Arc<Job>,
+    next: u64,
+    /// Runs waiting to start, from catch-up, schedule or `run_now`.
+    pending: u64,
+    /// A run is in progress, the job never overlaps itself.
+    running: bool,
+}
+
+static SLOTS: Mutex<Vec<Slot>> = Mutex::new(Vec::new());
+
+fn get_now() -> u64 {
+    UNIX_EPOCH.elapsed().unwrap().as_secs()
+}
+
+fn schedule(job: &Job, now: u64) -> u64 {
+    let next = next_after(job.crons, job.zone, now);
+    let jitter = rand::thread_rng().gen_range(0..=job.jitter.as_secs());
+    next.saturating_add(jitter)
+}
+
+/// Count the runs scheduled in `[since, now]`.
+fn count_missed(job: &Job, since: u64, now: u64) -> u64 {
+    let (mut count, mut t) = (0, since);
+    while t <= now && count < MAX_CATCH_UP {
+        count += 1;
+        t = next_after(job.crons, job.zone, t);
+    }
+    count
+}
+
+/// Run in a separate task, so a panic is caught as an error, and the task is aborted on timeout.
+async fn attempt(job: &Job) -> Result<()> {
+    let mut handle = tokio::spawn(timed(job.name, (job.run)()));
+    match tokio::time::timeout(job.timeout, &mut handle).await {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) if e.is_panic() => {
+            let payload = e.into_panic();
+            let msg = match payload.downcast_ref::<&str>() {
+                Some(v) => v.to_string(),
+                None => payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            Err(anyhow::anyhow!("panicked: {msg}"))
+        }
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => {
+            handle.abort();
+            Err(anyhow::anyhow!("timeout after {:?}", job.timeout))
+        }
+    }
+}
+
+async fn run_once(job: &Job) {
+    let time = get_now();
+    let instant = Instant::now();
+    let interval_ms = job.retry_interval.as_millis() as u64;
+    let ret = with_retry(|| attempt(job), job.retries + 1, interval_ms).await;
+    let duration = instant.elapsed().as_millis() as u64;
+    let msg = match &ret {
+        Ok(_) => String::new(),
+        Err(e) => {
+            log!(erro: "scheduler job {} failed: {e:?}", job.name);
+            format!("{e:#}")
+        }
+    };
+    db::add_run(job.name, time, duration, ret.is_ok(), msg).await;
+}
+
+/// Request an extra run of the job as soon as possible, returns `false` if not found.
+pub fn run_now(name: &str) -> bool {
+    let mut slots = SLOTS.lock().unwrap();
+    let Some(slot) = slots.iter_mut().find(|v| v.job.name == name) else {
+        return false;
+    };
+    slot.pending += 1;
+    true
+}
+
+/// Jobs and their recent runs, for the table in admin console.
+pub async fn report() -> Value {
+    let jobs: Vec<_> = SLOTS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|v| (v.job.clone(), v.next, v.pending, v.running))
+        .collect();
+    let date = |v: u64| match v {
+        u64::MAX => "never".to_owned(),
+        v => httpdate::fmt_http_date(SystemTime::UNIX_EPOCH + Duration::from_secs(v)),
+    };
+    let mut o = Vec::new();
+    for (job, next, pending, running) in jobs {
+        let mut runs = Vec::new();
+        for (time, duration, ok, msg) in db::list_runs(job.name, 10).await {
+            runs.push(json!({ "time": date(time), "duration": duration, "ok": ok, "msg": msg }));
+        }
+        o.push(json!({
+            "name": job.name,
+            "next": date(next),
+            "running": running,
+            "pending": pending,
+            "catch_up": format!("{:?}", job.catch_up),
+            "timeout": format!("{:?}", job.timeout),
+            "retries": job.retries,
+            "runs": runs,
+        }));
+    }
+    Value::Array(o)
+}
+
+/// Each job has its own supervisor task, so a slow or hung job never delays the others.
+async fn supervise(i: usize) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let now = get_now();
+        let (job, times, rescheduled) = {
+            let mut slots = SLOTS.lock().unwrap();
+            let slot = &mut slots[i];
+            let mut rescheduled = None;
+            if now >= slot.next {
+                slot.pending += 1;
+                slot.next = schedule(&slot.job, now);
+                rescheduled = Some(slot.next);
+            }
+            let times = std::mem::take(&mut slot.pending);
+            slot.running = times != 0;
+            (slot.job.clone(), times, rescheduled)
+        };
+        if let Some(next) = rescheduled {
+            db::set_next(job.name, next).await;
+        }
+        for _ in 0..times {
+            run_once(&job).await;
+        }
+        SLOTS.lock().unwrap()[i].running = false;
+    }
+}
+
+/// Run the jobs forever.
+pub async fn run(jobs: Vec<Job>) {
+    db::init().await;
+    let now = get_now();
+    for job in jobs {
+        let stored = db::get_next(job.name).await;
+        let missed = match stored {
+            Some(v) if v <= now => count_missed(&job, v, now),
+            _ => 0,
+        };
+        let pending = match job.catch_up {
+            CatchUp::Skip => 0,
+            CatchUp::Once => missed.min(1),
+            CatchUp::All => missed,
+        };
+        if missed != 0 {
+            log!(info: "scheduler job {} missed {missed} runs, catch up {pending}", job.name);
+        }
+        let next = match stored {
+            Some(v) if v > now => v,
+            _ => schedule(&job, now),
+        };
+        db::set_next(job.name, next).await;
+        let job = Arc::new(job);
+        let mut slots = SLOTS.lock().unwrap();
+        slots.push(Slot {
+            job,
+            next,
+            pending,
+            running: false,
+        });
+        tokio::spawn(supervise(slots.len() - 1));
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        db::clean_runs(get_now() - HISTORY_TTL).await;
+    }
+}
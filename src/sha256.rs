@@ -0,0 +1,213 @@
+//! SHA-256. FIPS 180-4, https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.180-4.pdf
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    /// Bytes in `block`.
+    len: usize,
+    total: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            state: H,
+            block: [0; 64],
+            len: 0,
+            total: 0,
+        }
+    }
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let mut w = [0; 64];
+    for i in 0..16 {
+        w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+    }
+    for (v, x) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *v = v.wrapping_add(x);
+    }
+}
+
+impl Sha256 {
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        while !data.is_empty() {
+            let n = data.len().min(64 - self.len);
+            self.block[self.len..self.len + n].copy_from_slice(&data[..n]);
+            (self.len, data) = (self.len + n, &data[n..]);
+            if self.len == 64 {
+                compress(&mut self.state, &self.block);
+                self.len = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bits = self.total * 8;
+        self.update(&[0x80]);
+        while self.len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut o = [0; 32];
+        for (i, v) in self.state.iter().enumerate() {
+            o[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+        }
+        o
+    }
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h = Sha256::default();
+    h.update(data);
+    h.finish()
+}
+
+/// HMAC-SHA256 with the key absorbed, clone it to reuse the key. RFC 2104
+#[derive(Clone)]
+pub struct Hmac {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl Hmac {
+    pub fn new(key: &[u8]) -> Self {
+        let mut k = [0; 64];
+        match key.len() > 64 {
+            true => k[..32].copy_from_slice(&sha256(key)),
+            false => k[..key.len()].copy_from_slice(key),
+        }
+        let (mut inner, mut outer) = (Sha256::default(), Sha256::default());
+        inner.update(&k.map(|v| v ^ 0x36));
+        outer.update(&k.map(|v| v ^ 0x5c));
+        Self { inner, outer }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 with 32 bytes output. RFC 8018 section 5.2
+pub fn pbkdf2(password: &[u8], salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mac = Hmac::new(password);
+    let mut h = mac.clone();
+    h.update(salt);
+    h.update(&1u32.to_be_bytes());
+    let mut u = h.finish();
+    let mut o = u;
+    for _ in 1..rounds {
+        let mut h = mac.clone();
+        h.update(&u);
+        u = h.finish();
+        o.iter_mut().zip(u).for_each(|(o, u)| *o ^= u);
+    }
+    o
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(v: &[u8]) -> String {
+        v.iter().map(|v| format!("{v:02x}")).collect()
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut h = Hmac::new(key);
+        h.update(data);
+        h.finish()
+    }
+
+    #[test]
+    fn fips180_4() {
+        let v = sha256(b"");
+        let expected = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(hex(&v), expected);
+        let v = sha256(b"abc");
+        let expected = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(hex(&v), expected);
+        let v = sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+        let expected = "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1";
+        assert_eq!(hex(&v), expected);
+        // fed in uneven pieces, to cross the block boundaries
+        let (mut h, data) = (Sha256::default(), [b'a'; 1000]);
+        for _ in 0..1000 {
+            h.update(&data[..7]);
+            h.update(&data[7..]);
+        }
+        let expected = "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0";
+        assert_eq!(hex(&h.finish()), expected);
+    }
+
+    #[test]
+    fn rfc4231() {
+        // test case 1, 2 and 6
+        let v = hmac(&[0x0b; 20], b"Hi There");
+        let expected = "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7";
+        assert_eq!(hex(&v), expected);
+        let v = hmac(b"Jefe", b"what do ya want for nothing?");
+        let expected = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+        assert_eq!(hex(&v), expected);
+        let data = b"Test Using Larger Than Block-Size Key - Hash Key First";
+        let v = hmac(&[0xaa; 131], data);
+        let expected = "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54";
+        assert_eq!(hex(&v), expected);
+    }
+
+    #[test]
+    fn rfc7914() {
+        // section 11, only the first 32 bytes as the output is
+        let v = pbkdf2(b"passwd", b"salt", 1);
+        let expected = "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc";
+        assert_eq!(hex(&v), expected);
+        let v = pbkdf2(b"Password", b"NaCl", 80000);
+        let expected = "4ddcd8f60b98be21830cee5ef22701f9641a4418d04c0414aeff08876b34ab56";
+        assert_eq!(hex(&v), expected);
+    }
+}
@@ -0,0 +1,301 @@
+//! Page templates parsed at compile time, rendered with `serde_json::Value` as data.
+//!
+//! Marks in templates:
+//!
+//! - `/*{name}*/` the value, html escaped. `name` may be a path like `host.cpu`, or `.` for the current item.
+//! - `/*{!name}*/` the value as raw html, only for trusted content.
+//! - `/*{#name}*/ ... /*{/name}*/` repeat for each item of an array, or once if the value is truthy.
+//! - `/*{^name}*/ ... /*{/name}*/` once if the value is falsy, like `null`, `false`, `""` or `[]`.
+//! - `/*{@title}*/` and `/*{@content}*/` only in the layout `units/common.html`, filled at compile time.
+
+use crate::utils::str_const_ops_::bytes_eq;
+use serde_json::Value;
+
+#[derive(Clone, Copy)]
+pub enum Node {
+    Text(&'static str),
+    Value {
+        name: &'static str,
+        raw: bool,
+    },
+    /// The `len` nodes after this are the body.
+    Section {
+        name: &'static str,
+        len: usize,
+        inverted: bool,
+    },
+}
+
+pub struct Template(pub &'static [Node]);
+
+const OPEN: &[u8] = b"/*{";
+const CLOSE: &[u8] = b"}*/";
+const TITLE_MARK: &[u8] = b"/*{@title}*/";
+const CONTENT_MARK: &[u8] = b"/*{@content}*/";
+
+const fn starts_with(s: &[u8], i: usize, p: &[u8]) -> bool {
+    if i + p.len() > s.len() {
+        return false;
+    }
+    let mut j = 0;
+    while j < p.len() {
+        if s[i + j] != p[j] {
+            return false;
+        }
+        j += 1;
+    }
+    true
+}
+
+/// Index of the first `p` in `s[from..]`, or `s.len()` if not found.
+const fn find(s: &[u8], from: usize, p: &[u8]) -> usize {
+    let mut i = from;
+    while i < s.len() && !starts_with(s, i, p) {
+        i += 1;
+    }
+    if i < s.len() {
+        i
+    } else {
+        s.len()
+    }
+}
+
+const fn sub(s: &'static str, begin: usize, end: usize) -> &'static str {
+    // `&s[begin..end]` is unusable in const fn, and the edges are always ASCII marks
+    unsafe {
+        let v = std::slice::from_raw_parts(s.as_ptr().add(begin), end - begin);
+        std::str::from_utf8_unchecked(v)
+    }
+}
+
+const fn check_name(name: &[u8]) {
+    assert!(!name.is_empty(), "template mark name is empty");
+    let mut i = 0;
+    while i < name.len() {
+        let c = name[i];
+        assert!(
+            c.is_ascii_alphanumeric() || c == b'_' || c == b'.',
+            "template mark name has unexpected char"
+        );
+        i += 1;
+    }
+}
+
+/// Returns the nodes and the real count. Only counts if `N` is `0`.
+const fn parse_inner<const N: usize>(s: &'static str) -> ([Node; N], usize) {
+    let b = s.as_bytes();
+    let mut nodes = [Node::Text(""); N];
+    let mut n = 0;
+    let mut stack = [(0, "", false); 8]; // (index, name, inverted) of open sections
+    let mut depth = 0;
+    let mut i = 0;
+    loop {
+        let begin = find(b, i, OPEN);
+        if begin > i {
+            if N != 0 {
+                nodes[n] = Node::Text(sub(s, i, begin));
+            }
+            n += 1;
+        }
+        if begin == b.len() {
+            break;
+        }
+        let end = find(b, begin, CLOSE);
+        assert!(end < b.len(), "template mark is not closed");
+        assert!(end > begin + OPEN.len(), "template mark name is empty");
+        i = end + CLOSE.len();
+        let (kind, name) = (b[begin + OPEN.len()], sub(s, begin + OPEN.len() + 1, end));
+        let node = match kind {
+            b'#' | b'^' => {
+                check_name(name.as_bytes());
+                assert!(depth < stack.len(), "template sections are too deep");
+                let inverted = kind == b'^';
+                stack[depth] = (n, name, inverted);
+                depth += 1;
+                Node::Section {
+                    name,
+                    len: 0,
+                    inverted,
+                }
+            }
+            b'/' => {
+                assert!(depth > 0, "template section end without start");
+                depth -= 1;
+                let (open, open_name, inverted) = stack[depth];
+                assert!(
+                    bytes_eq(name.as_bytes(), open_name.as_bytes()),
+                    "template section end does not match start"
+                );
+                if N != 0 {
+                    nodes[open] = Node::Section {
+                        name,
+                        len: n - open - 1,
+                        inverted,
+                    };
+                }
+                continue;
+            }
+            b'!' => {
+                check_name(name.as_bytes());
+                Node::Value { name, raw: true }
+            }
+            b'@' => panic!("template layout mark is not filled"),
+            _ => {
+                let name = sub(s, begin + OPEN.len(), end);
+                check_name(name.as_bytes());
+                Node::Value { name, raw: false }
+            }
+        };
+        if N != 0 {
+            nodes[n] = node;
+        }
+        n += 1;
+    }
+    assert!(depth == 0, "template section is not closed");
+    (nodes, n)
+}
+
+pub const fn count(s: &'static str) -> usize {
+    parse_inner::<0>(s).1
+}
+
+pub const fn parse<const N: usize>(s: &'static str) -> [Node; N] {
+    parse_inner(s).0
+}
+
+pub const fn fill_len(layout: &str, title: &str, page: &str) -> usize {
+    layout.len() - TITLE_MARK.len() - CONTENT_MARK.len() + title.len() + page.len()
+}
+
+/// Put the title and page into the layout.
+pub const fn fill<const LEN: usize>(layout: &str, title: &str, page: &str) -> [u8; LEN] {
+    let (layout, title, page) = (layout.as_bytes(), title.as_bytes(), page.as_bytes());
+    let mut buf = [0; LEN];
+    let (mut i, mut j) = (0, 0);
+    while i < layout.len() {
+        let (mark, value) = if starts_with(layout, i, TITLE_MARK) {
+            (TITLE_MARK, title)
+        } else if starts_with(layout, i, CONTENT_MARK) {
+            (CONTENT_MARK, page)
+        } else {
+            (b"".as_slice(), b"".as_slice())
+        };
+        if mark.is_empty() {
+            buf[j] = layout[i];
+            (i, j) = (i + 1, j + 1);
+            continue;
+        }
+        let mut k = 0;
+        while k < value.len() {
+            buf[j + k] = value[k];
+            k += 1;
+        }
+        (i, j) = (i + mark.len(), j + value.len());
+    }
+    assert!(j == LEN, "template layout marks must appear once");
+    buf
+}
+
+/// Escape the text for html content and quoted attribute values.
+pub fn escape(o: &mut String, v: &str) {
+    // https://github.com/djc/askama/blob/0.12.0/askama_escape/src/lib.rs
+    for c in v.chars() {
+        match c {
+            '<' => *o += "&lt;",
+            '>' => *o += "&gt;",
+            '&' => *o += "&amp;",
+            '"' => *o += "&quot;",
+            '\'' => *o += "&#x27;",
+            c => o.push(c),
+        }
+    }
+}
+
+fn lookup<'a>(scopes: &[&'a Value], name: &str) -> &'a Value {
+    static NULL: Value = Value::Null;
+    if name == "." {
+        return scopes.last().unwrap();
+    }
+    let mut keys = name.split('.');
+    let first = keys.next().unwrap();
+    // search the inner scope first, like variables in loops
+    let Some(v) = scopes.iter().rev().find_map(|v| v.get(first)) else {
+        return &NULL;
+    };
+    keys.try_fold(v, |v, k| v.get(k)).unwrap_or(&NULL)
+}
+
+fn render_nodes(nodes: &[Node], o: &mut String, scopes: &mut Vec<&Value>) {
+    let mut i = 0;
+    while i < nodes.len() {
+        match nodes[i] {
+            Node::Text(v) => *o += v,
+            Node::Value { name, raw } => match lookup(scopes, name) {
+                Value::Null => {}
+                Value::String(v) if raw => *o += v,
+                Value::String(v) => escape(o, v),
+                v => escape(o, &v.to_string()),
+            },
+            Node::Section {
+                name,
+                len,
+                inverted,
+            } => {
+                let body = &nodes[i + 1..i + 1 + len];
+                let items = match lookup(scopes, name) {
+                    Value::Array(v) => v.as_slice(),
+                    Value::Null | Value::Bool(false) => &[],
+                    Value::String(v) if v.is_empty() => &[],
+                    v => std::slice::from_ref(v),
+                };
+                let items = match (inverted, items.is_empty()) {
+                    (false, _) => items,
+                    (true, true) => std::slice::from_ref(*scopes.last().unwrap()),
+                    (true, false) => &[],
+                };
+                for item in items {
+                    scopes.push(item);
+                    render_nodes(body, o, scopes);
+                    scopes.pop();
+                }
+                i += len;
+            }
+        }
+        i += 1;
+    }
+}
+
+impl Template {
+    pub fn render(&self, data: &Value) -> String {
+        let mut o = String::new();
+        render_nodes(self.0, &mut o, &mut vec![data]);
+        o
+    }
+}
+
+/// Usage: `template!("page.html")`, or `template!("page.html", title = "Info")` to wrap in the layout.
+///
+/// Returns `Template`, malformed marks fail the build.
+#[macro_export]
+macro_rules! template {
+    (@parse $s:expr) => {{
+        const N: usize = $crate::template::count($s);
+        static NODES: [$crate::template::Node; N] = $crate::template::parse($s);
+        $crate::template::Template(&NODES)
+    }};
+    ($path:expr) => {{
+        const S: &str = $crate::strip_str!(include_str!($path));
+        $crate::template!(@parse S)
+    }};
+    ($path:expr, title = $title:expr) => {{
+        use $crate::template::{fill, fill_len};
+        const LAYOUT: &str = $crate::strip_str!(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/units/common.html"
+        )));
+        const PAGE: &str = $crate::strip_str!(include_str!($path));
+        const BUF: [u8; fill_len(LAYOUT, $title, PAGE)] = fill(LAYOUT, $title, PAGE);
+        const S: &str = unsafe { std::str::from_utf8_unchecked(&BUF) };
+        $crate::template!(@parse S)
+    }};
+}
@@ -1,74 +1,188 @@
+use crate::tz::{civil_from_days, days_in_month};
+use crate::utils::str_const_ops_::bytes_eq;
 use std::sync::atomic::{AtomicU64, Ordering};
 
-fn gen_next(mut now: u64, cfg: (u64, u64, u64)) -> u64 {
-    /// Convert time stamp to (hours, minutes, seconds).
-    fn hms(v: u64) -> (u64, u64, u64) {
-        (v / 60 / 60 % 24, v / 60 % 60, v % 60)
+/// Time zone of patterns, implemented for hour offsets like `8` and IANA names like `"Asia/Shanghai"`.
+pub trait TimeZone: Sync {
+    /// Offset to UTC in seconds at the instant.
+    fn offset(&self, utc: u64) -> i64;
+    /// The next instant after `utc` that the offset may change.
+    fn next_transition(&self, utc: u64) -> Option<u64>;
+}
+
+impl TimeZone for i32 {
+    fn offset(&self, _: u64) -> i64 {
+        *self as i64 * 3600
     }
-    fn floor_by(v: u64, r: u64) -> u64 {
-        v - v % r
+    fn next_transition(&self, _: u64) -> Option<u64> {
+        None
     }
-    now += 1;
-    let (ch, cm, cs) = cfg;
-    loop {
-        let (h, m, s) = hms(now);
-        now = match (
-            h == ch || ch == u64::MAX,
-            m == cm || cm == u64::MAX,
-            s == cs || cs == u64::MAX,
-        ) {
-            // legal
-            (true, true, true) => {
-                return now;
-            }
+}
 
-            // generate
-            (true, true, _) if s < cs && s < 59 => {
-                floor_by(now, 60) + if cs == u64::MAX { s + 1 } else { cs }
-            }
-            (true, _, _) if m < cm && m < 59 => {
-                floor_by(now, 60 * 60) + 60 * if cm == u64::MAX { m + 1 } else { cm }
-            }
-            (_, _, _) if h < ch && h < 23 => {
-                floor_by(now, 60 * 60 * 24) + 60 * 60 * if ch == u64::MAX { h + 1 } else { ch }
+/// Fallback to UTC if the zone is not found.
+impl TimeZone for &'static str {
+    fn offset(&self, utc: u64) -> i64 {
+        crate::tz::get(self).map_or(0, |v| v.offset(utc as i64))
+    }
+    fn next_transition(&self, utc: u64) -> Option<u64> {
+        let v = crate::tz::get(self)?.next_transition(utc as i64)?;
+        Some(v as u64)
+    }
+}
+
+/// A parsed pattern, each field is a bit set like `1 << 5` means the value `5` is allowed.
+#[derive(Clone, Copy)]
+pub struct Cron {
+    secs: u64,
+    mins: u64,
+    hours: u64,
+    doms: u64,
+    months: u64,
+    dows: u64,
+    /// Both day-of-month and day-of-week are restricted, match if either matches, like the standard cron.
+    day_or: bool,
+}
+
+impl Cron {
+    fn day_matches(&self, days: u64) -> bool {
+        let (_, m, d) = civil_from_days(days as i64);
+        let dow = (days + 4) % 7; // 1970-01-01 is Thursday
+        let (dom_ok, dow_ok) = (self.doms >> d & 1 == 1, self.dows >> dow & 1 == 1);
+        self.months >> m & 1 == 1
+            && match self.day_or {
+                true => dom_ok || dow_ok,
+                false => dom_ok && dow_ok,
             }
+    }
+}
+
+/// The smallest value in the bit set that is `>= from`.
+fn next_bit(set: u64, from: u64) -> Option<u64> {
+    let v = set & (u64::MAX << from);
+    (v != 0).then(|| v.trailing_zeros() as u64)
+}
+
+/// A fixed offset in seconds, the wall clock before a transition.
+struct Fixed(i64);
 
-            // next day
-            _ => floor_by(now, 60 * 60 * 24) + 60 * 60 * 24,
+impl TimeZone for Fixed {
+    fn offset(&self, _: u64) -> i64 {
+        self.0
+    }
+    fn next_transition(&self, _: u64) -> Option<u64> {
+        None
+    }
+}
+
+/// If the wall-clock time at `utc` was seen before the clock went back, returns the end of the repeated period.
+fn repeated_until(utc: u64, zone: &dyn TimeZone) -> Option<u64> {
+    // the clock goes back at most 3 hours, in all zones till now
+    let before = zone.offset(utc.saturating_sub(3 * 3600));
+    let back = before - zone.offset(utc);
+    let seen = (utc as i64 - back) as u64;
+    if back <= 0 || zone.offset(seen) != before {
+        return None;
+    }
+    Some(zone.next_transition(seen)? + back as u64)
+}
+
+/// The first instant in `(from, limit)` that matches, in the wall clock of the zone.
+///
+/// Like the standard cron, the repeated wall-clock times run only once when the clock goes back,
+/// and the skipped ones run at the transition when the clock goes forward.
+fn search(from: u64, limit: u64, cron: &Cron, zone: &dyn TimeZone) -> Option<u64> {
+    let mut now = from + 1;
+    while now < limit {
+        let offset = zone.offset(now);
+        let local = (now as i64 + offset) as u64;
+        let (days, sod) = (local / 86400, local % 86400);
+        let (h, m, s) = (sod / 3600, sod / 60 % 60, sod % 60);
+        let to_next_day = 86400 - sod;
+        let to_next_hour = 3600 - sod % 3600;
+        let jump = if !cron.day_matches(days) {
+            to_next_day
+        } else if cron.hours >> h & 1 == 0 {
+            next_bit(cron.hours, h).map_or(to_next_day, |v| to_next_hour + (v - h - 1) * 3600)
+        } else if cron.mins >> m & 1 == 0 {
+            next_bit(cron.mins, m).map_or(to_next_hour, |v| (v - m) * 60 - s)
+        } else {
+            match next_bit(cron.secs, s) {
+                Some(v) if v == s => match repeated_until(now, zone) {
+                    Some(end) => end - now,
+                    None => return Some(now),
+                },
+                Some(v) => v - s,
+                None => 60 - s,
+            }
         };
+        // the local time jumps when the offset changes, so stop at there and recalculate
+        let cap = zone.next_transition(now).unwrap_or(u64::MAX);
+        if now + jump >= cap {
+            let gap = zone.offset(cap) - offset;
+            if gap > 0 && search(cap - 1, cap + gap as u64, cron, &Fixed(offset)).is_some() {
+                return Some(cap);
+            }
+        }
+        now = (now + jump).min(cap);
     }
+    None
+}
+
+fn gen_next(now: u64, cron: &Cron, zone: &dyn TimeZone) -> u64 {
+    // the 29th of february on a specified weekday may be 28 years later
+    let limit = now + 3600 * 24 * 366 * 28;
+    search(now, limit, cron, zone).unwrap_or(u64::MAX) // never
 }
 
-/// A `cron` like timed task util.
+/// The nearest instant after `now` that matches any of the patterns, `u64::MAX` if never.
+pub fn next_after(crons: &[Cron], zone: &dyn TimeZone, now: u64) -> u64 {
+    let nexts = crons.iter().map(|cron| gen_next(now, cron, zone));
+    nexts.min().unwrap_or(u64::MAX)
+}
+
+/// Usage: `crons!("XX:04:00", "0 8 * * MON")`, returns `&'static [Cron]` checked at compile time.
+#[macro_export]
+macro_rules! crons {
+    ($($pattern:expr),*) => {{
+        const N: usize = [$($pattern),*].len();
+        static CRONS: [$crate::ticker::Cron; N] = $crate::ticker::parse_patterns([$($pattern),*]);
+        &CRONS
+    }};
+}
+
+/// A `cron` like timed task util, in memory only. Use the scheduler for the jobs need persistence or catch-up.
+#[allow(unused)]
 pub struct Ticker {
     pub next: AtomicU64,
-    pub cfgs: &'static [(u64, u64, u64)],
+    pub crons: &'static [Cron],
+    pub zone: &'static dyn TimeZone,
 }
 
+#[allow(unused)]
 impl Ticker {
     /// Returns `true` if the next instant has been reached.
     pub fn tick(&self) -> bool {
-        let now = get_now();
+        let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
         let next = self.next.load(Ordering::SeqCst);
         let ret = now >= next && next != 0;
         if now >= next {
-            let nexts = self.cfgs.iter().map(|&cfg| gen_next(now, cfg));
-            self.next.store(nexts.min().unwrap(), Ordering::SeqCst);
+            self.next
+                .store(next_after(self.crons, self.zone, now), Ordering::SeqCst);
         }
         ret
     }
 }
 
+/// Usage: `ticker!(return, 8, "XX:04:00")` or `ticker!(return, "Asia/Shanghai", "0 8 * * MON-FRI")`.
+///
+/// A thin wrapper over `crons!`, so the patterns are checked at compile time too.
 #[macro_export]
 macro_rules! ticker {
     ($if_not_tick:tt, $zone:literal, $($pattern:expr),*) => {{
-        use $crate::ticker::*;
-        use std::sync::atomic::AtomicU64;
-        const N: usize = [$($pattern),*].len();
-        static CFGS: [(u64, u64, u64); N] = parse_patterns($zone, [$($pattern),*]);
-        static TICKER: Ticker = Ticker {
-            next: AtomicU64::new(0),
-            cfgs: &CFGS,
+        static TICKER: $crate::ticker::Ticker = $crate::ticker::Ticker {
+            next: std::sync::atomic::AtomicU64::new(0),
+            crons: $crate::crons!($($pattern),*),
+            zone: &$zone,
         };
         if !TICKER.tick() {
             $if_not_tick;
@@ -76,50 +190,381 @@ macro_rules! ticker {
     }};
 }
 
-pub const fn parse_patterns<const N: usize>(
-    zone: i64,
-    patterns: [&'static str; N],
-) -> [(u64, u64, u64); N] {
-    let mut cfgs: [(u64, u64, u64); N] = [(0, 0, 0); N];
+const MONTH_NAMES: [&[u8; 3]; 12] = [
+    b"JAN", b"FEB", b"MAR", b"APR", b"MAY", b"JUN", b"JUL", b"AUG", b"SEP", b"OCT", b"NOV", b"DEC",
+];
+
+const DOW_NAMES: [&[u8; 3]; 7] = [b"SUN", b"MON", b"TUE", b"WED", b"THU", b"FRI", b"SAT"];
+
+/// Parse a number or a name in `p[i..end]`, returns `(value, next index)`.
+const fn parse_value(
+    p: &[u8],
+    i: usize,
+    end: usize,
+    names: &[&[u8; 3]],
+    base: u64,
+) -> (u64, usize) {
+    let mut j = i;
+    let mut v = 0;
+    while j < end && p[j].is_ascii_digit() {
+        v = v * 10 + (p[j] - b'0') as u64;
+        j += 1;
+        assert!(j - i <= 2, "cron value is too large");
+    }
+    if j > i {
+        return (v, j);
+    }
+    assert!(i + 3 <= end, "cron value is not a number or name");
+    let mut k = 0;
+    while k < names.len() {
+        let [a, b, c] = [p[i], p[i + 1], p[i + 2]];
+        let v = [
+            a.to_ascii_uppercase(),
+            b.to_ascii_uppercase(),
+            c.to_ascii_uppercase(),
+        ];
+        if bytes_eq(&v, names[k]) {
+            return (base + k as u64, i + 3);
+        }
+        k += 1;
+    }
+    panic!("cron name is unknown");
+}
+
+/// The `?` means no specific value in day fields, the same as `*` here.
+const fn is_any(c: u8) -> bool {
+    c == b'*' || c == b'?'
+}
+
+/// Parse a field like `*`, `5`, `1-5`, `*/15`, `10-50/20` or lists like `1,3,MON-WED`.
+const fn parse_field(
+    p: &[u8],
+    start: usize,
+    end: usize,
+    range: (u64, u64),
+    names: &[&[u8; 3]],
+) -> u64 {
+    assert!(start < end, "cron field is empty");
+    let (min, max) = range;
+    let mut set = 0;
+    let mut i = start;
+    loop {
+        let (mut lo, mut hi, mut j);
+        let star = is_any(p[i]);
+        if star {
+            (lo, hi, j) = (min, max, i + 1);
+        } else {
+            (lo, j) = parse_value(p, i, end, names, min);
+            hi = lo;
+            if j < end && p[j] == b'-' {
+                (hi, j) = parse_value(p, j + 1, end, names, min);
+            }
+        }
+        let mut step = 1;
+        if j < end && p[j] == b'/' {
+            (step, j) = parse_value(p, j + 1, end, &[], 0);
+            assert!(step > 0, "cron step is zero");
+            if !star && lo == hi {
+                hi = max; // `N/step` means from `N` to the max
+            }
+        }
+        assert!(
+            min <= lo && lo <= hi && hi <= max,
+            "cron value is out of range"
+        );
+        while lo <= hi {
+            set |= 1 << lo;
+            lo += step;
+        }
+        if j == end {
+            return set;
+        }
+        assert!(p[j] == b',', "cron field has unexpected char");
+        i = j + 1;
+    }
+}
+
+/// Parse the legacy `HH:MM:SS` pattern, `XX` means any.
+const fn parse_legacy(p: &[u8]) -> Cron {
+    assert!(
+        p.len() == 8 && p[2] == b':' && p[5] == b':',
+        "pattern is not HH:MM:SS"
+    );
+    let mut fields = [0; 3];
+    let maxs = [23, 59, 59];
     let mut i = 0;
-    while i < N {
-        let p = patterns[i].as_bytes();
-        macro_rules! part_code {
-            ($pidx:expr, $vidx:tt) => {
-                if p[$pidx] == b'X' {
-                    assert!(p[$pidx + 1] == b'X');
-                    cfgs[i].$vidx = u64::MAX;
-                } else {
-                    assert!(matches!(p[$pidx], b'0'..=b'9'));
-                    assert!(matches!(p[$pidx + 1], b'0'..=b'9'));
-                    cfgs[i].$vidx = (p[$pidx] - b'0') as u64 * 10 + (p[$pidx + 1] - b'0') as u64;
-                }
-            };
-        }
-        part_code!(0, 0);
-        part_code!(3, 1);
-        part_code!(6, 2);
-        if cfgs[i].0 != u64::MAX {
-            cfgs[i].0 = ((cfgs[i].0 as i64 + 24 - zone) % 24) as u64;
+    while i < 3 {
+        let (a, b) = (p[i * 3], p[i * 3 + 1]);
+        if a == b'X' {
+            assert!(b == b'X');
+            fields[i] = u64::MAX >> (63 - maxs[i]);
+        } else {
+            assert!(a.is_ascii_digit() && b.is_ascii_digit());
+            let v = (a - b'0') as u64 * 10 + (b - b'0') as u64;
+            assert!(v <= maxs[i], "pattern value is out of range");
+            fields[i] = 1 << v;
         }
         i += 1;
     }
-    cfgs
+    Cron {
+        secs: fields[2],
+        mins: fields[1],
+        hours: fields[0],
+        doms: ((1 << 32) - 1) & !1,
+        months: (1 << 13) - 2,
+        dows: (1 << 7) - 1,
+        day_or: false,
+    }
 }
 
-fn get_now() -> u64 {
-    std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as _
+/// Parse the cron pattern of 5 fields `min hour dom month dow`, or 6 fields with leading `sec`.
+const fn parse_cron(p: &[u8]) -> Cron {
+    const ALIASES: [(&[u8], &[u8]); 7] = [
+        (b"@yearly", b"0 0 1 1 *"),
+        (b"@annually", b"0 0 1 1 *"),
+        (b"@monthly", b"0 0 1 * *"),
+        (b"@weekly", b"0 0 * * 0"),
+        (b"@daily", b"0 0 * * *"),
+        (b"@midnight", b"0 0 * * *"),
+        (b"@hourly", b"0 * * * *"),
+    ];
+    let mut p = p;
+    let mut i = 0;
+    while i < ALIASES.len() {
+        if bytes_eq(p, ALIASES[i].0) {
+            p = ALIASES[i].1;
+        }
+        i += 1;
+    }
+    let mut bounds = [(0, 0); 6];
+    let (mut n, mut i) = (0, 0);
+    while i < p.len() {
+        if p[i] == b' ' {
+            i += 1;
+            continue;
+        }
+        assert!(n < 6, "cron pattern has more than 6 fields");
+        let start = i;
+        while i < p.len() && p[i] != b' ' {
+            i += 1;
+        }
+        bounds[n] = (start, i);
+        n += 1;
+    }
+    assert!(n == 5 || n == 6, "cron pattern must have 5 or 6 fields");
+    let secs = match n {
+        6 => parse_field(p, bounds[0].0, bounds[0].1, (0, 59), &[]),
+        _ => 1,
+    };
+    let [min, hour, dom, month, dow] = [
+        bounds[n - 5],
+        bounds[n - 4],
+        bounds[n - 3],
+        bounds[n - 2],
+        bounds[n - 1],
+    ];
+    let dows = parse_field(p, dow.0, dow.1, (0, 7), &DOW_NAMES);
+    Cron {
+        secs,
+        mins: parse_field(p, min.0, min.1, (0, 59), &[]),
+        hours: parse_field(p, hour.0, hour.1, (0, 23), &[]),
+        doms: parse_field(p, dom.0, dom.1, (1, 31), &[]),
+        months: parse_field(p, month.0, month.1, (1, 12), &MONTH_NAMES),
+        dows: (dows | dows >> 7) & 0x7f, // both 0 and 7 are sunday
+        day_or: !is_any(p[dom.0]) && !is_any(p[dow.0]),
+    }
 }
 
-// fn get_now() -> u64 {
-//     static CUR: AtomicU64 = AtomicU64::new(3000000000000);
-//     CUR.fetch_add(1, Ordering::SeqCst)
-// }
+/// Panics if no date can match, like `0 0 30 2 *`.
+const fn assert_possible(cron: &Cron) {
+    if cron.day_or {
+        return;
+    }
+    let mut m = 1;
+    while m <= 12 {
+        let max_dom = days_in_month(2000, m); // leap year
+        if cron.months >> m & 1 == 1 && cron.doms & (u64::MAX >> (63 - max_dom)) != 0 {
+            return;
+        }
+        m += 1;
+    }
+    panic!("cron pattern never matches");
+}
 
-// pub fn fuzzle_test() {
-// ticker!()
-// }
+/// Patterns are `HH:MM:SS` with `XX` as any, or standard cron syntax with optional seconds field.
+pub const fn parse_patterns<const N: usize>(patterns: [&'static str; N]) -> [Cron; N] {
+    const EMPTY: Cron = Cron {
+        secs: 0,
+        mins: 0,
+        hours: 0,
+        doms: 0,
+        months: 0,
+        dows: 0,
+        day_or: false,
+    };
+    let mut crons = [EMPTY; N];
+    let mut i = 0;
+    while i < N {
+        let p = patterns[i].as_bytes();
+        crons[i] = match p.len() == 8 && p[2] == b':' {
+            true => parse_legacy(p),
+            false => parse_cron(p),
+        };
+        assert_possible(&crons[i]);
+        i += 1;
+    }
+    crons
+}
 
-// bugtick: Tue, 28 Feb 2023 03:27:50 GMT
-// bugtick: Thu, 09 Mar 2023 16:04:29 GMT
-// bugtick: Tue, 05 Sep 2023 15:05:25 GMT
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tz::Zone;
+
+    /// 2026-10-19 00:00 UTC, Monday.
+    const MON: u64 = 1792368000;
+
+    struct Posix(Zone);
+
+    impl TimeZone for Posix {
+        fn offset(&self, utc: u64) -> i64 {
+            self.0.offset(utc as i64)
+        }
+        fn next_transition(&self, utc: u64) -> Option<u64> {
+            self.0.next_transition(utc as i64).map(|v| v as u64)
+        }
+    }
+
+    fn next(pattern: &'static str, zone: &dyn TimeZone, now: u64) -> u64 {
+        next_after(&parse_patterns([pattern]), zone, now)
+    }
+
+    /// The successive instants from `now`.
+    fn nexts<const N: usize>(pattern: &'static str, zone: &dyn TimeZone, now: u64) -> [u64; N] {
+        let mut now = now;
+        [0; N].map(|_| {
+            now = next(pattern, zone, now);
+            now
+        })
+    }
+
+    #[test]
+    fn fields() {
+        let utc = &0;
+        assert_eq!(next("*/15 9-17 * * MON-FRI", utc, 1792195200), 1792400400);
+        assert_eq!(
+            next("*/15 9-17 * * MON-FRI", utc, 1792400400),
+            1792400400 + 900
+        );
+        assert_eq!(
+            next("0 17 * * MON-FRI", utc, 1792400400),
+            1792400400 + 8 * 3600
+        );
+        assert_eq!(next("30 0 8 * * *", utc, MON), 1792396830);
+        assert_eq!(next("0 0 1 feb-mar,JUL *", utc, MON), 1801440000);
+        assert_eq!(next("0 0 1 */6 *", utc, MON), 1798761600);
+        assert_eq!(
+            next("0 0 1 */6 *", utc, 1798761600),
+            1798761600 + 181 * 86400
+        ); // 2027-07-01
+        assert_eq!(next("5/20 * * * *", utc, MON), MON + 5 * 60);
+        assert_eq!(
+            next("5/20 * * * *", utc, MON + 45 * 60),
+            MON + 3600 + 5 * 60
+        );
+        assert_eq!(next("0 0 * * 7", utc, MON), 1792886400); // both 0 and 7 are sunday
+        assert_eq!(next("0 8 * * *", &8, MON - 1), MON);
+        assert_eq!(next("0 8 * * *", &8, MON), MON + 86400);
+    }
+
+    #[test]
+    fn aliases() {
+        let utc = &0;
+        assert_eq!(next("@yearly", utc, MON), 1798761600);
+        assert_eq!(next("@annually", utc, MON), 1798761600);
+        assert_eq!(next("@monthly", utc, MON), 1793491200);
+        assert_eq!(next("@weekly", utc, MON), 1792886400);
+        assert_eq!(next("@daily", utc, MON), MON + 86400);
+        assert_eq!(next("@midnight", utc, MON), MON + 86400);
+        assert_eq!(next("@hourly", utc, MON), MON + 3600);
+    }
+
+    #[test]
+    fn day_or() {
+        let utc = &0;
+        // either the 13th or friday
+        assert_eq!(next("0 0 13 * FRI", utc, MON), 1792713600);
+        assert_eq!(next("0 0 13 * 5", utc, 1792713600), 1792713600 + 7 * 86400);
+        // the `?` and `*` leave the other field alone
+        assert_eq!(next("0 0 13 * ?", utc, MON), 1794528000);
+        assert_eq!(next("0 0 13 * *", utc, MON), 1794528000);
+        assert_eq!(next("0 0 ? * FRI", utc, MON), 1792713600);
+        assert_eq!(next("0 0 * * FRI", utc, MON), 1792713600);
+        assert_eq!(next("0 0 29 2 ?", utc, MON), 1835395200); // 2028-02-29
+    }
+
+    #[test]
+    fn legacy() {
+        let utc = &0;
+        assert_eq!(next("XX:04:00", utc, MON), 1792368240);
+        assert_eq!(next("XX:04:00", utc, 1792368240), 1792368240 + 3600);
+        assert_eq!(next("08:00:00", utc, MON), 1792396800);
+        assert_eq!(next("XX:XX:30", utc, MON), 1792368030);
+        assert_eq!(next("08:00:00", &8, MON), MON + 86400);
+        let crons = parse_patterns(["08:00:00", "XX:04:00"]);
+        assert_eq!(next_after(&crons, utc, MON), 1792368240);
+    }
+
+    #[test]
+    fn invalid() {
+        for pattern in [
+            "",
+            "* * * *",
+            "* * * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "100 * * * *",
+            "* * * FOO *",
+            "* * * * MON;",
+            "0 0 30 2 *",
+            "24:00:00",
+            "XX:60:00",
+            "X1:00:00",
+        ] {
+            let parse = std::panic::catch_unwind(|| parse_patterns([pattern]));
+            assert!(parse.is_err(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn dst_north() {
+        let ny = &Posix(Zone::from_posix("EST5EDT,M3.2.0,M11.1.0").unwrap());
+        // 01:30 repeats on 2026-11-01, runs once at the first
+        let v = nexts("30 1 * * *", ny, 1793505600);
+        assert_eq!(v, [1793511000, 1793601000]);
+        // the repeated hour is skipped entirely
+        assert_eq!(next("*/15 * * * *", ny, 1793511900), 1793516400);
+        // 02:30 is skipped on 2026-03-08, runs at the transition instead
+        let v = nexts("30 2 * * *", ny, 1772946000);
+        assert_eq!(v, [1772953200, 1773037800]);
+        // 03:00 is right after the gap, runs only once
+        let v = nexts("0 3 * * *", ny, 1772946000);
+        assert_eq!(v, [1772953200, 1773039600]);
+    }
+
+    #[test]
+    fn dst_south() {
+        let sydney = &Posix(Zone::from_posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap());
+        // the clock goes back at 03:00 on 2026-04-05
+        let v = nexts("30 2 * * *", sydney, 1775311200);
+        assert_eq!(v, [1775316600, 1775406600]);
+        // the clock goes forward at 02:00 on 2026-10-04
+        let v = nexts("30 2 * * *", sydney, 1791036000);
+        assert_eq!(v, [1791043200, 1791127800]);
+    }
+}
@@ -0,0 +1,439 @@
+//! Time zones from the system's `zoneinfo` database, in TZif format (RFC 8536).
+
+use crate::log;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Days since 1970-01-01 of the civil date. https://howardhinnant.github.io/date_algorithms.html
+pub const fn days_from_civil(y: i64, m: u64, d: u64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy as i64;
+    era * 146097 + doe - 719468
+}
+
+/// Returns `(year, month, day)` of days since 1970-01-01.
+pub const fn civil_from_days(z: i64) -> (i64, u64, u64) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+    (yoe + era * 400 + (m <= 2) as i64, m, d)
+}
+
+pub const fn days_in_month(y: i64, m: u64) -> u64 {
+    match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The `Mm.w.d/time` date of POSIX TZ string, like `M3.5.0/2` means 02:00 of the last Sunday in March.
+#[derive(Clone, Copy)]
+struct RuleDate {
+    month: u64,
+    /// 1 to 5, the 5 means the last one.
+    week: u64,
+    weekday: u64,
+    /// Seconds since the local midnight, may be negative or more than 24 hours.
+    time: i64,
+}
+
+impl RuleDate {
+    /// The transition instant in year `y`, while the local offset before it is `offset`.
+    fn instant(&self, y: i64, offset: i64) -> i64 {
+        let first = days_from_civil(y, self.month, 1);
+        let first_weekday = (first + 4).rem_euclid(7) as u64; // 1970-01-01 is Thursday
+        let mut day = 1 + (self.weekday + 7 - first_weekday) % 7 + (self.week - 1) * 7;
+        while day > days_in_month(y, self.month) {
+            day -= 7;
+        }
+        (first + day as i64 - 1) * 86400 + self.time - offset
+    }
+}
+
+/// Rule for instants after the last transition, from the footer of TZif v2+.
+#[derive(Clone, Copy)]
+struct Rule {
+    std_offset: i64,
+    /// `(offset, start, end)` of the daylight saving time.
+    dst: Option<(i64, RuleDate, RuleDate)>,
+}
+
+impl Rule {
+    /// Returns `(start, end)` instants of the daylight saving time in year `y`.
+    fn dst_range(&self, y: i64) -> Option<(i64, i64)> {
+        let (dst_offset, start, end) = self.dst?;
+        Some((
+            start.instant(y, self.std_offset),
+            end.instant(y, dst_offset),
+        ))
+    }
+
+    fn offset(&self, utc: i64) -> i64 {
+        let Some((dst_offset, ..)) = self.dst else {
+            return self.std_offset;
+        };
+        let y = civil_from_days((utc + self.std_offset).div_euclid(86400)).0;
+        let (start, end) = self.dst_range(y).unwrap();
+        let in_dst = match start < end {
+            true => start <= utc && utc < end,
+            false => !(end <= utc && utc < start), // southern hemisphere
+        };
+        if in_dst {
+            dst_offset
+        } else {
+            self.std_offset
+        }
+    }
+
+    fn next_transition(&self, utc: i64) -> Option<i64> {
+        let y = civil_from_days(utc.div_euclid(86400)).0;
+        let (a, b) = self.dst_range(y)?;
+        let (c, d) = self.dst_range(y + 1)?;
+        [a, b, c, d].into_iter().filter(|&v| v > utc).min()
+    }
+}
+
+pub struct Zone {
+    /// Transition instants, ascending.
+    times: Vec<i64>,
+    /// The offset in seconds since each transition.
+    offsets: Vec<i64>,
+    /// The offset before the first transition.
+    initial: i64,
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    /// Offset to UTC in seconds at the instant.
+    pub fn offset(&self, utc: i64) -> i64 {
+        // the rule goes first, the zones without transitions have only the rule
+        match self.times.partition_point(|&v| v <= utc) {
+            i if i == self.times.len() && self.rule.is_some() => self.rule.unwrap().offset(utc),
+            0 => self.initial,
+            i => self.offsets[i - 1],
+        }
+    }
+
+    /// The next instant after `utc` that the offset may change.
+    pub fn next_transition(&self, utc: i64) -> Option<i64> {
+        match self.times.get(self.times.partition_point(|&v| v <= utc)) {
+            Some(&v) => Some(v),
+            None => self.rule?.next_transition(utc),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Zone {
+    /// The zone of only the POSIX TZ string, for the tests without `zoneinfo`.
+    pub fn from_posix(s: &str) -> Option<Zone> {
+        let rule = parse_rule(s.as_bytes())?;
+        Some(Zone {
+            times: Vec::new(),
+            offsets: Vec::new(),
+            initial: rule.std_offset,
+            rule: Some(rule),
+        })
+    }
+}
+
+/// Parse `[+-]hh[:mm[:ss]]`, returns seconds.
+fn parse_hms(s: &mut &[u8]) -> Option<i64> {
+    let sign = match s.first()? {
+        b'-' => -1,
+        b'+' => 1,
+        _ => 0,
+    };
+    if sign != 0 {
+        *s = &s[1..];
+    }
+    let mut ret = 0;
+    for unit in [3600, 60, 1] {
+        let len = s.iter().take_while(|v| v.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        let v = std::str::from_utf8(&s[..len]).ok()?.parse::<i64>().ok()?;
+        ret = v.checked_mul(unit)?.checked_add(ret)?;
+        *s = &s[len..];
+        match s.first() {
+            Some(b':') if unit != 1 => *s = &s[1..],
+            _ => break,
+        }
+    }
+    Some(if sign == -1 { -ret } else { ret })
+}
+
+/// Parse the zone name like `CST` or `<+08>`.
+fn parse_name(s: &mut &[u8]) -> Option<()> {
+    let len = match s.first()? {
+        b'<' => s.iter().position(|&v| v == b'>')? + 1,
+        _ => s.iter().take_while(|v| v.is_ascii_alphabetic()).count(),
+    };
+    if len < 3 {
+        return None;
+    }
+    *s = &s[len..];
+    Some(())
+}
+
+fn parse_rule_date(s: &mut &[u8]) -> Option<RuleDate> {
+    // the Jn and n formats are rarely used in zoneinfo, not supported
+    let mut fields = [0; 3];
+    *s = s.strip_prefix(b"M")?;
+    for (i, field) in fields.iter_mut().enumerate() {
+        let len = s.iter().take_while(|v| v.is_ascii_digit()).count();
+        *field = std::str::from_utf8(&s[..len]).ok()?.parse().ok()?;
+        *s = &s[len..];
+        if i < 2 {
+            *s = s.strip_prefix(b".")?;
+        }
+    }
+    let time = match s.strip_prefix(b"/") {
+        Some(v) => {
+            *s = v;
+            parse_hms(s)?
+        }
+        None => 7200,
+    };
+    let [month, week, weekday] = fields;
+    let valid = (1..=12).contains(&month) && (1..=5).contains(&week) && weekday < 7;
+    valid.then_some(RuleDate {
+        month,
+        week,
+        weekday,
+        time,
+    })
+}
+
+/// Parse POSIX TZ string like `CET-1CEST,M3.5.0,M10.5.0/3`.
+fn parse_rule(mut s: &[u8]) -> Option<Rule> {
+    let s = &mut s;
+    parse_name(s)?;
+    let std_offset = -parse_hms(s)?; // POSIX offsets are positive to the west
+    if s.is_empty() {
+        return Some(Rule {
+            std_offset,
+            dst: None,
+        });
+    }
+    parse_name(s)?;
+    let dst_offset = match s.first()? {
+        b',' => std_offset + 3600,
+        _ => -parse_hms(s)?,
+    };
+    *s = s.strip_prefix(b",")?;
+    let start = parse_rule_date(s)?;
+    *s = s.strip_prefix(b",")?;
+    let end = parse_rule_date(s)?;
+    let dst = Some((dst_offset, start, end));
+    s.is_empty().then_some(Rule { std_offset, dst })
+}
+
+fn parse(data: &[u8]) -> Option<Zone> {
+    fn be(v: &[u8]) -> i64 {
+        match v.len() {
+            4 => i32::from_be_bytes(v.try_into().unwrap()) as i64,
+            _ => i64::from_be_bytes(v.try_into().unwrap()),
+        }
+    }
+    // returns (counts, total length) of the data block after header
+    fn block(data: &[u8], time_len: usize) -> Option<([usize; 6], usize)> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6]; // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+        for (i, v) in counts.iter_mut().enumerate() {
+            let bytes = data.get(20 + i * 4..24 + i * 4)?;
+            *v = u32::from_be_bytes(bytes.try_into().unwrap()) as usize;
+        }
+        let [isut, isstd, leap, time, typ, chars] = counts;
+        let len = time * (time_len + 1) + typ * 6 + chars + leap * (time_len + 4) + isstd + isut;
+        Some((counts, 44 + len))
+    }
+    let (mut counts, mut len) = block(data, 4)?;
+    let (mut data, mut time_len) = (data, 4);
+    let version = data[4];
+    if version != 0 {
+        // v2+ has the 64-bit block and the footer after the 32-bit block
+        data = data.get(len..)?;
+        (counts, len) = block(data, 8)?;
+        time_len = 8;
+    }
+    let [_, _, _, time_cnt, type_cnt, _] = counts;
+    let body = data.get(44..len)?;
+    let (times, rest) = body.split_at(time_cnt * time_len);
+    let (indexes, rest) = rest.split_at(time_cnt);
+    let types: Vec<i64> = (0..type_cnt).map(|i| be(&rest[i * 6..i * 6 + 4])).collect();
+    let times = times.chunks(time_len).map(be).collect();
+    let offsets = indexes.iter().map(|&i| types.get(i as usize).copied());
+    let offsets = offsets.collect::<Option<_>>()?;
+    let rule = match version {
+        0 => None,
+        _ => {
+            let footer = data.get(len..)?.strip_prefix(b"\n")?;
+            let footer = &footer[..footer.iter().position(|&v| v == b'\n')?];
+            parse_rule(footer)
+        }
+    };
+    Some(Zone {
+        times,
+        offsets,
+        initial: *types.first()?,
+        rule,
+    })
+}
+
+static ZONES: Mutex<BTreeMap<String, Option<Arc<Zone>>>> = Mutex::new(BTreeMap::new());
+
+/// Load the zone by IANA name like `"Asia/Shanghai"`, returns `None` if not found or invalid.
+pub fn get(name: &str) -> Option<Arc<Zone>> {
+    let mut zones = ZONES.lock().unwrap();
+    if let Some(v) = zones.get(name) {
+        return v.clone();
+    }
+    let dir = std::env::var("TZDIR").unwrap_or_else(|_| "/usr/share/zoneinfo".to_owned());
+    let zone = match name.split('/').any(|v| v.is_empty() || v.starts_with('.')) {
+        true => None, // avoid path traversal
+        false => std::fs::read(format!("{dir}/{name}"))
+            .ok()
+            .and_then(|v| parse(&v)),
+    };
+    if zone.is_none() {
+        log!(erro: "time zone not found or invalid, name = {name}");
+    }
+    let zone = zone.map(Arc::new);
+    zones.insert(name.to_owned(), zone.clone());
+    zone
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-03-08 07:00 UTC, 02:00 EST, the clock goes forward in New York.
+    const NY_FORWARD: i64 = 1772953200;
+    /// 2026-11-01 06:00 UTC, 02:00 EDT, the clock goes back in New York.
+    const NY_BACK: i64 = 1793512800;
+
+    /// A TZif of `version`, with the 32-bit block only for v1, and the footer for v2+.
+    fn sample(version: u8, footer: &str) -> Vec<u8> {
+        let block = |time_len: usize| {
+            let mut o = b"TZif".to_vec();
+            o.push(version);
+            o.extend([0; 15]);
+            for v in [0u32, 0, 0, 2, 2, 8] {
+                o.extend(v.to_be_bytes()); // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+            }
+            for v in [NY_FORWARD, NY_BACK] {
+                o.extend(&v.to_be_bytes()[8 - time_len..]);
+            }
+            o.extend([1, 0]);
+            o.extend((-5 * 3600i32).to_be_bytes());
+            o.extend([0, 0]);
+            o.extend((-4 * 3600i32).to_be_bytes());
+            o.extend([1, 4]);
+            o.extend(b"EST\0EDT\0");
+            o
+        };
+        let mut o = block(4);
+        if version != 0 {
+            o.extend(block(8));
+            o.extend(format!("\n{footer}\n").as_bytes());
+        }
+        o
+    }
+
+    #[test]
+    fn civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 2, 29), 11016);
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(days_in_month(1900, 2), 28);
+        assert_eq!(days_in_month(2000, 2), 29);
+    }
+
+    #[test]
+    fn posix_rule() {
+        let zone = Zone::from_posix("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        assert_eq!(zone.offset(NY_FORWARD - 1), -5 * 3600);
+        assert_eq!(zone.offset(NY_FORWARD), -4 * 3600);
+        assert_eq!(zone.offset(NY_BACK - 1), -4 * 3600);
+        assert_eq!(zone.offset(NY_BACK), -5 * 3600);
+        assert_eq!(zone.next_transition(NY_FORWARD - 1), Some(NY_FORWARD));
+        assert_eq!(zone.next_transition(NY_FORWARD), Some(NY_BACK));
+        // southern, the daylight saving time crosses the new year
+        let zone = Zone::from_posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(zone.offset(1767225600), 11 * 3600); // 2026-01-01
+        assert_eq!(zone.offset(1782864000), 10 * 3600); // 2026-07-01
+        assert_eq!(zone.next_transition(1767225600), Some(1775318400)); // 2026-04-04 16:00 UTC
+        let zone = Zone::from_posix("<+0330>-3:30").unwrap();
+        assert_eq!(zone.offset(0), 3 * 3600 + 30 * 60);
+        assert_eq!(zone.next_transition(0), None);
+        let zone = Zone::from_posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert_eq!(zone.offset(1774746000 - 1), 3600); // 2026-03-29 01:00 UTC
+        assert_eq!(zone.offset(1774746000), 7200);
+    }
+
+    #[test]
+    fn posix_rule_invalid() {
+        for s in [
+            "",
+            "EST",
+            "E5",
+            "<+0330",
+            "EST5EDT",
+            "EST5EDT,M3.2.0",
+            "EST5EDT,M13.2.0,M11.1.0",
+            "EST5EDT,M3.6.0,M11.1.0",
+            "EST5EDT,M3.2.7,M11.1.0",
+            "EST5EDT,J60,M11.1.0",
+            "EST5EDT,M3.2.0,M11.1.0x",
+            "EST99999999999999999999",
+            "EST9999999999999999:00",
+        ] {
+            assert!(Zone::from_posix(s).is_none(), "{s}");
+        }
+    }
+
+    #[test]
+    fn tzif() {
+        let zone = parse(&sample(0, "")).unwrap();
+        assert_eq!(zone.offset(NY_FORWARD - 1), -5 * 3600);
+        assert_eq!(zone.offset(NY_FORWARD), -4 * 3600);
+        assert_eq!(zone.offset(NY_BACK), -5 * 3600);
+        assert_eq!(zone.next_transition(NY_BACK), None);
+        // the footer rule goes on after the last transition
+        let zone = parse(&sample(2, "EST5EDT,M3.2.0,M11.1.0")).unwrap();
+        assert_eq!(zone.offset(NY_BACK), -5 * 3600);
+        assert_eq!(zone.next_transition(NY_BACK), Some(1805007600)); // 2027-03-14
+        assert_eq!(zone.offset(1805007600), -4 * 3600);
+    }
+
+    #[test]
+    fn tzif_invalid() {
+        let data = sample(2, "EST5EDT,M3.2.0,M11.1.0");
+        for len in 0..data.len() {
+            assert!(parse(&data[..len]).is_none(), "truncated at {len}");
+        }
+        let mut data = sample(0, "");
+        data[20..44].fill(0xff); // huge counts
+        assert!(parse(&data).is_none());
+        let mut data = sample(0, "");
+        data[52] = 9; // the type index out of range
+        assert!(parse(&data).is_none());
+        assert!(parse(b"garbage").is_none());
+        assert!(parse(&[0xff; 64]).is_none());
+    }
+}
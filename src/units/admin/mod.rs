@@ -2,12 +2,13 @@
 
 use crate::auth::auth_layer;
 use crate::database::DB;
-use crate::{include_src, log, strip_str};
+use crate::{log, strip_str, template};
 use axum::body::Bytes;
 use axum::extract::RawQuery;
 use axum::middleware;
 use axum::response::Html;
 use axum::routing::{MethodRouter, Router};
+use serde_json::json;
 use std::time::{Duration, UNIX_EPOCH};
 
 pub mod db {
@@ -86,37 +87,46 @@ async fn post_handler(q: RawQuery, body: Bytes) -> Bytes {
             file.read_to_string(&mut buf).unwrap();
             return Bytes::from(buf);
         }
-        "set_tls_ca" => {
-            db::set("tls_ca".to_owned(), body).await;
+        "trigger_run_job" => {
+            let name = String::from_utf8_lossy(&body);
+            if !crate::scheduler::run_now(name.trim()) {
+                return Bytes::from_static(b"unknown job");
+            }
         }
-        "set_tls_cert" => {
-            db::set("tls_cert".to_owned(), body).await;
-        }
-        "set_tls_key" => {
-            db::set("tls_key".to_owned(), body).await;
-        }
-        "set_copilot_token" => {
-            db::set("copilot_token".to_owned(), body).await;
-        }
-        "set_copilot_machineid" => {
-            db::set("copilot_machineid".to_owned(), body).await;
-        }
-        "set_qqbot_device" => {
-            db::set("qqbot_device".to_owned(), body).await;
+        "set_units" => {
+            if let Err(e) = crate::units::parse_config(&body) {
+                return Bytes::from(format!("invalid units: {e}"));
+            }
+            db::set("units".to_owned(), body).await;
+            // need restart to take effect
         }
-        "set_qqbot_token" => {
-            db::set("qqbot_token".to_owned(), body).await;
+        "set_info_probes" if crate::units::is_enabled("info") => {
+            if let Err(e) = crate::units::info::set_probes(&body).await {
+                return Bytes::from(format!("invalid probes: {e}"));
+            }
         }
-        "set_qqbot_notify_groups" => {
-            db::set("qqbot_notify_groups".to_owned(), body).await;
+        "set_dav_user" if crate::units::is_enabled("dav") => {
+            if let Err(e) = crate::units::dav::set_user(&body).await {
+                return Bytes::from(format!("invalid user: {e}"));
+            }
         }
-        "set_v2ex_cookies" => {
-            db::set("v2ex_cookies".to_owned(), body).await;
+        "set_dav_user_quota" if crate::units::is_enabled("dav") => {
+            if let Err(e) = crate::units::dav::set_user_quota(&body).await {
+                return Bytes::from(format!("invalid quota: {e}"));
+            }
         }
-        _ => {
-            log!(erro: "units::admin unknown op");
-            return Bytes::from_static(b"unknown op");
+        "set_dav_user_delete" if crate::units::is_enabled("dav") => {
+            if let Err(e) = crate::units::dav::del_user(&body).await {
+                return Bytes::from(format!("failed to delete user: {e}"));
+            }
         }
+        _ => match config_key(k) {
+            Some(key) => db::set(key.to_owned(), body).await,
+            None => {
+                log!(erro: "units::admin unknown op");
+                return Bytes::from_static(b"unknown op");
+            }
+        },
     }
     Bytes::from(format!(
         "finished, now = {}",
@@ -124,18 +134,55 @@ async fn post_handler(q: RawQuery, body: Bytes) -> Bytes {
     ))
 }
 
-pub fn service() -> Router {
-    crate::utils::block_on(async {
-        db::init().await;
-        if db::get("auth_key".to_owned()).await.is_none() {
-            db::set("auth_key".to_owned(), Bytes::from(crate::auth::auth_key())).await;
-        }
-    });
+/// Find the key of `set_*` op in config keys of enabled units.
+fn config_key(op: &str) -> Option<&'static str> {
+    let key = op.strip_prefix("set_")?;
+    let keys = crate::units::enabled().iter().flat_map(|v| v.config_keys());
+    keys.map(|v| v.split(' ').next().unwrap())
+        .find(|&v| v == key)
+}
+
+async fn get_handler() -> Html<String> {
+    let units = crate::units::enabled().iter();
+    let keys: Vec<_> = units.flat_map(|v| v.config_keys()).collect();
+    let jobs = crate::scheduler::report().await;
+    let data = json!({ "keys": keys, "jobs": jobs });
+    Html(template!("page.html", title = "Admin").render(&data))
+}
+
+fn service() -> Router {
     Router::new().route(
         "/admin",
         MethodRouter::new()
-            .get(Html((include_src!("page.html") as [_; 1])[0]))
+            .get(get_handler)
             .post(post_handler)
             .route_layer(middleware::from_fn(auth_layer)),
     )
 }
+
+pub struct Admin;
+
+impl crate::units::Unit for Admin {
+    fn name(&self) -> &'static str {
+        "admin"
+    }
+    fn migrate(&self) -> crate::units::BoxFuture<()> {
+        Box::pin(async {
+            db::init().await;
+            if db::get("auth_key".to_owned()).await.is_none() {
+                db::set("auth_key".to_owned(), Bytes::from(crate::auth::auth_key())).await;
+            }
+        })
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &[
+            "tls_ca (pem)",
+            "tls_cert (pem)",
+            "tls_key (pem)",
+            "units (json object)",
+        ]
+    }
+}
@@ -1,13 +1,12 @@
 //! Simple chat rooms, client-to-client encrypted.
 
-use crate::include_src;
+use crate::assets::Asset;
 use crate::utils::LazyLock as Lazy;
+use crate::{asset, template};
 use anyhow::Result;
 use axum::extract::Path;
-use axum::http::header::CACHE_CONTROL;
-use axum::http::HeaderValue;
+use axum::http::HeaderMap;
 use axum::response::sse::{Event as SseEvent, Sse};
-use axum::response::Html;
 use axum::routing::{MethodRouter, Router};
 use futures_core::Stream;
 use std::collections::HashMap;
@@ -60,6 +59,13 @@ pub struct ChatServer {
 }
 
 impl ChatServer {
+    /// Returns `(rooms, subscribers)`.
+    pub fn stats(&self) -> (usize, u64) {
+        let rooms = self.rooms.lock().unwrap();
+        let subscribers = rooms.values().map(|v| v.user_count as u64).sum();
+        (rooms.len(), subscribers)
+    }
+
     pub fn post_router(&self) -> MethodRouter {
         let rooms_mutex = self.rooms.clone();
         MethodRouter::new().post(|Path(id): Path<u32>, msg: String| async move {
@@ -104,15 +110,37 @@ impl ChatServer {
 
 static CHAT_SERVER: Lazy<ChatServer> = Lazy::new(Default::default);
 
-pub fn service() -> Router {
+pub fn stats() -> (usize, u64) {
+    CHAT_SERVER.stats()
+}
+
+static SCRIPT: &Asset = asset!("page.mjs");
+
+static PAGE: Lazy<Asset> = Lazy::new(|| {
+    let page = template!("page.html").render(&serde_json::json!({ "script": SCRIPT.url() }));
+    Asset::new("page.html", page.into_bytes())
+});
+
+fn service() -> Router {
     Router::new()
         .route(
             "/chat", // https://127.0.0.1:9304/chat#123
-            MethodRouter::new().get((
-                [(CACHE_CONTROL, HeaderValue::from_static("max-age=300"))],
-                Html((include_src!("page.html") as [_; 1])[0]),
-            )),
+            MethodRouter::new().get(|headers: HeaderMap| async move { PAGE.serve(&headers) }),
         )
         .route("/chat/post/:room", CHAT_SERVER.post_router())
         .route("/chat/sse/:room", CHAT_SERVER.sse_router())
 }
+
+pub struct Chat;
+
+impl crate::units::Unit for Chat {
+    fn name(&self) -> &'static str {
+        "chat"
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn assets(&self) -> Vec<&'static Asset> {
+        vec![SCRIPT]
+    }
+}
@@ -0,0 +1,81 @@
+if (!isSecureContext) throw alert("needs https or localhost");
+// base64 <-> array buffer
+const b2a = (b) => Uint8Array.from(atob(b), (c) => c.charCodeAt(0));
+const a2b = (a) => btoa(String.fromCharCode(...new Uint8Array(a)));
+const [textEnc, textDec] = [new TextEncoder(), new TextDecoder()];
+const algo = {
+  name: "RSA-OAEP",
+  modulusLength: 2048, // limited. raw string length should less than about 128
+  publicExponent: new Uint8Array([1, 0, 1]),
+  hash: "SHA-256",
+};
+const keepProfile = localStorage.cfg && confirm("Keep previous profile?");
+const cfg = keepProfile ? JSON.parse(localStorage.cfg) : {};
+if (!cfg.id) {
+  const keyPair = await crypto.subtle //
+    .generateKey(algo, true, ["encrypt", "decrypt"]);
+  const pubKey = await crypto.subtle.exportKey("jwk", keyPair.publicKey);
+  const privKey = await crypto.subtle.exportKey("jwk", keyPair.privateKey);
+  cfg.id = prompt("User ID", Math.round(Math.random() * 1e9).toString(36));
+  cfg.pubKey = JSON.stringify(pubKey);
+  cfg.privKey = JSON.stringify(privKey);
+  cfg.friends = [{ id: cfg.id, pubKey: cfg.pubKey }]; // my best friend is myself!
+  // decrypt: () => {} // JSON.stringify will ignore functions
+}
+const myPrivKey = await crypto.subtle //
+  .importKey("jwk", JSON.parse(cfg.privKey), algo, true, ["decrypt"]);
+cfg.decrypt = async (i) =>
+  textDec.decode(await crypto.subtle.decrypt(algo, myPrivKey, b2a(i)));
+const initFriend = async (friend) => {
+  const pubKey = await crypto.subtle //
+    .importKey("jwk", JSON.parse(friend.pubKey), algo, true, ["encrypt"]);
+  friend.encrypt = async (i) =>
+    a2b(await crypto.subtle.encrypt(algo, pubKey, textEnc.encode(i)));
+};
+for (const friend of cfg.friends) await initFriend(friend);
+if (!location.hash)
+  location.hash = prompt("Room ID", (Math.random() * 1e5).toFixed());
+const room = location.hash.slice(1);
+const sse = new EventSource(`/chat/sse/${room}`);
+await new Promise((r) => (sse.onopen = r));
+$send.placeholder += ` as ${cfg.id} in room ${room}`;
+const post = async (data) => {
+  const body = JSON.stringify(data);
+  const e = await fetch(`/chat/post/${room}`, { method: "POST", body })
+    .then((r) => r.text())
+    .catch((e) => e);
+  if (e) alert(`post failed, error = ${e}`);
+};
+const joinRoom = () => post({ op: "join", id: cfg.id, pubKey: cfg.pubKey });
+const sendMsg = (msg) => {
+  cfg.friends.forEach(async (friend) => {
+    const value = await friend.encrypt(msg);
+    post({ op: "msg", id: cfg.id, target: friend.id, value });
+  });
+};
+sse.onmessage = async (e) => {
+  const data = JSON.parse(e.data);
+  if (data.op === "msg" && data.target === cfg.id) {
+    const el = document.createElement("p");
+    el.textContent = data.id + " : " + (await cfg.decrypt(data.value));
+    $send.parentNode.insertBefore(el, $send).scrollIntoView();
+  } else if (data.op === "join") {
+    if (cfg.friends.find((v) => v.id === data.id)) return;
+    else if (confirm(`[${data.id}] want to join this room`)) {
+      const friend = { id: data.id, pubKey: data.pubKey };
+      initFriend(friend);
+      cfg.friends.push(friend);
+      joinRoom(); // send new friend a request to gain trust
+    }
+  }
+};
+$send.onkeyup = (e) => {
+  if (e.key !== "Enter") return;
+  sendMsg($send.value);
+  $send.value = "";
+};
+joinRoom();
+onunload = () => {
+  sendMsg("[disconnected]");
+  localStorage.cfg = JSON.stringify(cfg);
+};
@@ -80,7 +80,7 @@ async fn post_handler(mut req: Request<Body>) -> impl IntoResponse {
     Ok(res)
 }
 
-pub fn service() -> Router {
+fn service() -> Router {
     Router::new()
         .route(
             "/copilotgpt/v1/models",
@@ -95,3 +95,25 @@ pub fn service() -> Router {
             ]),
         )
 }
+
+pub struct CopilotGpt;
+
+impl crate::units::Unit for CopilotGpt {
+    fn name(&self) -> &'static str {
+        "copilotgpt"
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["copilot_token", "copilot_machineid"]
+    }
+    fn health(&self) -> crate::units::BoxFuture<anyhow::Result<()>> {
+        Box::pin(async {
+            match admin::db::get("copilot_token".to_owned()).await {
+                Some(_) => Ok(()),
+                None => Err(anyhow::anyhow!("copilot_token is not set")),
+            }
+        })
+    }
+}
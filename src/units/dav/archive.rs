@@ -0,0 +1,397 @@
+//! Archives of the entries as zip or tar.gz, streamed while reading the chunks.
+//!
+//! The zip is stored without compression, as most large files are compressed already. No zip64, the quota keeps
+//! the archive far below 4 GiB.
+
+use super::crypt::{self, Key};
+use super::db;
+use crate::utils::OptionResult;
+use axum::body::{Body, Bytes};
+use axum::http::header::*;
+use axum::response::{IntoResponse, Response};
+use flate2::write::{GzEncoder, MultiGzDecoder};
+use flate2::Compression;
+use std::io::Write;
+use tokio::sync::mpsc;
+
+type Entries = Vec<(String, u64, u64, u64, u64)>;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    fn parse(v: &str) -> Option<Self> {
+        match v {
+            "zip" => Some(Self::Zip),
+            "tar.gz" | "tgz" => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+}
+
+/// The sender with the count of bytes sent, which is the offset in the archive before compressing.
+struct Writer {
+    tx: mpsc::Sender<anyhow::Result<Bytes>>,
+    offset: u64,
+    gzip: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl Writer {
+    async fn send(&mut self, data: Vec<u8>) -> anyhow::Result<()> {
+        self.offset += data.len() as u64;
+        let data = match &mut self.gzip {
+            Some(e) => {
+                e.write_all(&data)?;
+                if e.get_ref().len() < 64 * 1024 {
+                    return Ok(());
+                }
+                std::mem::take(e.get_mut())
+            }
+            None => data,
+        };
+        let sent = self.tx.send(Ok(Bytes::from(data))).await;
+        sent.map_err(|_| anyhow::anyhow!("receiver closed"))
+    }
+    async fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(e) = self.gzip.take() {
+            let sent = self.tx.send(Ok(Bytes::from(e.finish()?))).await;
+            sent.map_err(|_| anyhow::anyhow!("receiver closed"))?;
+        }
+        Ok(())
+    }
+}
+
+/// The content of a file piece by piece, opened by the key, and decompressed by the decoder if any.
+struct Content<'a> {
+    hashes: std::vec::IntoIter<[u8; 32]>,
+    key: Option<&'a Key>,
+    decoder: Option<MultiGzDecoder<Vec<u8>>>,
+    pending: (Vec<u8>, usize),
+}
+
+impl<'a> Content<'a> {
+    async fn new(eid: &str, gunzip: bool, key: Option<&'a Key>) -> Content<'a> {
+        let hashes = db::list_blocks(eid.to_owned()).await.into_iter();
+        let decoder = gunzip.then(|| MultiGzDecoder::new(Vec::new()));
+        let pending = (Vec::new(), 0);
+        Self {
+            hashes,
+            key,
+            decoder,
+            pending,
+        }
+    }
+    async fn chunk(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(hash) = self.hashes.next() else {
+            return Ok(None);
+        };
+        let chunk = db::get_chunk(hash).await.e()?;
+        Ok(Some(match self.key {
+            Some(key) => crypt::open(key, &chunk).e()?,
+            None => chunk,
+        }))
+    }
+    async fn next(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        loop {
+            if self.decoder.is_none() {
+                return self.chunk().await;
+            }
+            if self.pending.1 == self.pending.0.len() {
+                let Some(chunk) = self.chunk().await? else {
+                    let rest = self.decoder.take().unwrap().finish()?;
+                    return Ok(Some(rest).filter(|v| !v.is_empty()));
+                };
+                self.pending = (chunk, 0);
+            }
+            // in small pieces, so the decompressed output is bounded
+            let (pending, pos) = &mut self.pending;
+            let end = pending.len().min(*pos + 64 * 1024);
+            let decoder = self.decoder.as_mut().unwrap();
+            decoder.write_all(&pending[*pos..end])?;
+            *pos = end;
+            let out = std::mem::take(decoder.get_mut());
+            if !out.is_empty() {
+                return Ok(Some(out));
+            }
+        }
+    }
+}
+
+/// The name in the archive, or `None` to skip. The gzip files kept as-is are suffixed by `.gz`.
+fn entry_name(base: &str, eid: &str, flag: u64, gunzip: bool) -> Option<String> {
+    let name = eid.strip_prefix(base)?.strip_prefix('/')?;
+    if flag & db::ENTRY_HREF != 0 {
+        return None;
+    }
+    Some(if flag & db::ENTRY_DIR != 0 {
+        format!("{name}/")
+    } else if flag & db::ENTRY_GZIP != 0 && !gunzip {
+        format!("{name}.gz")
+    } else {
+        name.to_owned()
+    })
+}
+
+/// The MS-DOS `(time, date)`, in UTC as the zone of the reader is unknown. The precise time is in the extra field.
+fn dos_time(secs: u64) -> (u16, u16) {
+    let (y, m, d) = crate::tz::civil_from_days((secs / 86400) as _);
+    let secs = secs % 86400;
+    let time = (secs / 3600) << 11 | (secs / 60 % 60) << 5 | (secs % 60 / 2);
+    let date = ((y.clamp(1980, 2107) - 1980) as u64) << 9 | m << 5 | d;
+    (time as _, date as _)
+}
+
+/// The header fields shared by the local and central ones, from the version needed to the extra length.
+fn zip_header(flags: u16, time: u64, crc: u32, size: u32, name: &str) -> Vec<u8> {
+    let (dos_time, dos_date) = dos_time(time);
+    let mut o = Vec::new();
+    o.extend(20u16.to_le_bytes()); // version needed, 2.0
+    o.extend(flags.to_le_bytes());
+    o.extend(0u16.to_le_bytes()); // stored
+    o.extend(dos_time.to_le_bytes());
+    o.extend(dos_date.to_le_bytes());
+    o.extend(crc.to_le_bytes());
+    o.extend(size.to_le_bytes()); // compressed
+    o.extend(size.to_le_bytes());
+    o.extend((name.len() as u16).to_le_bytes());
+    o.extend(9u16.to_le_bytes()); // extra length
+    o
+}
+
+/// The extended timestamp extra field with the mtime.
+fn zip_extra(time: u64) -> Vec<u8> {
+    let mut o = Vec::new();
+    o.extend(0x5455u16.to_le_bytes());
+    o.extend(5u16.to_le_bytes());
+    o.push(1); // mtime only
+    o.extend((time as u32).to_le_bytes());
+    o
+}
+
+async fn write_zip(
+    w: &mut Writer,
+    base: &str,
+    entries: Entries,
+    gunzip: bool,
+    key: Option<&Key>,
+) -> anyhow::Result<()> {
+    let mut central = Vec::new();
+    let mut count = 0u16;
+    for (eid, time, _, flag, _) in entries {
+        let Some(name) = entry_name(base, &eid, flag, gunzip) else {
+            continue;
+        };
+        let is_dir = flag & db::ENTRY_DIR != 0;
+        let offset = w.offset;
+        // utf-8 names, and the sizes and crc of files are in the data descriptor
+        let flags = match is_dir {
+            true => 0x0800,
+            false => 0x0808,
+        };
+        let mut local = 0x04034b50u32.to_le_bytes().to_vec();
+        local.extend(zip_header(flags, time, 0, 0, &name));
+        local.extend(name.as_bytes());
+        local.extend(zip_extra(time));
+        w.send(local).await?;
+        let (mut crc, mut size) = (flate2::Crc::new(), 0u32);
+        if !is_dir {
+            let gunzip = gunzip && flag & db::ENTRY_GZIP != 0;
+            let mut content = Content::new(&eid, gunzip, key).await;
+            while let Some(data) = content.next().await? {
+                crc.update(&data);
+                size = size.checked_add(data.len().try_into()?).e()?;
+                w.send(data).await?;
+            }
+            let mut descriptor = 0x08074b50u32.to_le_bytes().to_vec();
+            descriptor.extend(crc.sum().to_le_bytes());
+            descriptor.extend(size.to_le_bytes());
+            descriptor.extend(size.to_le_bytes());
+            w.send(descriptor).await?;
+        }
+        central.extend(0x02014b50u32.to_le_bytes());
+        central.extend((3u16 << 8 | 20).to_le_bytes()); // made by unix, for the permissions
+        central.extend(zip_header(flags, time, crc.sum(), size, &name));
+        central.extend([0; 4]); // comment length, disk number
+        central.extend(0u16.to_le_bytes()); // internal attributes
+        let mode: u32 = match is_dir {
+            true => 0o40755 << 16 | 0x10,
+            false => 0o100644 << 16,
+        };
+        central.extend(mode.to_le_bytes());
+        central.extend(u32::try_from(offset)?.to_le_bytes());
+        central.extend(name.as_bytes());
+        central.extend(zip_extra(time));
+        count = count.checked_add(1).e()?;
+    }
+    let (central_offset, central_size) = (w.offset, central.len());
+    w.send(central).await?;
+    let mut end = 0x06054b50u32.to_le_bytes().to_vec();
+    end.extend([0; 4]); // disk numbers
+    end.extend(count.to_le_bytes());
+    end.extend(count.to_le_bytes());
+    end.extend((central_size as u32).to_le_bytes());
+    end.extend(u32::try_from(central_offset)?.to_le_bytes());
+    end.extend(0u16.to_le_bytes()); // comment length
+    w.send(end).await
+}
+
+/// The ustar header, the longer name is truncated here and given by `tar_pax`.
+fn tar_header(name: &str, size: u64, time: u64, kind: u8) -> Vec<u8> {
+    let mut h = vec![0; 512];
+    let mut put = |at: usize, len: usize, v: &[u8]| {
+        let n = v.len().min(len);
+        h[at..at + n].copy_from_slice(&v[..n]);
+    };
+    let octal = |v: u64, len: usize| format!("{v:0w$o}", w = len - 1).into_bytes();
+    let mode = match kind {
+        b'5' => 0o755,
+        _ => 0o644,
+    };
+    put(0, 100, name.as_bytes());
+    put(100, 8, &octal(mode, 8));
+    put(108, 8, &octal(0, 8)); // uid
+    put(116, 8, &octal(0, 8)); // gid
+    put(124, 12, &octal(size, 12));
+    put(136, 12, &octal(time, 12));
+    put(148, 8, b"        "); // the checksum is counted as spaces
+    put(156, 1, &[kind]);
+    put(257, 8, b"ustar\x0000");
+    let sum: u32 = h.iter().map(|&v| v as u32).sum();
+    h[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+    h
+}
+
+/// The pax extended header with the path, for the name longer than 100 bytes.
+fn tar_pax(name: &str, time: u64) -> Vec<u8> {
+    // the record starts with its own length
+    let record = |len: usize| format!("{len} path={name}\n");
+    let mut len = record(0).len();
+    while record(len).len() != len {
+        len = record(len).len();
+    }
+    let mut o = tar_header("././@PaxHeader", len as _, time, b'x');
+    o.extend(record(len).as_bytes());
+    o.resize((o.len() + 511) / 512 * 512, 0);
+    o
+}
+
+async fn write_tar(
+    w: &mut Writer,
+    base: &str,
+    entries: Entries,
+    gunzip: bool,
+    key: Option<&Key>,
+) -> anyhow::Result<()> {
+    for (eid, time, size, flag, _) in entries {
+        let Some(name) = entry_name(base, &eid, flag, gunzip) else {
+            continue;
+        };
+        let is_dir = flag & db::ENTRY_DIR != 0;
+        let gunzip = gunzip && flag & db::ENTRY_GZIP != 0;
+        // the header goes first, so the decompressed size is counted in advance
+        let size = match (is_dir, gunzip) {
+            (true, _) => 0,
+            (false, true) => {
+                let (mut content, mut size) = (Content::new(&eid, true, key).await, 0);
+                while let Some(data) = content.next().await? {
+                    size += data.len() as u64;
+                }
+                size
+            }
+            (false, false) => size,
+        };
+        let mut header = match name.len() > 100 {
+            true => tar_pax(&name, time),
+            false => Vec::new(),
+        };
+        let kind = if is_dir { b'5' } else { b'0' };
+        header.extend(tar_header(&name, size, time, kind));
+        w.send(header).await?;
+        if is_dir {
+            continue;
+        }
+        let (mut content, mut sent) = (Content::new(&eid, gunzip, key).await, 0);
+        while let Some(data) = content.next().await? {
+            sent += data.len() as u64;
+            w.send(data).await?;
+        }
+        if sent != size {
+            return Err(anyhow::anyhow!("size mismatch"));
+        }
+        w.send(vec![0; ((512 - size % 512) % 512) as _]).await?;
+    }
+    w.send(vec![0; 1024]).await // the end of archive
+}
+
+/// The archive of `entries` like `db::list_entry_tree`, named by the path under `base`.
+///
+/// The gzip files are decompressed if `gunzip`, or kept as-is.
+pub fn build(
+    format: Format,
+    base: String,
+    entries: Entries,
+    gunzip: bool,
+    key: Option<Key>,
+) -> Body {
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        let gzip = match format {
+            Format::Zip => None,
+            Format::TarGz => Some(GzEncoder::new(Vec::new(), Compression::default())),
+        };
+        let mut w = Writer {
+            tx,
+            offset: 0,
+            gzip,
+        };
+        let r = match format {
+            Format::Zip => write_zip(&mut w, &base, entries, gunzip, key.as_ref()).await,
+            Format::TarGz => write_tar(&mut w, &base, entries, gunzip, key.as_ref()).await,
+        };
+        if let Err(e) = r.and(w.finish().await) {
+            w.tx.send(Err(e)).await.ok(); // break the body, so the client knows it's incomplete
+        }
+    });
+    Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx))
+}
+
+/// The `GET` of a dir, with the query `archive=zip` or `archive=tar.gz`, and `gzip=keep` to keep the gzip files.
+pub async fn get(
+    eid: String,
+    query: Option<&str>,
+    is_head: bool,
+    key: Option<Key>,
+) -> anyhow::Result<Response> {
+    let (mut format, mut gunzip) = (None, true);
+    for (k, v) in query
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|v| v.split_once('='))
+    {
+        match k {
+            "archive" => format = Format::parse(v),
+            "gzip" => gunzip = v != "keep",
+            _ => {}
+        }
+    }
+    let format = format.e()?;
+    let (content_type, ext) = match format {
+        Format::Zip => ("application/zip", "zip"),
+        Format::TarGz => ("application/gzip", "tar.gz"),
+    };
+    let name = eid.rsplit_once('/').map(|v| v.1).unwrap_or("dav");
+    let name = percent_encoding::utf8_percent_encode(name, percent_encoding::NON_ALPHANUMERIC);
+    let disposition = format!("attachment; filename*=UTF-8''{name}.{ext}");
+    let headers = [
+        (CONTENT_TYPE, content_type.to_owned()),
+        (CONTENT_DISPOSITION, disposition),
+    ];
+    if is_head {
+        return Ok(headers.into_response());
+    }
+    let entries = db::list_entry_tree(eid.to_owned(), u32::MAX as _).await;
+    Ok((headers, build(format, eid, entries, gunzip, key)).into_response())
+}
@@ -0,0 +1,115 @@
+//! File content as chunks, each chunk is stored once and addressed by its SHA-256.
+
+use super::crypt::{self, Key};
+use super::db;
+use crate::log;
+use crate::sha256::{sha256, Sha256};
+use axum::body::{Body, Bytes};
+use std::time::UNIX_EPOCH;
+use tokio_stream::StreamExt as _;
+
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The chunks unreferenced for longer than this are removed, so uploads in progress are safe.
+const GC_GRACE: u64 = 3600 * 6;
+
+async fn put(chunk: Vec<u8>, hashes: &mut Vec<[u8; 32]>, key: Option<&Key>) {
+    let chunk = match key {
+        Some(key) => crypt::seal(key, &chunk),
+        None => chunk,
+    };
+    let hash = sha256(&chunk);
+    db::put_chunk(hash, chunk).await;
+    hashes.push(hash);
+}
+
+/// Store the body while receiving, returns the chunk hashes and size, or `None` if larger than `limit`.
+///
+/// The chunks are sealed if `key` is given, the size is of the plaintext.
+pub async fn write(
+    body: Body,
+    limit: u64,
+    key: Option<&Key>,
+) -> anyhow::Result<Option<(Vec<[u8; 32]>, u64)>> {
+    let mut stream = body.into_data_stream();
+    let (mut hashes, mut size) = (Vec::new(), 0);
+    let mut buf = Vec::with_capacity(CHUNK_SIZE);
+    while let Some(data) = stream.next().await {
+        let mut data = &data?[..];
+        size += data.len() as u64;
+        if size > limit {
+            return Ok(None); // the stored chunks will be removed by gc
+        }
+        while !data.is_empty() {
+            let n = data.len().min(CHUNK_SIZE - buf.len());
+            buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if buf.len() == CHUNK_SIZE {
+                let chunk = std::mem::replace(&mut buf, Vec::with_capacity(CHUNK_SIZE));
+                put(chunk, &mut hashes, key).await;
+            }
+        }
+    }
+    if !buf.is_empty() {
+        put(buf, &mut hashes, key).await;
+    }
+    Ok(Some((hashes, size)))
+}
+
+/// Strong etag of the content, the chunk hashes identify it already.
+pub fn etag(hashes: &[[u8; 32]]) -> String {
+    let mut h = Sha256::default();
+    hashes.iter().for_each(|v| h.update(v));
+    let v = h.finish();
+    let hex: String = v[..16].iter().map(|b| format!("{b:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+pub enum Piece {
+    Bytes(Bytes),
+    /// The `start..end` part of the chunk.
+    Chunk([u8; 32], usize, usize),
+}
+
+/// Pieces of the `start..end` part of the content. All chunks except the last are `CHUNK_SIZE`.
+pub fn range(hashes: &[[u8; 32]], start: u64, end: u64, o: &mut Vec<Piece>) {
+    let size = CHUNK_SIZE as u64;
+    let mut pos = start;
+    while pos < end {
+        let (idx, offset) = ((pos / size) as usize, (pos % size) as usize);
+        let len = (size - offset as u64).min(end - pos) as usize;
+        o.push(Piece::Chunk(hashes[idx], offset, offset + len));
+        pos += len as u64;
+    }
+}
+
+/// Load the chunks one by one while sending, opened by `key` if given.
+pub fn read(pieces: Vec<Piece>, key: Option<Key>) -> Body {
+    let stream = tokio_stream::iter(pieces).then(move |piece| {
+        let key = key.clone();
+        async move {
+            let (hash, start, end) = match piece {
+                Piece::Bytes(v) => return Ok(v),
+                Piece::Chunk(hash, start, end) => (hash, start, end),
+            };
+            let chunk = db::get_chunk(hash).await;
+            let chunk = chunk.ok_or_else(|| anyhow::anyhow!("chunk missing"))?;
+            let chunk = match key {
+                Some(key) => {
+                    crypt::open(&key, &chunk).ok_or_else(|| anyhow::anyhow!("chunk corrupted"))?
+                }
+                None => chunk,
+            };
+            let chunk = Bytes::from(chunk);
+            anyhow::Ok(chunk.slice(start..end.min(chunk.len())))
+        }
+    });
+    Body::from_stream(stream)
+}
+
+pub async fn gc() -> anyhow::Result<()> {
+    let before = UNIX_EPOCH.elapsed().unwrap().as_secs() - GC_GRACE;
+    let removed = db::gc_chunks(before).await;
+    log!(info: "dav gc removed {removed} chunks");
+    Ok(())
+}
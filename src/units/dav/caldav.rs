@@ -0,0 +1,505 @@
+//! CalDAV and CardDAV. RFC 4791, RFC 6352, RFC 6578, RFC 6764
+//!
+//! The calendars and address books are dirs with a flag, each object inside is a plain file of one `.ics` or `.vcf`.
+//! The user's root is both the principal and the home. The recurrences are not expanded, a recurring component
+//! always matches the time range, which is allowed as the clients filter again.
+
+use super::crypt::{self, Key};
+use super::props::{self, Ctx, Find};
+use super::xml::{self, Element, DAV};
+use super::{db, path};
+use crate::tz::days_from_civil;
+use crate::utils::OptionResult;
+use axum::http::{header::*, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+pub const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+pub const CARDDAV: &str = "urn:ietf:params:xml:ns:carddav";
+/// For `getctag`, which the old clients use instead of the sync token.
+pub const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+
+pub const COMPONENTS: [&str; 3] = ["VEVENT", "VTODO", "VJOURNAL"];
+
+/// The objects are read into memory for the filters.
+pub const MAX_OBJECT_SIZE: usize = 4 * 1024 * 1024;
+
+const SYNC_TOKEN_PREFIX: &str = "data:,sync-";
+
+pub fn sync_token(seq: u64) -> String {
+    format!("{SYNC_TOKEN_PREFIX}{seq}")
+}
+
+/// A component of iCalendar or vCard, with the lines unfolded.
+struct Component {
+    name: String,
+    /// `(name, params, value)`, the name is in upper case without the vCard group.
+    props: Vec<(String, String, String)>,
+    children: Vec<Component>,
+}
+
+impl Component {
+    fn prop(&self, name: &str) -> Option<&str> {
+        self.props.iter().find(|v| v.0 == name).map(|v| &v.2[..])
+    }
+}
+
+/// Parse the first component, `None` if malformed.
+fn parse(text: &str) -> Option<Component> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) => lines.last_mut()?.push_str(rest),
+            None if line.is_empty() => {}
+            None => lines.push(line.to_owned()),
+        }
+    }
+    let mut stack: Vec<Component> = Vec::new();
+    for line in lines {
+        // the colon may be quoted in params
+        let mut quoted = false;
+        let colon = line.find(|c| {
+            quoted ^= c == '"';
+            c == ':' && !quoted
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let (name, params) = head.split_once(';').unwrap_or((head, ""));
+        let name = name.rsplit('.').next().unwrap().to_ascii_uppercase();
+        match &name[..] {
+            "BEGIN" => stack.push(Component {
+                name: value.to_ascii_uppercase(),
+                props: Vec::new(),
+                children: Vec::new(),
+            }),
+            "END" => {
+                let comp = stack.pop()?;
+                if !comp.name.eq_ignore_ascii_case(value) {
+                    return None;
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(comp),
+                    None => return Some(comp),
+                }
+            }
+            _ => {
+                let prop = (name, params.to_owned(), value.to_owned());
+                stack.last_mut()?.props.push(prop);
+            }
+        }
+    }
+    None
+}
+
+/// Seconds of `19970714T173000Z` or `19970714`, returns `(secs, is_date, is_utc)`. The local times are taken as UTC.
+fn parse_time(v: &str) -> Option<(i64, bool, bool)> {
+    let num = |start: usize, len: usize| -> Option<u64> {
+        let v = v.get(start..start + len)?;
+        v.bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| v.parse().ok())?
+    };
+    let (month, day) = (num(4, 2)?, num(6, 2)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(num(0, 4)? as _, month, day);
+    if v.len() == 8 {
+        return Some((days * 86400, true, false));
+    }
+    if v.as_bytes().get(8) != Some(&b'T') {
+        return None;
+    }
+    let (hour, minute, second) = (num(9, 2)?, num(11, 2)?, num(13, 2)?);
+    if hour > 23 || minute > 59 || second > 60 {
+        return None; // the 60 is leap second
+    }
+    let secs = hour * 3600 + minute * 60 + second;
+    let is_utc = match &v[15..] {
+        "" => false,
+        "Z" => true,
+        _ => return None,
+    };
+    Some((days * 86400 + secs as i64, false, is_utc))
+}
+
+/// Seconds of `P1W`, `-PT15M` or `P1DT2H`.
+fn parse_duration(v: &str) -> Option<i64> {
+    let (sign, v) = match v.strip_prefix('-') {
+        Some(v) => (-1, v),
+        None => (1, v.trim_start_matches('+')),
+    };
+    let (mut secs, mut n) = (0i64, 0i64);
+    for c in v.strip_prefix('P')?.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                n = n
+                    .checked_mul(10)?
+                    .checked_add(c.to_digit(10).unwrap() as _)?;
+                continue;
+            }
+            'T' => continue,
+            'W' => 7 * 86400,
+            'D' => 86400,
+            'H' => 3600,
+            'M' => 60,
+            'S' => 1,
+            _ => return None,
+        };
+        secs = secs.checked_add(std::mem::take(&mut n).checked_mul(unit)?)?;
+    }
+    Some(sign * secs)
+}
+
+/// RFC 4791 9.9, the times without zone are compared loosely as the zone is unknown here.
+fn time_range(filter: &Element, comp: &Component) -> bool {
+    let bound = |name| filter.attr(name).and_then(parse_time).map(|v| v.0);
+    let (start, end) = (bound("start"), bound("end"));
+    let (start, end) = (start.unwrap_or(i64::MIN), end.unwrap_or(i64::MAX));
+    if comp.prop("RRULE").is_some() || comp.prop("RDATE").is_some() {
+        return true;
+    }
+    let time = |name| comp.prop(name).and_then(parse_time);
+    let duration = comp.prop("DURATION").and_then(parse_duration);
+    let (from, to) = match &comp.name[..] {
+        "VEVENT" | "VJOURNAL" => {
+            let Some(from) = time("DTSTART") else {
+                return true;
+            };
+            let to = match (time("DTEND"), duration) {
+                (Some(to), _) => to,
+                (None, Some(v)) => (from.0.saturating_add(v), from.1, from.2),
+                (None, None) if from.1 => (from.0 + 86400, true, false),
+                (None, None) => from,
+            };
+            (from, to)
+        }
+        "VTODO" => match (time("DTSTART"), time("DUE")) {
+            (Some(from), Some(to)) => (from, to),
+            (Some(from), None) => {
+                let to = from.0.saturating_add(duration.unwrap_or(0));
+                (from, (to, from.1, from.2))
+            }
+            (None, Some(to)) => (to, to),
+            (None, None) => return true,
+        },
+        _ => return true,
+    };
+    let slack = match from.2 && to.2 {
+        true => 0,
+        false => 14 * 3600, // the widest zone offset
+    };
+    let (start, end) = (start.saturating_sub(slack), end.saturating_add(slack));
+    match from.0 == to.0 {
+        true => start <= from.0 && from.0 < end,
+        false => start < to.0 && from.0 < end,
+    }
+}
+
+/// RFC 4791 9.7.5 and RFC 6352 10.5.4, the collations are all taken as case insensitive except `i;octet`.
+fn text_match(filter: &Element, value: &str) -> bool {
+    let (mut value, mut needle) = (value.to_owned(), filter.text());
+    if filter.attr("collation") != Some("i;octet") {
+        (value, needle) = (value.to_lowercase(), needle.to_lowercase());
+    }
+    let matched = match filter.attr("match-type") {
+        Some("equals") => value == needle,
+        Some("starts-with") => value.starts_with(&needle),
+        Some("ends-with") => value.ends_with(&needle),
+        _ => value.contains(&needle),
+    };
+    matched != (filter.attr("negate-condition") == Some("yes"))
+}
+
+fn param_filter(filter: &Element, ns: &str, params: &str) -> bool {
+    let name = filter.attr("name").unwrap_or_default();
+    let mut values = params.split(';').filter_map(|v| {
+        let (k, v) = v.split_once('=')?;
+        k.eq_ignore_ascii_case(name).then(|| v.trim_matches('"'))
+    });
+    if filter.child(ns, "is-not-defined").is_some() {
+        return values.next().is_none();
+    }
+    match filter.child(ns, "text-match") {
+        Some(m) => values.any(|v| text_match(m, v)),
+        None => values.next().is_some(),
+    }
+}
+
+/// The CardDAV one has `test` to choose between `anyof` and `allof`, the CalDAV one is always `allof`.
+fn prop_filter(filter: &Element, ns: &str, comp: &Component) -> bool {
+    let name = filter.attr("name").unwrap_or_default();
+    let mut props = comp.props.iter().filter(|v| v.0.eq_ignore_ascii_case(name));
+    if filter.child(ns, "is-not-defined").is_some() {
+        return props.next().is_none();
+    }
+    let allof = ns == CALDAV || filter.attr("test") == Some("allof");
+    props.any(|(_, params, value)| {
+        let mut tests =
+            filter
+                .elements()
+                .filter(|v| v.ns == ns)
+                .filter_map(|v| match &v.name[..] {
+                    "text-match" => Some(text_match(v, value)),
+                    "param-filter" => Some(param_filter(v, ns, params)),
+                    _ => None,
+                });
+        match allof {
+            true => tests.all(|v| v),
+            false => {
+                let tests: Vec<bool> = tests.collect();
+                tests.is_empty() || tests.contains(&true)
+            }
+        }
+    })
+}
+
+/// RFC 4791 9.7.1, `comps` are the candidates of this level.
+fn comp_filter(filter: &Element, comps: &[Component]) -> bool {
+    let name = filter.attr("name").unwrap_or_default();
+    let mut found = comps.iter().filter(|v| v.name.eq_ignore_ascii_case(name));
+    if filter.child(CALDAV, "is-not-defined").is_some() {
+        return found.next().is_none();
+    }
+    found.any(|comp| {
+        filter.elements().all(|v| match (&v.ns[..], &v.name[..]) {
+            (CALDAV, "time-range") => time_range(v, comp),
+            (CALDAV, "prop-filter") => prop_filter(v, CALDAV, comp),
+            (CALDAV, "comp-filter") => comp_filter(v, &comp.children),
+            _ => true,
+        })
+    })
+}
+
+/// The `filter` element of `calendar-query` or `addressbook-query`.
+fn filter_matches(filter: &Element, object: Component) -> bool {
+    if filter.ns == CALDAV {
+        let mut filters = filter.elements().filter(|v| v.is(CALDAV, "comp-filter"));
+        let objects = [object];
+        return filters.all(|v| comp_filter(v, &objects));
+    }
+    let filters = filter.elements().filter(|v| v.is(CARDDAV, "prop-filter"));
+    let mut tests = filters.map(|v| prop_filter(v, CARDDAV, &object));
+    match filter.attr("test") == Some("allof") {
+        true => tests.all(|v| v),
+        false => {
+            let tests: Vec<bool> = tests.collect();
+            tests.is_empty() || tests.contains(&true)
+        }
+    }
+}
+
+/// `403 Forbidden` with the failed precondition.
+pub fn error(ns: &str, name: &str) -> Response {
+    let mut o = String::new();
+    o += r#"<?xml version="1.0" encoding="utf-8" ?><D:error xmlns:D="DAV:">"#;
+    props::write_prop(&mut o, ns, name, None);
+    o += "</D:error>";
+    let headers = [(CONTENT_TYPE, "application/xml; charset=utf-8")];
+    (StatusCode::FORBIDDEN, headers, o).into_response()
+}
+
+/// Check the body put into a calendar or address book, returns the error response if rejected.
+pub fn check_object(parent_flag: u64, body: &[u8]) -> Option<Response> {
+    let (ns, name, root) = match parent_flag & db::ENTRY_CALENDAR {
+        0 => (CARDDAV, "valid-address-data", "VCARD"),
+        _ => (CALDAV, "valid-calendar-data", "VCALENDAR"),
+    };
+    let object = std::str::from_utf8(body).ok().and_then(parse);
+    let valid = object.is_some_and(|v| {
+        let comps = v
+            .children
+            .iter()
+            .filter(|v| COMPONENTS.contains(&&v.name[..]));
+        v.name == root && (root == "VCARD" || comps.count() != 0)
+    });
+    (!valid).then(|| error(ns, name))
+}
+
+/// `(ns, name, value)` to set, like `db::set_props`.
+type PropOps = Vec<(String, String, Option<String>)>;
+
+/// The flag and dead properties for `MKCALENDAR` or the extended `MKCOL` (RFC 5689), `None` if the body is bad.
+///
+/// The properties in `DAV:` like `displayname` are ignored, as they are live here.
+pub fn parse_mkcol(is_calendar: bool, body: &str) -> Option<(u64, PropOps)> {
+    let mut flag = match is_calendar {
+        true => db::ENTRY_DIR | db::ENTRY_CALENDAR,
+        false => db::ENTRY_DIR,
+    };
+    let mut ops = Vec::new();
+    if body.trim().is_empty() {
+        return Some((flag, ops));
+    }
+    let root = xml::parse(body).ok()?;
+    match is_calendar {
+        true if root.is(CALDAV, "mkcalendar") => {}
+        false if root.is(DAV, "mkcol") => {}
+        _ => return None,
+    }
+    let sets = root.elements().filter(|v| v.is(DAV, "set"));
+    let props = sets.flat_map(|v| v.elements().filter(|v| v.is(DAV, "prop")));
+    for prop in props.flat_map(|v| v.elements()) {
+        if prop.is(DAV, "resourcetype") {
+            for v in prop.elements() {
+                match (&v.ns[..], &v.name[..]) {
+                    (DAV, "collection") => {}
+                    (CALDAV, "calendar") => flag |= db::ENTRY_CALENDAR,
+                    (CARDDAV, "addressbook") => flag |= db::ENTRY_ADDRESSBOOK,
+                    _ => return None,
+                }
+            }
+        } else if prop.ns != DAV && !props::is_live(&prop.ns, &prop.name) {
+            let mut value = String::new();
+            prop.write_inner(&mut value);
+            ops.push((prop.ns.clone(), prop.name.clone(), Some(value)));
+        }
+    }
+    if flag & db::ENTRY_CALENDAR != 0 && flag & db::ENTRY_ADDRESSBOOK != 0 {
+        return None;
+    }
+    Some((flag, ops))
+}
+
+/// The object text, `None` if unreadable or too large.
+async fn load(row: &(String, u64, u64, u64, u64), key: Option<&Key>) -> Option<String> {
+    if row.3 & (db::ENTRY_DIR | db::ENTRY_HREF | db::ENTRY_GZIP) != 0
+        || row.2 > MAX_OBJECT_SIZE as u64
+    {
+        return None;
+    }
+    let mut o = Vec::new();
+    for hash in db::list_blocks(row.0.to_owned()).await {
+        let chunk = db::get_chunk(hash).await?;
+        match key {
+            Some(key) => o.extend(crypt::open(key, &chunk)?),
+            None => o.extend(chunk),
+        }
+    }
+    String::from_utf8(o).ok()
+}
+
+fn wants_content(find: &Find) -> bool {
+    match find {
+        Find::Props(v) => v.iter().any(|v| {
+            (v.0 == CALDAV && v.1 == "calendar-data") || (v.0 == CARDDAV && v.1 == "address-data")
+        }),
+        _ => false,
+    }
+}
+
+fn write_not_found(o: &mut String, href: &str) {
+    *o += "<D:response><D:href>";
+    xml::escape(o, href);
+    *o += "</D:href><D:status>HTTP/1.1 404 Not Found</D:status></D:response>";
+}
+
+/// The queries, multigets and `sync-collection`. The `depth` is only for the queries.
+pub async fn report(
+    prefix: &str,
+    eid: String,
+    depth: u64,
+    body: &str,
+    key: Option<Key>,
+) -> anyhow::Result<Response> {
+    let Ok(root) = xml::parse(body) else {
+        return Ok(props::bad_request());
+    };
+    let (_, _, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
+    let find = match root.child(DAV, "prop") {
+        Some(v) => Find::Props(props::names(v)),
+        None if root.child(DAV, "propname").is_some() => Find::Names,
+        None => Find::All(Vec::new()),
+    };
+    let (uid, _) = eid.split_once(':').unwrap();
+    let ctx = Ctx::new(prefix, uid).await;
+    let mut o = String::new();
+    o += props::MULTISTATUS_START;
+    match (&root.ns[..], &root.name[..]) {
+        (CALDAV, "calendar-query") | (CARDDAV, "addressbook-query") => {
+            let filter = root.child(&root.ns, "filter");
+            let limit = root.child(CARDDAV, "limit");
+            let limit = limit.and_then(|v| v.child(CARDDAV, "nresults"));
+            let limit = limit.and_then(|v| v.text().trim().parse().ok());
+            let mut count = 0;
+            for row in db::list_entry_tree(eid.to_owned(), depth).await {
+                let Some(content) = load(&row, key.as_ref()).await else {
+                    continue;
+                };
+                let Some(object) = parse(&content) else {
+                    continue;
+                };
+                if filter.is_some_and(|v| !filter_matches(v, object)) {
+                    continue;
+                }
+                if Some(count) == limit {
+                    break;
+                }
+                count += 1;
+                props::write_response(&mut o, &ctx, row, &find, Some(&content)).await;
+            }
+        }
+        (CALDAV, "calendar-multiget") | (CARDDAV, "addressbook-multiget") => {
+            for href in root.elements().filter(|v| v.is(DAV, "href")) {
+                let href = href.text();
+                let href = href.trim();
+                let pathname = path::strip_url(prefix, href.as_bytes()).and_then(path::decode);
+                let row = match pathname {
+                    Some(v) => db::list_entry_tree(uid.to_owned() + ":" + &v, 0)
+                        .await
+                        .pop(),
+                    None => None,
+                };
+                let Some(row) = row.filter(|v| v.3 & db::ENTRY_DIR == 0) else {
+                    write_not_found(&mut o, href);
+                    continue;
+                };
+                let content = load(&row, key.as_ref()).await;
+                props::write_response(&mut o, &ctx, row, &find, content.as_deref()).await;
+            }
+        }
+        (DAV, "sync-collection") => {
+            if flag & (db::ENTRY_CALENDAR | db::ENTRY_ADDRESSBOOK) == 0 {
+                return Ok(error(DAV, "supported-report"));
+            }
+            // the sync-level makes no difference, as the collections have no collection inside
+            let current = db::get_sync_token(eid.to_owned()).await;
+            let token = root.child(DAV, "sync-token").map(|v| v.text());
+            let changes = match token.as_deref().map(str::trim).unwrap_or_default() {
+                "" => {
+                    let tree = db::list_entry_tree(eid.to_owned(), 1).await;
+                    tree.into_iter().skip(1).map(|v| (v.0, false)).collect()
+                }
+                token => {
+                    let since = token.strip_prefix(SYNC_TOKEN_PREFIX);
+                    match since.and_then(|v| v.parse().ok()) {
+                        Some(since) if since <= current => db::list_changes(eid, since).await,
+                        _ => return Ok(error(DAV, "valid-sync-token")),
+                    }
+                }
+            };
+            for (eid, deleted) in changes {
+                let row = match deleted {
+                    true => None,
+                    false => db::list_entry_tree(eid.to_owned(), 0).await.pop(),
+                };
+                let Some(row) = row else {
+                    let mut href = prefix.to_owned();
+                    path::encode(&mut href, eid.split_once(':').unwrap().1);
+                    write_not_found(&mut o, &href);
+                    continue;
+                };
+                let content = match wants_content(&find) {
+                    true => load(&row, key.as_ref()).await,
+                    false => None,
+                };
+                props::write_response(&mut o, &ctx, row, &find, content.as_deref()).await;
+            }
+            o += "<D:sync-token>";
+            xml::escape(&mut o, &sync_token(current));
+            o += "</D:sync-token>";
+        }
+        _ => return Ok(error(DAV, "supported-report")),
+    }
+    o += "</D:multistatus>";
+    Ok(props::multistatus(o))
+}
@@ -0,0 +1,167 @@
+//! Encryption at rest, optional per user.
+//!
+//! Each encrypted user has a random data key, stored wrapped by the key derived from both the password
+//! (each app password has its own copy) and the server master key. The master key is a file beside the
+//! executable, out of the database and its backups. The chunks are sealed deterministically, the nonce is
+//! the HMAC of the plaintext, so the same content of one user still dedups while nothing is shared across users.
+//!
+//! Only the file content is encrypted. The uids, paths, sizes, times, flags, dead properties, locks, versions and
+//! trash records stay visible, as well as which chunks of the same user are equal.
+
+use super::{db, user};
+use crate::chacha20::chacha20;
+use crate::sha256::{sha256, Hmac};
+use crate::utils::{bytes_eq, LazyLock};
+use std::collections::HashMap;
+use std::io::Write as _;
+
+/// Created on first use, lost key means lost content.
+static MASTER_KEY: LazyLock<[u8; 32]> = LazyLock::new(|| {
+    let path = std::env::current_exe().unwrap().with_extension("dav.key");
+    if let Ok(v) = std::fs::read(&path) {
+        return v.try_into().expect("invalid dav master key file");
+    }
+    let key: [u8; 32] = rand::random();
+    let mut options = std::fs::OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.write(true).create_new(true).open(&path).unwrap();
+    file.write_all(&key).unwrap();
+    key
+});
+
+const TAG_LEN: usize = 32;
+
+#[derive(Clone)]
+pub struct Key {
+    enc: [u8; 32],
+    mac: Hmac,
+}
+
+impl Key {
+    pub fn new(data_key: &[u8; 32]) -> Self {
+        let derive = |label: &[u8]| {
+            let mut h = Hmac::new(data_key);
+            h.update(label);
+            h.finish()
+        };
+        Self {
+            enc: derive(b"enc"),
+            mac: Hmac::new(&derive(b"mac")),
+        }
+    }
+}
+
+/// Returns `tag + ciphertext`, the tag is the HMAC of plaintext and the nonce is taken from it.
+pub fn seal(key: &Key, data: &[u8]) -> Vec<u8> {
+    let mut h = key.mac.clone();
+    h.update(data);
+    let tag = h.finish();
+    let mut o = Vec::with_capacity(TAG_LEN + data.len());
+    o.extend_from_slice(&tag);
+    o.extend_from_slice(data);
+    chacha20(
+        &key.enc,
+        tag[..12].try_into().unwrap(),
+        1,
+        &mut o[TAG_LEN..],
+    );
+    o
+}
+
+/// Returns `None` if it was modified or sealed by another key.
+pub fn open(key: &Key, data: &[u8]) -> Option<Vec<u8>> {
+    let (tag, data) = (data.get(..TAG_LEN)?, &data[TAG_LEN..]);
+    let mut o = data.to_vec();
+    chacha20(&key.enc, tag[..12].try_into().unwrap(), 1, &mut o);
+    let mut h = key.mac.clone();
+    h.update(&o);
+    bytes_eq(&h.finish(), tag).then_some(o)
+}
+
+fn wrapping_key(password: &str, salt: &[u8]) -> Key {
+    let mut h = Hmac::new(&*MASTER_KEY);
+    h.update(&crate::sha256::pbkdf2(
+        password.as_bytes(),
+        salt,
+        user::ROUNDS,
+    ));
+    Key::new(&h.finish())
+}
+
+/// Returns `salt + sealed data key`, slow as the password hashing.
+pub fn wrap(password: &str, data_key: &[u8; 32]) -> Vec<u8> {
+    let salt: [u8; 16] = rand::random();
+    [&salt[..], &seal(&wrapping_key(password, &salt), data_key)].concat()
+}
+
+pub fn unwrap(password: &str, wrapped: &[u8]) -> Option<[u8; 32]> {
+    let (salt, sealed) = (wrapped.get(..16)?, &wrapped[16..]);
+    open(&wrapping_key(password, salt), sealed)?.try_into().ok()
+}
+
+/// The chunks converted in each batch, the db is free between the batches.
+const BATCH: usize = 16;
+
+/// Rewrite each chunk of the user by `convert` batch by batch, then switch to them with the wrapped data key.
+///
+/// Returns false if any chunk failed to convert. The old chunks are left to gc.
+async fn convert_chunks(
+    uid: String,
+    enc: Option<Vec<u8>>,
+    convert: impl Fn(&[u8]) -> Option<Vec<u8>> + Clone + Send + 'static,
+) -> bool {
+    let mut converted = HashMap::new();
+    for batch in db::list_user_chunks(uid.to_owned()).await.chunks(BATCH) {
+        let mut list = Vec::new();
+        for &hash in batch {
+            list.push((hash, db::get_chunk(hash).await));
+        }
+        let convert = convert.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let list = list.into_iter();
+            list.map(|(hash, v)| Some((hash, convert(&v?)?)))
+                .collect::<Option<Vec<_>>>()
+        });
+        let Some(list) = task.await.unwrap() else {
+            return false;
+        };
+        for (hash, data) in list {
+            let new_hash = sha256(&data);
+            db::put_chunk(new_hash, data).await;
+            converted.insert(hash, new_hash);
+        }
+    }
+    db::switch_chunks(uid, enc, converted, convert).await
+}
+
+/// Encrypt all content of the user, the app passwords and shares are removed as they can't reach the key.
+pub async fn enable(uid: String, password: String) -> anyhow::Result<()> {
+    if db::get_user_enc(uid.to_owned()).await.is_some() {
+        return Err(anyhow::anyhow!("encrypted already"));
+    }
+    let data_key: [u8; 32] = rand::random();
+    let wrapped = tokio::task::spawn_blocking(move || wrap(&password, &data_key));
+    let wrapped = wrapped.await.unwrap();
+    let key = Key::new(&data_key);
+    let convert = move |v: &[u8]| Some(seal(&key, v));
+    if !convert_chunks(uid, Some(wrapped), convert).await {
+        return Err(anyhow::anyhow!("failed to encrypt"));
+    }
+    user::forget();
+    Ok(())
+}
+
+/// Decrypt all content of the user back.
+pub async fn disable(uid: String, data_key: Option<[u8; 32]>) -> anyhow::Result<()> {
+    let Some(data_key) = data_key else {
+        return Err(anyhow::anyhow!("not encrypted"));
+    };
+    let key = Key::new(&data_key);
+    let convert = move |v: &[u8]| open(&key, v);
+    if !convert_chunks(uid, None, convert).await {
+        return Err(anyhow::anyhow!("chunk corrupted"));
+    }
+    user::forget();
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! File versions and the trash. Overwritten content and deleted trees are kept for a while, to be restored from the page.
+
+use super::{config, db, lock, path, props, DAV_PATH_PREFIX};
+use crate::log;
+use crate::utils::OptionResult;
+use axum::http::HeaderMap;
+use serde_json::json;
+use std::time::UNIX_EPOCH;
+
+/// The count of versions kept for each file, `0` to disable.
+pub async fn max_versions() -> u64 {
+    config("dav_versions", 10).await
+}
+
+/// The days to keep the trashed trees, `0` to delete directly.
+pub async fn trash_days() -> u64 {
+    config("dav_trash_days", 30).await
+}
+
+/// The versions of the file as json, for the page.
+pub async fn list_versions(eid: String) -> String {
+    let list = db::list_versions(eid).await.into_iter();
+    let list = list.map(|(vid, time, size)| json!({ "ver": vid, "time": time, "size": size }));
+    serde_json::Value::Array(list.collect()).to_string()
+}
+
+pub async fn restore_version(eid: String, vid: u64) -> anyhow::Result<()> {
+    let (_, size, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
+    if flag & db::ENTRY_READ_ONLY != 0 {
+        return Err(anyhow::anyhow!("read only"));
+    }
+    if lock::check(DAV_PATH_PREFIX, &HeaderMap::new(), &eid, &[(&eid, false)])
+        .await
+        .is_some()
+    {
+        return Err(anyhow::anyhow!("locked"));
+    }
+    let versions = db::list_versions(eid.to_owned()).await;
+    let (_, _, ver_size) = versions.into_iter().find(|v| v.0 == vid).e()?;
+    let (uid, _) = eid.split_once(':').unwrap();
+    let used = db::get_usage(uid.to_owned()).await;
+    if used.saturating_sub(size) + ver_size > props::quota(uid).await {
+        return Err(anyhow::anyhow!("insufficient storage"));
+    }
+    let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+    if !db::restore_version(eid, vid, time, max_versions().await).await {
+        return Err(anyhow::anyhow!("not found"));
+    }
+    Ok(())
+}
+
+/// The trashed trees of the user as json, for the page.
+pub async fn list_trash(uid: String) -> String {
+    let mut o = Vec::new();
+    for (tid, eid, time) in db::list_trash(uid.to_owned()).await {
+        let tree = db::list_entry_tree(db::trash_eid(&uid, tid), u32::MAX as _).await;
+        let Some((_, _, _, flag, _)) = tree.first() else {
+            continue;
+        };
+        let mut pathname = String::new();
+        path::encode(&mut pathname, eid.split_once(':').unwrap().1);
+        o.push(json!({
+            "id": tid,
+            "path": pathname,
+            "dir": flag & db::ENTRY_DIR != 0,
+            "time": time,
+            "size": tree.iter().map(|v| v.2).sum::<u64>(),
+        }));
+    }
+    serde_json::Value::Array(o).to_string()
+}
+
+/// Move the trashed tree back, fails if the path is taken or the parent dir is gone.
+pub async fn restore_trash(uid: String, tid: u64) -> anyhow::Result<()> {
+    let list = db::list_trash(uid.to_owned()).await;
+    let (_, eid, _) = list.into_iter().find(|v| v.0 == tid).e()?;
+    let (parent, _) = eid.rsplit_once('/').e()?;
+    let targets = [(&eid[..], false), (parent, false)];
+    if lock::check(DAV_PATH_PREFIX, &HeaderMap::new(), &eid, &targets)
+        .await
+        .is_some()
+    {
+        return Err(anyhow::anyhow!("locked"));
+    }
+    if !db::restore_trash(uid, tid, eid).await {
+        return Err(anyhow::anyhow!(
+            "path is taken or parent is not writable dir"
+        ));
+    }
+    Ok(())
+}
+
+/// Purge the trashed trees older than the retention, run by the gc job.
+pub async fn expire() {
+    let retention = trash_days().await * 24 * 3600;
+    let before = UNIX_EPOCH
+        .elapsed()
+        .unwrap()
+        .as_secs()
+        .saturating_sub(retention);
+    let purged = db::purge_trash(db::list_expired_trash(before).await).await;
+    log!(info: "dav gc purged {purged} trashed trees");
+}
@@ -0,0 +1,330 @@
+//! Write locks of WebDAV class 2, and the `If` header. RFC 4918 section 6, 7 and 10.4
+
+use super::xml::{self, DAV};
+use super::{blob, db, path};
+use axum::http::{header::*, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::time::UNIX_EPOCH;
+
+/// The timeout when not requested, and the max one, in seconds.
+const DEFAULT_TIMEOUT: u64 = 60 * 10;
+const MAX_TIMEOUT: u64 = 60 * 60 * 24;
+
+pub const SUPPORTED_LOCK: &str = "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry><D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>";
+
+pub struct Lock {
+    /// Like `opaquelocktoken:f81d4fae-7dec-41d0-a765-00a0c91e6bf6`.
+    pub token: String,
+    pub eid: String,
+    pub exclusive: bool,
+    /// Depth infinity, or `0`.
+    pub deep: bool,
+    /// The inner xml of `owner` element.
+    pub owner: String,
+    /// Seconds since unix epoch.
+    pub expire: u64,
+}
+
+/// Whether `eid` is `dir` or inside it.
+pub fn is_within(eid: &str, dir: &str) -> bool {
+    eid == dir || eid.strip_prefix(dir).is_some_and(|v| v.starts_with('/'))
+}
+
+impl Lock {
+    pub fn covers(&self, eid: &str) -> bool {
+        self.eid == eid || (self.deep && is_within(eid, &self.eid))
+    }
+
+    pub fn write_active(&self, o: &mut String, prefix: &str) {
+        let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+        *o += "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>";
+        *o += if self.exclusive {
+            "<D:exclusive/>"
+        } else {
+            "<D:shared/>"
+        };
+        *o += "</D:lockscope><D:depth>";
+        *o += if self.deep { "infinity" } else { "0" };
+        *o += "</D:depth><D:owner>";
+        *o += &self.owner;
+        *o += "</D:owner><D:timeout>Second-";
+        *o += &self.expire.saturating_sub(now).to_string();
+        *o += "</D:timeout><D:locktoken><D:href>";
+        xml::escape(o, &self.token);
+        *o += "</D:href></D:locktoken><D:lockroot><D:href>";
+        *o += prefix;
+        path::encode(o, self.eid.split_once(':').unwrap().1);
+        *o += "</D:href></D:lockroot></D:activelock>";
+    }
+}
+
+fn new_token() -> String {
+    let v: u128 = rand::random();
+    let (a, b, c) = (v >> 96, (v >> 80) & 0xffff, (v >> 68) & 0xfff);
+    let (d, e) = ((v >> 52) & 0x3fff | 0x8000, v & 0xffff_ffff_ffff);
+    format!("opaquelocktoken:{a:08x}-{b:04x}-4{c:03x}-{d:04x}-{e:012x}")
+}
+
+fn eid_of(prefix: &str, uid: &str, url: &str) -> Option<String> {
+    let pathname = path::decode(path::strip_url(prefix, url.as_bytes())?)?;
+    Some(uid.to_owned() + ":" + &pathname)
+}
+
+enum Cond {
+    Token(String),
+    ETag(String),
+}
+
+/// Lists of `(not, condition)`, with the resource url if tagged.
+type IfLists = Vec<(Option<String>, Vec<(bool, Cond)>)>;
+
+fn parse_if(v: &str) -> Option<IfLists> {
+    let mut lists = Vec::new();
+    let mut tag = None;
+    let mut s = v.trim_start();
+    while !s.is_empty() {
+        if let Some(v) = s.strip_prefix('<') {
+            let (url, rest) = v.split_once('>')?;
+            tag = Some(url.to_owned());
+            s = rest;
+        } else if let Some(v) = s.strip_prefix('(') {
+            let (list, rest) = v.split_once(')')?;
+            let mut conds = Vec::new();
+            let mut l = list.trim_start();
+            while !l.is_empty() {
+                let not = match l.strip_prefix("Not") {
+                    Some(v) => {
+                        l = v.trim_start();
+                        true
+                    }
+                    None => false,
+                };
+                let (cond, rest) = if let Some(v) = l.strip_prefix('<') {
+                    let (token, rest) = v.split_once('>')?;
+                    (Cond::Token(token.to_owned()), rest)
+                } else if let Some(v) = l.strip_prefix('[') {
+                    let (etag, rest) = v.split_once(']')?;
+                    (Cond::ETag(etag.to_owned()), rest)
+                } else {
+                    return None;
+                };
+                conds.push((not, cond));
+                l = rest.trim_start();
+            }
+            lists.push((tag.clone(), conds));
+            s = rest;
+        } else {
+            return None;
+        }
+        s = s.trim_start();
+    }
+    Some(lists)
+}
+
+/// Evaluate the `If` header, returns the submitted lock tokens, or `None` if the precondition failed.
+async fn eval_if(
+    prefix: &str,
+    eid: &str,
+    headers: &HeaderMap,
+    locks: &[Lock],
+) -> Option<Vec<String>> {
+    let Some(v) = headers.get("if") else {
+        return Some(Vec::new());
+    };
+    let lists = parse_if(std::str::from_utf8(v.as_bytes()).ok()?)?; // may be raw utf-8
+    let uid = eid.split_once(':').unwrap().0;
+    let mut tokens = Vec::new();
+    let mut passed = false;
+    for (tag, conds) in &lists {
+        let eid = match tag {
+            Some(url) => eid_of(prefix, uid, url),
+            None => Some(eid.to_owned()),
+        };
+        let meta = match &eid {
+            Some(eid) => db::get_entry_meta(eid.to_owned()).await,
+            None => None,
+        };
+        let cur_etag = match (&eid, meta) {
+            (Some(eid), Some((_, _, flag))) if flag & db::ENTRY_DIR == 0 => {
+                Some(blob::etag(&db::list_blocks(eid.to_owned()).await))
+            }
+            _ => None,
+        };
+        let mut ok = true;
+        for (not, cond) in conds {
+            let v = match cond {
+                Cond::Token(token) => {
+                    tokens.push(token.to_owned());
+                    let lock = locks.iter().find(|v| &v.token == token);
+                    lock.zip(eid.as_ref()).is_some_and(|(l, eid)| l.covers(eid))
+                }
+                Cond::ETag(etag) => cur_etag.as_deref() == Some(etag.trim_start_matches("W/")),
+            };
+            ok &= v != *not;
+        }
+        passed |= ok;
+    }
+    passed.then_some(tokens)
+}
+
+fn locked_response(prefix: &str, lock: &Lock) -> Response {
+    let mut o = String::new();
+    o += r#"<?xml version="1.0" encoding="utf-8" ?><D:error xmlns:D="DAV:"><D:lock-token-submitted><D:href>"#;
+    o += prefix;
+    path::encode(&mut o, lock.eid.split_once(':').unwrap().1);
+    o += "</D:href></D:lock-token-submitted></D:error>";
+    let headers = [(CONTENT_TYPE, "application/xml; charset=utf-8")];
+    (StatusCode::LOCKED, headers, o).into_response()
+}
+
+/// Check the locks before writing, returns the error response if not allowed.
+///
+/// Each target is `(eid, deep)`, the `deep` means the children are also modified, like `DELETE` a dir.
+pub async fn check(
+    prefix: &str,
+    headers: &HeaderMap,
+    eid: &str,
+    targets: &[(&str, bool)],
+) -> Option<Response> {
+    let uid = eid.split_once(':').unwrap().0;
+    let locks = db::list_locks(uid.to_owned()).await;
+    let Some(tokens) = eval_if(prefix, eid, headers, &locks).await else {
+        return Some(StatusCode::PRECONDITION_FAILED.into_response());
+    };
+    for lock in locks.iter().filter(|v| !tokens.contains(&v.token)) {
+        for &(target, deep) in targets {
+            if lock.covers(target) || (deep && is_within(&lock.eid, target)) {
+                return Some(locked_response(prefix, lock));
+            }
+        }
+    }
+    None
+}
+
+fn parse_timeout(headers: &HeaderMap) -> u64 {
+    let v = headers.get("timeout").and_then(|v| v.to_str().ok());
+    let timeout = v.and_then(|v| {
+        v.split(',').map(str::trim).find_map(|v| match v {
+            "Infinite" => Some(MAX_TIMEOUT),
+            v => v.strip_prefix("Second-")?.parse().ok(),
+        })
+    });
+    timeout.unwrap_or(DEFAULT_TIMEOUT).min(MAX_TIMEOUT)
+}
+
+fn lock_response(prefix: &str, status: StatusCode, lock: &Lock) -> Response {
+    let mut o = String::new();
+    o += r#"<?xml version="1.0" encoding="utf-8" ?><D:prop xmlns:D="DAV:"><D:lockdiscovery>"#;
+    lock.write_active(&mut o, prefix);
+    o += "</D:lockdiscovery></D:prop>";
+    let token = format!("<{}>", lock.token);
+    let headers = [
+        (CONTENT_TYPE, "application/xml; charset=utf-8".to_owned()),
+        (HeaderName::from_static("lock-token"), token),
+    ];
+    (status, headers, o).into_response()
+}
+
+/// Create a lock, or refresh if the body is empty. Locking a missing entry creates an empty file.
+pub async fn lock(
+    prefix: &str,
+    eid: String,
+    headers: &HeaderMap,
+    body: &str,
+) -> anyhow::Result<Response> {
+    let uid = eid.split_once(':').unwrap().0;
+    let expire = UNIX_EPOCH.elapsed().unwrap().as_secs() + parse_timeout(headers);
+    let locks = db::list_locks(uid.to_owned()).await;
+    if body.trim().is_empty() {
+        let tokens = eval_if(prefix, &eid, headers, &locks)
+            .await
+            .unwrap_or_default();
+        let found = locks
+            .into_iter()
+            .find(|v| tokens.contains(&v.token) && v.covers(&eid));
+        let Some(mut lock) = found else {
+            return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+        };
+        lock.expire = expire;
+        db::set_lock(&lock).await;
+        return Ok(lock_response(prefix, StatusCode::OK, &lock));
+    }
+    let Ok(root) = xml::parse(body) else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    let scope = root
+        .child(DAV, "lockscope")
+        .and_then(|v| v.elements().next());
+    let exclusive = match scope {
+        Some(v) if v.is(DAV, "exclusive") => true,
+        Some(v) if v.is(DAV, "shared") => false,
+        _ => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    };
+    let locktype = root
+        .child(DAV, "locktype")
+        .and_then(|v| v.elements().next());
+    if !root.is(DAV, "lockinfo") || !locktype.is_some_and(|v| v.is(DAV, "write")) {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    }
+    let deep = match headers.get("depth").map(|v| v.as_bytes()) {
+        Some(b"0") => false,
+        Some(b"infinity") | None => true,
+        _ => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    };
+    let mut owner = String::new();
+    if let Some(v) = root.child(DAV, "owner") {
+        v.write_inner(&mut owner);
+    }
+    let conflict = locks.iter().find(|v| {
+        (v.covers(&eid) || (deep && is_within(&v.eid, &eid))) && (v.exclusive || exclusive)
+    });
+    if let Some(v) = conflict {
+        return Ok(locked_response(prefix, v));
+    }
+    let mut status = StatusCode::OK;
+    if db::get_entry_meta(eid.to_owned()).await.is_none() {
+        let (parent, _) = eid
+            .rsplit_once('/')
+            .ok_or_else(|| anyhow::anyhow!("no parent"))?;
+        match db::get_entry_meta(parent.to_owned()).await {
+            Some((_, _, flag)) if flag & db::ENTRY_DIR != 0 => {}
+            _ => return Ok(StatusCode::CONFLICT.into_response()),
+        }
+        if let Some(res) = check(prefix, headers, &eid, &[(parent, false)]).await {
+            return Ok(res);
+        }
+        let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+        db::set_entry(eid.to_owned(), Default::default(), time, 0, 0).await;
+        status = StatusCode::CREATED;
+    }
+    let lock = Lock {
+        token: new_token(),
+        eid,
+        exclusive,
+        deep,
+        owner,
+        expire,
+    };
+    db::set_lock(&lock).await;
+    Ok(lock_response(prefix, status, &lock))
+}
+
+pub async fn unlock(eid: String, headers: &HeaderMap) -> anyhow::Result<Response> {
+    let uid = eid.split_once(':').unwrap().0;
+    let v = headers
+        .get("lock-token")
+        .ok_or_else(|| anyhow::anyhow!("no token"))?;
+    let token = v
+        .to_str()?
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    let locks = db::list_locks(uid.to_owned()).await;
+    match locks.iter().find(|v| v.token == token && v.covers(&eid)) {
+        Some(_) => {
+            db::del_lock(token.to_owned()).await;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        None => Ok(StatusCode::CONFLICT.into_response()),
+    }
+}
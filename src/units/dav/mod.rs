@@ -1,16 +1,41 @@
 //! WebDAV. The goal is fast and short, not to implement full RFC4918 + RFC2518.
 
+use crate::assets::Asset;
 use crate::database::DB;
-use crate::utils::{escape_check_html, OptionResult};
-use crate::{care, include_src, strip_str};
+use crate::scheduler::{CatchUp, Job};
+use crate::utils::{escape_check_html, LazyLock, OptionResult};
+use crate::{asset, care, crons, log, strip_str, template};
 use axum::body::{Body, Bytes};
 use axum::extract::{Path, Request};
 use axum::handler::Handler;
-use axum::http::{header::*, StatusCode, Uri};
+use axum::http::{header::*, StatusCode};
 use axum::response::{Html, IntoResponse, Response};
 use axum::routing::{MethodRouter, Router};
+use std::collections::HashMap;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+mod archive;
+mod blob;
+mod caldav;
+mod crypt;
+mod history;
+mod lock;
+mod path;
+mod props;
+mod serve;
+mod share;
+mod upload;
+mod user;
+mod xml;
+
+pub use user::{del_user, set_user, set_user_quota};
+
+async fn config(key: &str, default: u64) -> u64 {
+    let v = crate::units::admin::db::get(key.to_owned()).await;
+    let v = v.and_then(|v| std::str::from_utf8(&v).ok()?.trim().parse().ok());
+    v.unwrap_or(default)
+}
+
 mod db {
     use super::*;
     pub const ENTRY_DIR: u64 = 0b_0000_0000_0000_0001;
@@ -18,100 +43,1123 @@ mod db {
     pub const ENTRY_HREF: u64 = 0b_0000_0000_0000_1000;
     pub const ENTRY_GZIP: u64 = 0b_0000_0000_0001_0000;
     pub const ENTRY_STABLE: u64 = 0b_0000_0000_0100_0000;
+    pub const ENTRY_CALENDAR: u64 = 0b_0000_0001_0000_0000;
+    pub const ENTRY_ADDRESSBOOK: u64 = 0b_0000_0010_0000_0000;
     pub async fn init() {
         DB.call(|db| {
             // db.execute("DROP TABLE IF EXISTS dav_users", ()).unwrap();
             // db.execute("DROP TABLE IF EXISTS dav_entries", ()).unwrap();
-            // dav_users: uid = "username", auth = "Basic dXNlcm5hbWU6cGFzc3dvcmQ="
+            // dav_users: uid = "username", auth = "pbkdf2-sha256$<rounds>$<salt hex>$<hash hex>", was "Basic dXNlcm5hbWU6cGFzc3dvcmQ=" in old versions, enc = "<wrapped data key>" or null if not encrypted, quota (bytes) or null for the `dav_quota` config
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_users (uid BLOB PRIMARY KEY, auth BLOB UNIQUE, enc BLOB, quota INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_entries: eid = "username:/dir/file", data = "<bin data or empty>", time (modified, seconds) = 1706298055, size (bytes) = 4096, flag = 0b0000, ctime (created, seconds)
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_entries (eid BLOB PRIMARY KEY, data BLOB, time INTEGER, size INTEGER, flag INTEGER, ctime INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            if db.prepare("SELECT ctime FROM dav_entries LIMIT 0").is_err() {
+                let sql = strip_str! {"
+                    ALTER TABLE dav_entries ADD COLUMN ctime INTEGER
+                "};
+                db.execute(sql, ()).unwrap(); // the old entries' ctime is null, fallback to time
+            }
+            // dav_locks: token = "opaquelocktoken:<uuid>", eid = "username:/dir", exclusive = 1, deep = 1, owner = "<inner xml>", expire (seconds) = 1706298055
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_locks (token BLOB PRIMARY KEY, eid BLOB, exclusive INTEGER, deep INTEGER, owner BLOB, expire INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_props: dead properties set by PROPPATCH, value = "<inner xml>"
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_props (eid BLOB, ns BLOB, name BLOB, value BLOB, PRIMARY KEY (eid, ns, name))
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_chunks: hash = sha256(data), data = "<at most blob::CHUNK_SIZE>", time (last referenced, seconds) = 1706298055
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_chunks (hash BLOB PRIMARY KEY, data BLOB, time INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_blocks: the content of file entry is the chunks ordered by idx
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_blocks (eid BLOB, idx INTEGER, hash BLOB, PRIMARY KEY (eid, idx))
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            let sql = strip_str! {"
+                CREATE INDEX IF NOT EXISTS dav_blocks_hash ON dav_blocks (hash)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_app_passwords: name = "phone", auth and enc = the same as dav_users
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_app_passwords (uid BLOB, name BLOB, auth BLOB, enc BLOB, PRIMARY KEY (uid, name))
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // the old tables have no enc, which means not encrypted
+            for table in ["dav_users", "dav_app_passwords"] {
+                if db.prepare(&format!("SELECT enc FROM {table} LIMIT 0")).is_err() {
+                    let sql = format!("ALTER TABLE {table} ADD COLUMN enc BLOB");
+                    db.execute(&sql, ()).unwrap();
+                }
+            }
+            if db.prepare("SELECT quota FROM dav_users LIMIT 0").is_err() {
+                let sql = strip_str! {"
+                    ALTER TABLE dav_users ADD COLUMN quota INTEGER
+                "};
+                db.execute(sql, ()).unwrap();
+            }
+            // dav_shares: token = "<32 hex>", scope = 0 (read) or 1 (upload), auth = the same as dav_users or empty, expire (seconds, 0 for never), remain (downloads, null for unlimited)
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_shares (token BLOB PRIMARY KEY, eid BLOB, scope INTEGER, auth BLOB, expire INTEGER, remain INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_versions: vid = 1, eid = "username:/dir/file", time and size of the content before overwritten
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_versions (vid INTEGER PRIMARY KEY, eid BLOB, time INTEGER, size INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            let sql = strip_str! {"
+                CREATE INDEX IF NOT EXISTS dav_versions_eid ON dav_versions (eid)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_version_blocks: the same as dav_blocks, for the versions
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_version_blocks (vid INTEGER, idx INTEGER, hash BLOB, PRIMARY KEY (vid, idx))
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            let sql = strip_str! {"
+                CREATE INDEX IF NOT EXISTS dav_version_blocks_hash ON dav_version_blocks (hash)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_trash: tid = 1, eid = "username:/dir" (the original), time (deleted, seconds), the tree is moved to `trash_eid`
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_trash (tid INTEGER PRIMARY KEY, uid BLOB, eid BLOB, time INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_uploads: eid = "username:/file", id = "1048577-1700000000000" (by the page), idx = 0, hash, size, time, the received parts of the resumable uploads
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_uploads (eid BLOB, id BLOB, idx INTEGER, hash BLOB, size INTEGER, time INTEGER, PRIMARY KEY (eid, id, idx))
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // dav_changes: seq = 1, eid = "username:/calendar/event.ics", deleted = 0 or 1, the latest change of each object in calendars and address books, for the sync tokens
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_changes (seq INTEGER PRIMARY KEY AUTOINCREMENT, eid BLOB UNIQUE, deleted INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // recorded by triggers, so every path that modifies the entries is covered. the `rtrim` removes the last name to get the parent, and no `REPLACE` as the outer conflict clause overrides it
+            let parent = |v: &str| format!("CAST(rtrim(CAST({v} AS TEXT), replace(CAST({v} AS TEXT), '/', '')) AS BLOB)");
+            let is_object = |v: &str| {
+                let parent = parent(v);
+                let flag = ENTRY_CALENDAR | ENTRY_ADDRESSBOOK;
+                format!("(SELECT flag FROM dav_entries WHERE eid = substr({parent}, 1, length({parent}) - 1)) & {flag} != 0")
+            };
+            let triggers = [
+                ("insert", "INSERT ON dav_entries", "NEW", 0),
+                ("update", "UPDATE ON dav_entries", "NEW", 0),
+                ("rename", "UPDATE OF eid ON dav_entries", "OLD", 1),
+                ("delete", "DELETE ON dav_entries", "OLD", 1),
+                ("content", "UPDATE OF hash ON dav_blocks", "NEW", 0),
+            ];
+            for (name, event, row, deleted) in triggers {
+                let cond = match name {
+                    "rename" => format!("OLD.eid != NEW.eid AND {}", is_object("OLD.eid")),
+                    "content" => format!("NEW.idx = 0 AND {}", is_object("NEW.eid")),
+                    _ => is_object(&format!("{row}.eid")),
+                };
+                let sql = format!("CREATE TRIGGER IF NOT EXISTS dav_changes_{name} AFTER {event} WHEN {cond} BEGIN DELETE FROM dav_changes WHERE eid = {row}.eid; INSERT INTO dav_changes (eid, deleted) VALUES ({row}.eid, {deleted}); END");
+                db.execute(&sql, ()).unwrap();
+            }
+            // dav_migrations: name = "pathnames", the one-time migrations done
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS dav_migrations (name BLOB PRIMARY KEY)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+        })
+        .await;
+        migrate_inline_data().await;
+        migrate_pathnames().await;
+        migrate_plain_auth().await;
+    }
+    /// Hash the passwords, which were stored as the `Authorization` header by the old versions.
+    async fn migrate_plain_auth() {
+        let users = DB.call(|db| {
+            let sql = strip_str! {"
+                SELECT uid, auth FROM dav_users WHERE substr(auth, 1, 6) = CAST('Basic ' AS BLOB)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            let v: Vec<(String, String)> = stmd
+                .query_map((), |r| Ok((v2s(r.get(0)?), v2s(r.get(1)?))))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect();
+            v
+        });
+        for (uid, auth) in users.await {
+            match user::parse_basic(auth.as_bytes()) {
+                Some((v, password)) if v == uid => {
+                    set_user(uid, user::hash(password).await, None).await
+                }
+                _ => log!(warn: "dav user {uid} has invalid auth, not migrated"),
+            }
+        }
+    }
+    /// Move the content in `dav_entries.data` into chunks, for the databases before chunked storage.
+    async fn migrate_inline_data() {
+        let eids = DB.call(|db| {
+            let sql = strip_str! {"
+                SELECT eid FROM dav_entries WHERE flag & ? = 0 AND length(data) > 0
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            let v: Vec<String> = stmd
+                .query_map((ENTRY_HREF,), |r| r.get(0).map(v2s))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect();
+            v
+        });
+        for eid in eids.await {
+            let data = get_entry_data(eid.to_owned()).await.unwrap();
+            let hashes = data.chunks(blob::CHUNK_SIZE).map(crate::sha256::sha256);
+            let hashes: Vec<_> = hashes.collect();
+            for (hash, chunk) in hashes.iter().zip(data.chunks(blob::CHUNK_SIZE)) {
+                put_chunk(*hash, chunk.to_vec()).await;
+            }
+            DB.call(move |db| {
+                let tx = db.transaction().unwrap();
+                set_blocks(&tx, &eid, &hashes);
+                let sql = strip_str! {"
+                    UPDATE dav_entries SET data = x'' WHERE eid = ?
+                "};
+                tx.execute(sql, (eid.as_bytes(),)).unwrap();
+                tx.commit().unwrap();
+            })
+            .await;
+        }
+    }
+    /// Decode the eids, which were stored percent-encoded as in the url by the old versions.
+    async fn migrate_pathnames() {
+        DB.call(|db| {
+            let tx = db.transaction().unwrap();
+            let sql = strip_str! {"
+                INSERT OR IGNORE INTO dav_migrations VALUES (CAST('pathnames' AS BLOB))
+            "};
+            if tx.execute(sql, ()).unwrap() == 0 {
+                return; // done already, must not decode twice
+            }
+            let mut stmd = tx.prepare("SELECT eid FROM dav_entries").unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            let eids: Vec<String> = stmd
+                .query_map((), |r| r.get(0).map(v2s))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect();
+            drop(stmd);
+            for eid in eids {
+                let (uid, pathname) = eid.split_once(':').unwrap();
+                let Some(pathname) = path::decode(pathname) else {
+                    log!(warn: "dav entry {eid} has invalid pathname, skipped");
+                    continue;
+                };
+                let new_eid = uid.to_owned() + ":" + &pathname;
+                if new_eid == eid {
+                    continue;
+                }
+                let sql = "SELECT 1 FROM dav_entries WHERE eid = ?";
+                if tx.query_row(sql, (new_eid.as_bytes(),), |_| Ok(())).is_ok() {
+                    log!(warn: "dav entry {eid} conflicts with {new_eid}, skipped");
+                    continue;
+                }
+                for table in ["dav_entries", "dav_props", "dav_locks", "dav_blocks"] {
+                    let sql = format!("UPDATE {table} SET eid = ? WHERE eid = ?");
+                    tx.execute(&sql, (new_eid.as_bytes(), eid.as_bytes()))
+                        .unwrap();
+                }
+            }
+            tx.commit().unwrap();
+        })
+        .await
+    }
+    /// Returns `(auth, enc)` of the user.
+    pub async fn get_user_auth(uid: String) -> Option<(String, Option<Vec<u8>>)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT auth, enc FROM dav_users WHERE uid = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            stmd.query_row((uid.into_bytes(),), |r| Ok((v2s(r.get(0)?), r.get(1)?)))
+                .ok()
+        })
+        .await
+    }
+    /// Returns the wrapped data key if the user is encrypted.
+    pub async fn get_user_enc(uid: String) -> Option<Vec<u8>> {
+        get_user_auth(uid).await?.1
+    }
+    pub async fn set_user(uid: String, auth: String, enc: Option<Vec<u8>>) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                INSERT INTO dav_users (uid, auth, enc) VALUES (?, ?, ?)
+                ON CONFLICT (uid) DO UPDATE SET auth = excluded.auth, enc = excluded.enc
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((uid.into_bytes(), auth.into_bytes(), enc))
+                .unwrap();
+        })
+        .await
+    }
+    /// Returns the quota of the user, `None` if not set.
+    pub async fn get_user_quota(uid: String) -> Option<u64> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT quota FROM dav_users WHERE uid = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((uid.into_bytes(),), |r| r.get(0))
+                .ok()
+                .flatten()
+        })
+        .await
+    }
+    /// Returns false if the user not found.
+    pub async fn set_user_quota(uid: String, quota: Option<u64>) -> bool {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                UPDATE dav_users SET quota = ? WHERE uid = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((quota, uid.into_bytes())).unwrap() != 0
+        })
+        .await
+    }
+    /// Delete the user and the app passwords, entries, props, locks. Returns false if not found.
+    pub async fn del_user(uid: String) -> bool {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            let sql = strip_str! {"
+                DELETE FROM dav_users WHERE uid = ?
+            "};
+            if tx.execute(sql, (uid.as_bytes(),)).unwrap() == 0 {
+                return false;
+            }
+            let sql = strip_str! {"
+                DELETE FROM dav_app_passwords WHERE uid = ?
+            "};
+            tx.execute(sql, (uid.as_bytes(),)).unwrap();
+            let sql = strip_str! {"
+                DELETE FROM dav_trash WHERE uid = ?
+            "};
+            tx.execute(sql, (uid.as_bytes(),)).unwrap();
+            // the trashed trees are also under the prefix
+            let prefix = uid + ":";
+            let scope = "substr(eid, 1, length(?1)) = ?1";
+            let sql = format!("DELETE FROM dav_version_blocks WHERE vid IN (SELECT vid FROM dav_versions WHERE {scope})");
+            tx.execute(&sql, (prefix.as_bytes(),)).unwrap();
+            for table in [
+                "dav_entries",
+                "dav_props",
+                "dav_locks",
+                "dav_blocks",
+                "dav_shares",
+                "dav_versions",
+                "dav_changes",
+                "dav_uploads",
+            ] {
+                let sql = format!("DELETE FROM {table} WHERE {scope}");
+                tx.execute(&sql, (prefix.as_bytes(),)).unwrap();
+            }
+            tx.commit().unwrap();
+            true
+        })
+        .await
+    }
+    /// Returns `(name, auth, enc)` of each app password.
+    pub async fn list_app_passwords(uid: String) -> Vec<(String, String, Option<Vec<u8>>)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT name, auth, enc FROM dav_app_passwords WHERE uid = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            stmd.query_map((uid.as_bytes(),), |r| {
+                Ok((v2s(r.get(0)?), v2s(r.get(1)?), r.get(2)?))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+        })
+        .await
+    }
+    pub async fn set_app_password(uid: String, name: String, auth: String, enc: Option<Vec<u8>>) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                REPLACE INTO dav_app_passwords VALUES (?, ?, ?, ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((uid.as_bytes(), name.as_bytes(), auth.as_bytes(), enc))
+                .unwrap();
+        })
+        .await
+    }
+    pub async fn del_app_password(uid: String, name: String) -> bool {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM dav_app_passwords WHERE uid = ? AND name = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((uid.as_bytes(), name.as_bytes())).unwrap() != 0
+        })
+        .await
+    }
+    pub async fn set_entry(eid: String, data: Bytes, time: u64, size: u64, flag: u64) {
+        DB.call(move |db| {
+            // keep the ctime when overwriting
+            let sql = strip_str! {"
+                INSERT INTO dav_entries VALUES (?1, ?2, ?3, ?4, ?5, ?3)
+                ON CONFLICT (eid) DO UPDATE SET data = ?2, time = ?3, size = ?4, flag = ?5
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((eid.into_bytes(), data.as_ref(), time, size, flag))
+                .unwrap();
+        })
+        .await
+    }
+    fn set_blocks(tx: &rusqlite::Transaction, eid: &str, hashes: &[[u8; 32]]) {
+        let sql = strip_str! {"
+            DELETE FROM dav_blocks WHERE eid = ?
+        "};
+        tx.prepare_cached(sql)
+            .unwrap()
+            .execute((eid.as_bytes(),))
+            .unwrap();
+        let sql = strip_str! {"
+            INSERT INTO dav_blocks VALUES (?, ?, ?)
+        "};
+        let mut stmd = tx.prepare_cached(sql).unwrap();
+        for (idx, hash) in hashes.iter().enumerate() {
+            stmd.execute((eid.as_bytes(), idx, hash)).unwrap();
+        }
+    }
+    /// Save the content of the file entry as a version, then remove the versions except the latest `keep`.
+    ///
+    /// The empty files are skipped, as some clients create them before the real upload.
+    fn push_version(tx: &rusqlite::Transaction, eid: &str, keep: u64) {
+        let sql = strip_str! {"
+            INSERT INTO dav_versions (eid, time, size) SELECT eid, time, size FROM dav_entries
+            WHERE eid = ? AND flag & ? = 0 AND size > 0
+        "};
+        let mut stmd = tx.prepare_cached(sql).unwrap();
+        if keep != 0
+            && stmd
+                .execute((eid.as_bytes(), ENTRY_DIR | ENTRY_HREF))
+                .unwrap()
+                != 0
+        {
+            let sql = strip_str! {"
+                INSERT INTO dav_version_blocks SELECT ?, idx, hash FROM dav_blocks WHERE eid = ?
+            "};
+            let mut stmd = tx.prepare_cached(sql).unwrap();
+            stmd.execute((tx.last_insert_rowid(), eid.as_bytes()))
+                .unwrap();
+        }
+        let scope = "vid IN (SELECT vid FROM dav_versions WHERE eid = ? ORDER BY vid DESC LIMIT -1 OFFSET ?)";
+        for table in ["dav_version_blocks", "dav_versions"] {
+            let sql = format!("DELETE FROM {table} WHERE {scope}");
+            let mut stmd = tx.prepare_cached(&sql).unwrap();
+            stmd.execute((eid.as_bytes(), keep)).unwrap();
+        }
+    }
+    /// Like `set_entry`, but the content is the chunks. The old content is kept as a version if `keep` is not 0.
+    pub async fn set_file(
+        eid: String,
+        hashes: Vec<[u8; 32]>,
+        time: u64,
+        size: u64,
+        flag: u64,
+        keep: u64,
+    ) {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            push_version(&tx, &eid, keep);
+            let sql = strip_str! {"
+                INSERT INTO dav_entries VALUES (?1, x'', ?2, ?3, ?4, ?2)
+                ON CONFLICT (eid) DO UPDATE SET data = x'', time = ?2, size = ?3, flag = ?4
+            "};
+            let mut stmd = tx.prepare_cached(sql).unwrap();
+            stmd.execute((eid.as_bytes(), time, size, flag)).unwrap();
+            drop(stmd);
+            set_blocks(&tx, &eid, &hashes);
+            tx.commit().unwrap();
+        })
+        .await
+    }
+    pub async fn list_blocks(eid: String) -> Vec<[u8; 32]> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT hash FROM dav_blocks WHERE eid = ? ORDER BY idx
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_map((eid.as_bytes(),), |r| r.get(0))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        })
+        .await
+    }
+    /// Insert the chunk if not exists, or refresh the time to keep it from `gc_chunks`.
+    pub async fn put_chunk(hash: [u8; 32], data: Vec<u8>) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                INSERT INTO dav_chunks VALUES (?1, ?2, ?3) ON CONFLICT (hash) DO UPDATE SET time = ?3
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+            stmd.execute((hash, data, time)).unwrap();
+        })
+        .await
+    }
+    pub async fn get_chunk(hash: [u8; 32]) -> Option<Vec<u8>> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT data FROM dav_chunks WHERE hash = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((hash,), |r| r.get(0)).ok()
+        })
+        .await
+    }
+    /// Returns the hashes of the user's chunks, with the versions and the uploading parts.
+    pub async fn list_user_chunks(uid: String) -> Vec<[u8; 32]> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT hash FROM dav_blocks WHERE substr(eid, 1, length(?1)) = ?1
+                UNION SELECT hash FROM dav_version_blocks WHERE vid IN (SELECT vid FROM dav_versions WHERE substr(eid, 1, length(?1)) = ?1)
+                UNION SELECT hash FROM dav_uploads WHERE substr(eid, 1, length(?1)) = ?1
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let uid = uid + ":";
+            stmd.query_map((uid.as_bytes(),), |r| r.get(0))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        })
+        .await
+    }
+    /// Switch the user's chunks to the `converted` ones and set the wrapped data key, all or nothing.
+    ///
+    /// The chunks written after `list_user_chunks` are converted here by `convert`, usually none.
+    /// Encrypting removes the app passwords and shares, decrypting keeps the app passwords.
+    /// Returns false if any chunk failed to convert, or the user was switched meanwhile.
+    pub async fn switch_chunks(
+        uid: String,
+        enc: Option<Vec<u8>>,
+        converted: HashMap<[u8; 32], [u8; 32]>,
+        convert: impl Fn(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+    ) -> bool {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            let encrypt = enc.is_some();
+            let sql = "UPDATE dav_users SET enc = ?1 WHERE uid = ?2 AND (enc IS NULL) = (?1 IS NOT NULL)";
+            if tx.execute(sql, (enc, uid.as_bytes())).unwrap() == 0 {
+                return false;
+            }
+            let sql = match encrypt {
+                true => "DELETE FROM dav_app_passwords WHERE uid = ?",
+                false => "UPDATE dav_app_passwords SET enc = NULL WHERE uid = ?",
+            };
+            tx.execute(sql, (uid.as_bytes(),)).unwrap();
+            let prefix = uid + ":";
+            let scope = "substr(eid, 1, length(?1)) = ?1";
+            if encrypt {
+                let sql = format!("DELETE FROM dav_shares WHERE {scope}");
+                tx.execute(&sql, (prefix.as_bytes(),)).unwrap();
+            }
+            let versions = format!("vid IN (SELECT vid FROM dav_versions WHERE {scope})");
+            let sql = format!("SELECT hash FROM dav_blocks WHERE {scope} UNION SELECT hash FROM dav_version_blocks WHERE {versions} UNION SELECT hash FROM dav_uploads WHERE {scope}");
+            let mut stmd = tx.prepare(&sql).unwrap();
+            let hashes: Vec<[u8; 32]> = stmd
+                .query_map((prefix.as_bytes(),), |r| r.get(0))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect();
+            drop(stmd);
+            let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+            for hash in hashes {
+                // refresh the converted one, it may be removed by `gc_chunks` if the conversion took long
+                let sql = "UPDATE dav_chunks SET time = ? WHERE hash = ?";
+                let new_hash = match converted.get(&hash) {
+                    Some(&v) if tx.execute(sql, (now, v)).unwrap() != 0 => v,
+                    _ => {
+                        let sql = "SELECT data FROM dav_chunks WHERE hash = ?";
+                        let data: Vec<u8> = tx.query_row(sql, (hash,), |r| r.get(0)).unwrap();
+                        let Some(data) = convert(&data) else {
+                            return false; // rollback
+                        };
+                        let new_hash = crate::sha256::sha256(&data);
+                        let sql = "INSERT INTO dav_chunks VALUES (?1, ?2, ?3) ON CONFLICT (hash) DO UPDATE SET time = ?3";
+                        tx.execute(sql, (new_hash, data, now)).unwrap();
+                        new_hash
+                    }
+                };
+                let sql = format!("UPDATE dav_blocks SET hash = ?2 WHERE hash = ?3 AND {scope}");
+                tx.execute(&sql, (prefix.as_bytes(), new_hash, hash)).unwrap();
+                let sql = format!("UPDATE dav_version_blocks SET hash = ?2 WHERE hash = ?3 AND {versions}");
+                tx.execute(&sql, (prefix.as_bytes(), new_hash, hash)).unwrap();
+                let sql = format!("UPDATE dav_uploads SET hash = ?2 WHERE hash = ?3 AND {scope}");
+                tx.execute(&sql, (prefix.as_bytes(), new_hash, hash)).unwrap();
+            }
+            tx.commit().unwrap();
+            true
+        })
+        .await
+    }
+    /// Remove the chunks not referenced since `before`, returns the count.
+    ///
+    /// The uploading ones are not referenced by blocks yet, so the recent chunks are kept.
+    pub async fn gc_chunks(before: u64) -> usize {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM dav_chunks WHERE time < ?
+                AND hash NOT IN (SELECT hash FROM dav_blocks) AND hash NOT IN (SELECT hash FROM dav_version_blocks)
+                AND hash NOT IN (SELECT hash FROM dav_uploads)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((before,)).unwrap()
+        })
+        .await
+    }
+    pub async fn set_upload_part(eid: String, id: String, idx: u64, hash: [u8; 32], size: u64) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                REPLACE INTO dav_uploads VALUES (?, ?, ?, ?, ?, ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+            stmd.execute((eid.as_bytes(), id.as_bytes(), idx, hash, size, time))
+                .unwrap();
+        })
+        .await
+    }
+    /// Returns `(idx, hash, size)` of each received part.
+    pub async fn list_upload_parts(eid: String, id: String) -> Vec<(u64, [u8; 32], u64)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT idx, hash, size FROM dav_uploads WHERE eid = ? AND id = ? ORDER BY idx
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_map((eid.as_bytes(), id.as_bytes()), |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+        })
+        .await
+    }
+    /// Returns the bytes of the received parts of the user's uploads, not counted by `get_usage`.
+    pub async fn get_upload_usage(uid: String) -> u64 {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT ifnull(sum(size), 0) FROM dav_uploads WHERE substr(eid, 1, length(?1)) = ?1
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let uid = uid + ":";
+            stmd.query_row((uid.as_bytes(),), |r| r.get(0)).unwrap()
+        })
+        .await
+    }
+    pub async fn del_upload(eid: String, id: String) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM dav_uploads WHERE eid = ? AND id = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((eid.as_bytes(), id.as_bytes())).unwrap();
+        })
+        .await
+    }
+    /// Remove the uploads without any part received since `before`, returns the count of parts.
+    pub async fn expire_uploads(before: u64) -> usize {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM dav_uploads WHERE (eid, id) IN (SELECT eid, id FROM dav_uploads GROUP BY eid, id HAVING max(time) < ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((before,)).unwrap()
+        })
+        .await
+    }
+    pub async fn set_entry_flag(eid: String, flag: u64) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                UPDATE dav_entries SET flag = ? WHERE eid = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((flag, eid.as_bytes())).unwrap();
+        })
+        .await
+    }
+    pub async fn get_entry_data(eid: String) -> Option<Vec<u8>> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT data FROM dav_entries WHERE eid = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((eid.as_bytes(),), |r| r.get(0)).ok()
+        })
+        .await
+    }
+    pub async fn get_entry_meta(eid: String) -> Option<(u64, u64, u64)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT time, size, flag FROM dav_entries WHERE eid = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((eid.as_bytes(),), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+                .ok()
+        })
+        .await
+    }
+    /// Returns `(eid, time, size, flag, ctime)` of the entry itself and the children within `depth`.
+    pub async fn list_entry_tree(eid: String, depth: u64) -> Vec<(String, u64, u64, u64, u64)> {
+        DB.call(move |db| {
+            // the depth is counted by slashes in the remaining path, all lengths in bytes as the `replace` returns text
             let sql = strip_str! {"
-                CREATE TABLE IF NOT EXISTS dav_users (uid BLOB PRIMARY KEY, auth BLOB UNIQUE)
+                SELECT eid, time, size, flag, ifnull(ctime, time) FROM dav_entries
+                WHERE eid = ?1 OR (substr(eid, 1, length(?2)) = ?2
+                AND length(eid) - length(CAST(replace(eid, '/', '') AS BLOB)) - length(?2) + length(CAST(replace(?2, '/', '') AS BLOB)) < ?3)
+                ORDER BY eid != ?1, eid
             "};
-            let mut stmd = db.prepare(sql).unwrap();
-            stmd.execute(()).unwrap();
-            // dav_entries: eid = "username:/dir/file", data = "<bin data or empty>", time (modified, seconds) = 1706298055, size (bytes) = 4096, flag = 0b0000
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            let dir = eid.to_owned() + "/";
+            stmd.query_map((eid.as_bytes(), dir.as_bytes(), depth), |r| {
+                Ok((v2s(r.get(0)?), r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+        })
+        .await
+    }
+    /// Returns `(eid, time, size, flag)` of the entries under `eid` whose name contains `query`, ignoring ASCII case.
+    pub async fn search_entry_tree(
+        eid: String,
+        query: String,
+        limit: usize,
+    ) -> Vec<(String, u64, u64, u64)> {
+        DB.call(move |db| {
+            // matches any part of the remaining path first, then the name is checked
             let sql = strip_str! {"
-                CREATE TABLE IF NOT EXISTS dav_entries (eid BLOB PRIMARY KEY, data BLOB, time INTEGER, size INTEGER, flag INTEGER)
+                SELECT eid, time, size, flag FROM dav_entries
+                WHERE substr(eid, 1, length(?1)) = ?1 AND instr(lower(CAST(substr(eid, length(?1) + 1) AS TEXT)), lower(?2)) > 0
+                ORDER BY eid
             "};
-            let mut stmd = db.prepare(sql).unwrap();
-            stmd.execute(()).unwrap();
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            let dir = eid + "/";
+            let query = query.to_ascii_lowercase();
+            stmd.query_map((dir.as_bytes(), &query), |r| {
+                Ok((v2s(r.get(0)?), r.get(1)?, r.get(2)?, r.get(3)?))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .filter(|v: &(String, u64, u64, u64)| {
+                let (_, name) = v.0.rsplit_once('/').unwrap();
+                name.to_ascii_lowercase().contains(&query)
+            })
+            .take(limit)
+            .collect()
+        })
+        .await
+    }
+    pub async fn get_usage(uid: String) -> u64 {
+        DB.call(move |db| {
+            // the versions are counted too, as they are kept for the user
+            let sql = strip_str! {"
+                SELECT (SELECT ifnull(sum(size), 0) FROM dav_entries WHERE substr(eid, 1, length(?1)) = ?1)
+                    + (SELECT ifnull(sum(size), 0) FROM dav_versions WHERE substr(eid, 1, length(?1)) = ?1)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let uid = uid + ":";
+            stmd.query_row((uid.as_bytes(),), |r| r.get(0)).unwrap()
         })
         .await
     }
-    pub async fn get_user_uid(auth: String) -> Option<String> {
+    /// Returns `(ns, name, value)` of dead properties.
+    pub async fn list_props(eid: String) -> Vec<(String, String, String)> {
         DB.call(move |db| {
             let sql = strip_str! {"
-                SELECT uid FROM dav_users WHERE auth = ?
+                SELECT ns, name, value FROM dav_props WHERE eid = ?
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
             let v2s = |v| String::from_utf8(v).unwrap();
-            stmd.query_row((auth.into_bytes(),), |r| r.get(0).map(v2s))
-                .ok()
+            stmd.query_map((eid.as_bytes(),), |r| {
+                Ok((v2s(r.get(0)?), v2s(r.get(1)?), v2s(r.get(2)?)))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+        })
+        .await
+    }
+    /// Set or remove (if value is `None`) dead properties, all or nothing.
+    pub async fn set_props(eid: String, props: Vec<(String, String, Option<String>)>) {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            for (ns, name, value) in props {
+                let key = (eid.as_bytes(), ns.as_bytes(), name.as_bytes());
+                match value {
+                    Some(value) => {
+                        let sql = strip_str! {"
+                            REPLACE INTO dav_props VALUES (?, ?, ?, ?)
+                        "};
+                        let mut stmd = tx.prepare_cached(sql).unwrap();
+                        stmd.execute((key.0, key.1, key.2, value.as_bytes()))
+                            .unwrap();
+                    }
+                    None => {
+                        let sql = strip_str! {"
+                            DELETE FROM dav_props WHERE eid = ? AND ns = ? AND name = ?
+                        "};
+                        let mut stmd = tx.prepare_cached(sql).unwrap();
+                        stmd.execute(key).unwrap();
+                    }
+                }
+            }
+            tx.commit().unwrap();
         })
         .await
     }
-    pub async fn set_user(uid: String, auth: String) {
+    /// The latest change of the direct children, `0` if none.
+    pub async fn get_sync_token(eid: String) -> u64 {
         DB.call(move |db| {
             let sql = strip_str! {"
-                REPLACE INTO dav_users VALUES (?, ?)
+                SELECT ifnull(max(seq), 0) FROM dav_changes
+                WHERE substr(eid, 1, length(?1)) = ?1 AND instr(substr(eid, length(?1) + 1), '/') = 0
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
-            stmd.execute((uid.into_bytes(), auth.into_bytes())).unwrap();
+            let dir = eid + "/";
+            stmd.query_row((dir.as_bytes(),), |r| r.get(0)).unwrap()
         })
         .await
     }
-    pub async fn set_entry(eid: String, data: Bytes, time: u64, size: u64, flag: u64) {
+    /// Returns `(eid, deleted)` of the direct children changed after `since`.
+    pub async fn list_changes(eid: String, since: u64) -> Vec<(String, bool)> {
         DB.call(move |db| {
             let sql = strip_str! {"
-                REPLACE INTO dav_entries VALUES (?, ?, ?, ?, ?)
+                SELECT eid, deleted FROM dav_changes
+                WHERE substr(eid, 1, length(?1)) = ?1 AND instr(substr(eid, length(?1) + 1), '/') = 0 AND seq > ?2
+                ORDER BY seq
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
-            stmd.execute((eid.into_bytes(), data.as_ref(), time, size, flag))
-                .unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            let dir = eid + "/";
+            stmd.query_map((dir.as_bytes(), since), |r| Ok((v2s(r.get(0)?), r.get(1)?)))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
         })
         .await
     }
-    pub async fn set_entry_flag(eid: String, flag: u64) {
+    /// The trashed tree is kept here, out of the user's root so no path reaches it.
+    pub fn trash_eid(uid: &str, tid: u64) -> String {
+        format!("{uid}:~trash/{tid}")
+    }
+    /// Delete the entry and the children, with everything attached.
+    fn del_tree(tx: &rusqlite::Transaction, eid: &str) {
+        let dir = eid.to_owned() + "/";
+        let scope = "eid = ?1 OR substr(eid, 1, length(?2)) = ?2";
+        let sql = format!("DELETE FROM dav_version_blocks WHERE vid IN (SELECT vid FROM dav_versions WHERE {scope})");
+        tx.execute(&sql, (eid.as_bytes(), dir.as_bytes())).unwrap();
+        for table in [
+            "dav_entries",
+            "dav_props",
+            "dav_locks",
+            "dav_blocks",
+            "dav_shares",
+            "dav_versions",
+            "dav_uploads",
+        ] {
+            let sql = format!("DELETE FROM {table} WHERE {scope}");
+            let mut stmd = tx.prepare_cached(&sql).unwrap();
+            stmd.execute((eid.as_bytes(), dir.as_bytes())).unwrap();
+        }
+        // keep the entry's own change, which tells the clients it's deleted
+        let sql = "DELETE FROM dav_changes WHERE substr(eid, 1, length(?1)) = ?1";
+        tx.execute(sql, (dir.as_bytes(),)).unwrap();
+    }
+    /// Move the entry and the children (if `deep`) to `dest_eid`, which keep everything except locks.
+    fn move_tree(
+        tx: &rusqlite::Transaction,
+        eid: &str,
+        dest_eid: &str,
+        deep: bool,
+    ) -> rusqlite::Result<()> {
+        let dir = eid.to_owned() + "/";
+        // the `||` makes text, so cast back to keep the type of eid
+        let new_eid = "CAST(?3 || substr(eid, length(?1) + 1) AS BLOB)";
+        let scope = "(eid = ?1 OR (?4 AND substr(eid, 1, length(?2)) = ?2))";
+        let params = (eid.as_bytes(), dir.as_bytes(), dest_eid.as_bytes(), deep);
+        for table in [
+            "dav_entries",
+            "dav_props",
+            "dav_blocks",
+            "dav_shares",
+            "dav_versions",
+        ] {
+            let sql = format!("UPDATE {table} SET eid = {new_eid} WHERE {scope}");
+            tx.execute(&sql, params)?;
+        }
+        let sql = format!("DELETE FROM dav_locks WHERE {scope}");
+        tx.execute(&sql, params)?;
+        // the children left the old path, only the entry's own change is meaningful there
+        let sql = "DELETE FROM dav_changes WHERE ?2 AND substr(eid, 1, length(?1)) = ?1";
+        tx.execute(sql, (dir.as_bytes(), deep))?;
+        Ok(())
+    }
+    /// Move the entry and the children into the trash if exists, the shares and the uploads are removed.
+    fn trash_tree(tx: &rusqlite::Transaction, eid: &str, now: u64) {
+        let sql = "SELECT 1 FROM dav_entries WHERE eid = ?";
+        if tx.query_row(sql, (eid.as_bytes(),), |_| Ok(())).is_err() {
+            return;
+        }
+        let (uid, _) = eid.split_once(':').unwrap();
+        let sql = strip_str! {"
+            INSERT INTO dav_trash (uid, eid, time) VALUES (?, ?, ?)
+        "};
+        tx.execute(sql, (uid.as_bytes(), eid.as_bytes(), now))
+            .unwrap();
+        let trash_eid = trash_eid(uid, tx.last_insert_rowid() as _);
+        let dir = eid.to_owned() + "/";
+        for table in ["dav_shares", "dav_uploads"] {
+            let sql =
+                format!("DELETE FROM {table} WHERE eid = ?1 OR substr(eid, 1, length(?2)) = ?2");
+            tx.execute(&sql, (eid.as_bytes(), dir.as_bytes())).unwrap();
+        }
+        move_tree(tx, eid, &trash_eid, true).unwrap();
+    }
+    /// Copy or move the entry and the children (if `deep`) to `dest_eid` in a transaction.
+    ///
+    /// The existing dest is replaced, and goes to the trash if `trash`. The copies are created now, without versions.
+    pub async fn copy_entry_tree(
+        eid: String,
+        dest_eid: String,
+        deep: bool,
+        is_move: bool,
+        trash: bool,
+    ) {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+            if trash {
+                trash_tree(&tx, &dest_eid, now);
+            }
+            del_tree(&tx, &dest_eid);
+            if is_move {
+                move_tree(&tx, &eid, &dest_eid, deep).unwrap();
+                tx.commit().unwrap();
+                return;
+            }
+            let dir = eid.to_owned() + "/";
+            let new_eid = "CAST(?3 || substr(eid, length(?1) + 1) AS BLOB)";
+            let scope = "(eid = ?1 OR (?4 AND substr(eid, 1, length(?2)) = ?2))";
+            let params = (eid.as_bytes(), dir.as_bytes(), dest_eid.as_bytes(), deep);
+            let sql = format!("INSERT INTO dav_entries SELECT {new_eid}, data, time, size, flag, ?5 FROM dav_entries WHERE {scope}");
+            tx.execute(&sql, (params.0, params.1, params.2, params.3, now)).unwrap();
+            let sql = format!("INSERT INTO dav_props SELECT {new_eid}, ns, name, value FROM dav_props WHERE {scope}");
+            tx.execute(&sql, params).unwrap();
+            let sql = format!("INSERT INTO dav_blocks SELECT {new_eid}, idx, hash FROM dav_blocks WHERE {scope}");
+            tx.execute(&sql, params).unwrap();
+            tx.commit().unwrap();
+        })
+        .await
+    }
+    /// Returns `(vid, time, size)` of the versions of the file, the latest first.
+    pub async fn list_versions(eid: String) -> Vec<(u64, u64, u64)> {
         DB.call(move |db| {
             let sql = strip_str! {"
-                UPDATE dav_entries SET flag = ? WHERE eid = ?
+                SELECT vid, time, size FROM dav_versions WHERE eid = ? ORDER BY vid DESC
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
-            stmd.execute((flag, eid.as_bytes())).unwrap();
+            stmd.query_map((eid.as_bytes(),), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
         })
         .await
     }
-    pub async fn get_entry_data(eid: String) -> Option<Vec<u8>> {
+    /// Replace the content of the file with the version, the current content becomes a version.
+    ///
+    /// Returns false if the version not found.
+    pub async fn restore_version(eid: String, vid: u64, time: u64, keep: u64) -> bool {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            let sql = "SELECT size FROM dav_versions WHERE vid = ? AND eid = ?";
+            let Ok(size) = tx.query_row(sql, (vid, eid.as_bytes()), |r| r.get::<_, u64>(0)) else {
+                return false;
+            };
+            let sql = "SELECT hash FROM dav_version_blocks WHERE vid = ? ORDER BY idx";
+            let mut stmd = tx.prepare(sql).unwrap();
+            let hashes: Vec<[u8; 32]> = stmd
+                .query_map((vid,), |r| r.get(0))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect();
+            drop(stmd);
+            for table in ["dav_version_blocks", "dav_versions"] {
+                let sql = format!("DELETE FROM {table} WHERE vid = ?");
+                tx.execute(&sql, (vid,)).unwrap();
+            }
+            push_version(&tx, &eid, keep);
+            let sql = "UPDATE dav_entries SET time = ?, size = ? WHERE eid = ?";
+            tx.execute(sql, (time, size, eid.as_bytes())).unwrap();
+            set_blocks(&tx, &eid, &hashes);
+            tx.commit().unwrap();
+            true
+        })
+        .await
+    }
+    /// Delete the version of the file, or all of them if `vid` is `None`, returns the count.
+    pub async fn del_versions(eid: String, vid: Option<u64>) -> usize {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            let scope =
+                "vid IN (SELECT vid FROM dav_versions WHERE eid = ?1 AND (?2 IS NULL OR vid = ?2))";
+            let sql = format!("DELETE FROM dav_version_blocks WHERE {scope}");
+            tx.execute(&sql, (eid.as_bytes(), vid)).unwrap();
+            let sql = format!("DELETE FROM dav_versions WHERE {scope}");
+            let count = tx.execute(&sql, (eid.as_bytes(), vid)).unwrap();
+            tx.commit().unwrap();
+            count
+        })
+        .await
+    }
+    /// Returns `(tid, eid, time)` of the user's trashed trees, the latest first.
+    pub async fn list_trash(uid: String) -> Vec<(u64, String, u64)> {
         DB.call(move |db| {
             let sql = strip_str! {"
-                SELECT data FROM dav_entries WHERE eid = ?
+                SELECT tid, eid, time FROM dav_trash WHERE uid = ? ORDER BY tid DESC
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
-            stmd.query_row((eid.as_bytes(),), |r| r.get(0)).ok()
+            let v2s = |v| String::from_utf8(v).unwrap();
+            stmd.query_map((uid.as_bytes(),), |r| {
+                Ok((r.get(0)?, v2s(r.get(1)?), r.get(2)?))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
         })
         .await
     }
-    pub async fn get_entry_meta(eid: String) -> Option<(u64, u64, u64)> {
+    /// Returns `(uid, tid)` of the trashed trees deleted before the time.
+    pub async fn list_expired_trash(before: u64) -> Vec<(String, u64)> {
         DB.call(move |db| {
             let sql = strip_str! {"
-                SELECT time, size, flag FROM dav_entries WHERE eid = ?
+                SELECT uid, tid FROM dav_trash WHERE time < ?
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
-            stmd.query_row((eid.as_bytes(),), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
-                .ok()
+            let v2s = |v| String::from_utf8(v).unwrap();
+            stmd.query_map((before,), |r| Ok((v2s(r.get(0)?), r.get(1)?)))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        })
+        .await
+    }
+    /// Move the trashed tree back to the original eid.
+    /// Returns false if the trash is gone, the path is taken or the parent is not a writable dir.
+    pub async fn restore_trash(uid: String, tid: u64, eid: String) -> bool {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            // checked in the transaction, so nothing can be created between
+            let get_flag = |eid: &str| {
+                let sql = "SELECT flag FROM dav_entries WHERE eid = ?";
+                tx.query_row(sql, (eid.as_bytes(),), |r| r.get::<_, u64>(0))
+                    .ok()
+            };
+            let Some((parent, _)) = eid.rsplit_once('/') else {
+                return false;
+            };
+            let writable =
+                get_flag(parent).is_some_and(|v| v & ENTRY_DIR != 0 && v & ENTRY_READ_ONLY == 0);
+            if !writable || get_flag(&eid).is_some() {
+                return false;
+            }
+            let sql = strip_str! {"
+                DELETE FROM dav_trash WHERE tid = ? AND uid = ?
+            "};
+            if tx.execute(sql, (tid, uid.as_bytes())) != Ok(1) {
+                return false;
+            }
+            // the transaction is rolled back on drop if failed
+            if move_tree(&tx, &trash_eid(&uid, tid), &eid, true).is_err() {
+                return false;
+            }
+            tx.commit().is_ok()
+        })
+        .await
+    }
+    /// Delete the trashed trees by `(uid, tid)`, returns the count.
+    pub async fn purge_trash(items: Vec<(String, u64)>) -> usize {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            let mut count = 0;
+            for (uid, tid) in items {
+                let sql = strip_str! {"
+                    DELETE FROM dav_trash WHERE uid = ? AND tid = ?
+                "};
+                if tx.execute(sql, (uid.as_bytes(), tid)).unwrap() != 0 {
+                    del_tree(&tx, &trash_eid(&uid, tid));
+                    count += 1;
+                }
+            }
+            tx.commit().unwrap();
+            count
         })
         .await
     }
-    pub async fn list_entry_meta(eid: String, recursive: bool) -> Vec<(String, u64, u64, u64)> {
+    /// Returns the unexpired locks of the user, the expired ones are removed.
+    pub async fn list_locks(uid: String) -> Vec<lock::Lock> {
         DB.call(move |db| {
             let sql = strip_str! {"
-                SELECT eid, time, size, flag FROM dav_entries WHERE eid LIKE ? AND (? OR eid NOT LIKE ?)
+                DELETE FROM dav_locks WHERE expire < ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((UNIX_EPOCH.elapsed().unwrap().as_secs(),)).unwrap();
+            let sql = strip_str! {"
+                SELECT token, eid, exclusive, deep, owner, expire FROM dav_locks WHERE substr(eid, 1, length(?1)) = ?1
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
-            let mut v = eid.into_bytes();
-            v.extend(b"/%/%");
             let v2s = |v| String::from_utf8(v).unwrap();
-            stmd.query_map((&v[..v.len() - b"/%".len()], recursive, &v), |r| {
-                Ok((v2s(r.get(0)?), r.get(1)?, r.get(2)?, r.get(3)?))
+            let uid = uid + ":";
+            stmd.query_map((uid.as_bytes(),), |r| {
+                Ok(lock::Lock {
+                    token: v2s(r.get(0)?),
+                    eid: v2s(r.get(1)?),
+                    exclusive: r.get(2)?,
+                    deep: r.get(3)?,
+                    owner: v2s(r.get(4)?),
+                    expire: r.get(5)?,
+                })
             })
             .unwrap()
             .map(|v| v.unwrap())
@@ -119,42 +1167,171 @@ mod db {
         })
         .await
     }
-    pub async fn del_entry_recursive(eid: String) {
+    pub async fn set_lock(lock: &lock::Lock) {
+        let v = (
+            lock.token.to_owned(),
+            lock.eid.to_owned(),
+            lock.exclusive,
+            lock.deep,
+        );
+        let v = (v.0, v.1, v.2, v.3, lock.owner.to_owned(), lock.expire);
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                REPLACE INTO dav_locks VALUES (?, ?, ?, ?, ?, ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let (token, eid, exclusive, deep, owner, expire) = v;
+            let v = (
+                token.as_bytes(),
+                eid.as_bytes(),
+                exclusive,
+                deep,
+                owner.as_bytes(),
+                expire,
+            );
+            stmd.execute(v).unwrap();
+        })
+        .await
+    }
+    pub async fn del_lock(token: String) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM dav_locks WHERE token = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((token.as_bytes(),)).unwrap();
+        })
+        .await
+    }
+    pub async fn list_usage() -> Vec<(String, u64)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT substr(eid, 1, instr(eid, ':') - 1) AS uid, sum(size)
+                FROM (SELECT eid, size FROM dav_entries UNION ALL SELECT eid, size FROM dav_versions) GROUP BY uid
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            stmd.query_map((), |r| Ok((v2s(r.get(0)?), r.get(1)?)))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        })
+        .await
+    }
+    pub async fn get_share(token: String) -> Option<share::Share> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT token, eid, scope, auth, expire, remain FROM dav_shares WHERE token = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((token.as_bytes(),), share::Share::from_row)
+                .ok()
+        })
+        .await
+    }
+    /// Returns the shares of the user's entries.
+    pub async fn list_shares(uid: String) -> Vec<share::Share> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT token, eid, scope, auth, expire, remain FROM dav_shares WHERE substr(eid, 1, length(?1)) = ?1 ORDER BY eid
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_map(((uid + ":").as_bytes(),), share::Share::from_row)
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        })
+        .await
+    }
+    pub async fn set_share(share: share::Share) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                REPLACE INTO dav_shares VALUES (?, ?, ?, ?, ?, ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((
+                share.token.as_bytes(),
+                share.eid.as_bytes(),
+                share.upload,
+                share.auth.as_bytes(),
+                share.expire,
+                share.remain,
+            ))
+            .unwrap();
+        })
+        .await
+    }
+    /// Delete the share of the user's entry, returns false if not found.
+    pub async fn del_share(uid: String, token: String) -> bool {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM dav_shares WHERE token = ?1 AND substr(eid, 1, length(?2)) = ?2
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let prefix = uid + ":";
+            stmd.execute((token.as_bytes(), prefix.as_bytes())).unwrap() != 0
+        })
+        .await
+    }
+    /// Count a download, returns false if the limit is reached.
+    pub async fn take_share_download(token: String) -> bool {
         DB.call(move |db| {
             let sql = strip_str! {"
-                DELETE FROM dav_entries WHERE eid = ? OR eid LIKE ?
+                UPDATE dav_shares SET remain = remain - 1 WHERE token = ? AND (remain IS NULL OR remain > 0)
             "};
             let mut stmd = db.prepare_cached(sql).unwrap();
-            let mut v = eid.into_bytes();
-            v.extend(b"/%");
-            stmd.execute((&v[..v.len() - b"/%".len()], &v)).unwrap();
+            stmd.execute((token.as_bytes(),)).unwrap() != 0
+        })
+        .await
+    }
+    /// Delete the entry and the children, or move them into the trash if `trash`.
+    pub async fn del_entry_recursive(eid: String, trash: bool) {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            match trash {
+                true => trash_tree(&tx, &eid, UNIX_EPOCH.elapsed().unwrap().as_secs()),
+                false => del_tree(&tx, &eid),
+            }
+            tx.commit().unwrap();
         })
         .await
     }
 }
 
 async fn dav_handler(prefix: &'static str, mut req: Request) -> anyhow::Result<Response> {
-    const MAX_SIZE: usize = 1024 * 1024 * 16;
+    const MAX_BODY_SIZE: usize = 1024 * 1024; // the xml bodies
     let method = req.method().as_str();
     if method == "OPTIONS" {
         return Ok(([
-            ("allow", "OPTIONS, DELETE, PROPPATCH, COPY, MOVE, PROPFIND"),
-            ("dav", "1, 2"),
+            (
+                "allow",
+                "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, MKCALENDAR, COPY, MOVE, PROPFIND, PROPPATCH, REPORT, LOCK, UNLOCK",
+            ),
+            ("dav", "1, 2, calendar-access, addressbook"),
         ])
         .into_response());
     }
     let Some(auth) = req.headers().get(AUTHORIZATION) else {
         return Ok((StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Basic")]).into_response());
     };
-    let uid = db::get_user_uid(auth.to_str()?.to_owned()).await.e()?;
-    let pathname = req.uri().path().trim_start_matches(prefix); // safety: xss will not happen because uri is encoded already
-    let eid = uid.to_owned() + ":" + pathname.trim_end_matches('/');
+    let Some((uid, data_key)) = user::authenticate(auth.as_bytes(), true).await else {
+        return Ok((StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Basic")]).into_response());
+    };
+    let pathname = req.uri().path().strip_prefix(prefix).and_then(path::decode);
+    let Some(pathname) = pathname else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    let eid = uid.to_owned() + ":" + &pathname;
     match method {
-        "PUT" | "MKCOL" => {
-            if let Some((_, _, flag)) = db::get_entry_meta(eid.to_owned()).await {
+        "PUT" | "MKCOL" | "MKCALENDAR" => {
+            let old = db::get_entry_meta(eid.to_owned()).await;
+            if let Some((_, _, flag)) = old {
                 if flag & db::ENTRY_READ_ONLY != 0 {
                     return Err(anyhow::anyhow!("read only"));
                 }
+                if method != "PUT" || flag & db::ENTRY_DIR != 0 {
+                    return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+                }
             }
             let (parent, _cur_name) = eid.rsplit_once('/').e()?;
             let (_, _, flag) = db::get_entry_meta(parent.to_owned()).await.e()?;
@@ -164,123 +1341,247 @@ async fn dav_handler(prefix: &'static str, mut req: Request) -> anyhow::Result<R
             if flag & db::ENTRY_DIR == 0 {
                 return Err(anyhow::anyhow!("parent is not dir"));
             }
+            // the calendars and address books hold objects only
+            if method != "PUT" && flag & db::ENTRY_CALENDAR != 0 {
+                return Ok(caldav::error(
+                    caldav::CALDAV,
+                    "calendar-collection-location-ok",
+                ));
+            }
+            if method != "PUT" && flag & db::ENTRY_ADDRESSBOOK != 0 {
+                return Ok(caldav::error(
+                    caldav::CARDDAV,
+                    "addressbook-collection-location-ok",
+                ));
+            }
+            // creating a new entry modifies the parent's members
+            let targets = match old {
+                Some(_) => vec![(&eid[..], false)],
+                None => vec![(&eid[..], false), (parent, false)],
+            };
+            if let Some(res) = lock::check(prefix, req.headers(), &eid, &targets).await {
+                return Ok(res);
+            }
+            let old_size = old.map(|v| v.1).unwrap_or(0);
             let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
             match method {
                 "PUT" => {
-                    let data = axum::body::to_bytes(req.into_body(), MAX_SIZE).await?;
-                    let size = data.len() as _;
-                    db::set_entry(eid, data, time, size, 0).await;
+                    // reject the lost updates, by `If-Match` with the etag got before
+                    let etag = match old {
+                        Some(_) => Some(blob::etag(&db::list_blocks(eid.to_owned()).await)),
+                        None => None,
+                    };
+                    if !serve::precondition(req.headers(), etag.as_deref()) {
+                        return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+                    }
+                    let used = db::get_usage(uid.to_owned()).await;
+                    let quota = props::quota(&uid).await;
+                    let limit = quota.saturating_sub(used.saturating_sub(old_size));
+                    let len = req.headers().get(CONTENT_LENGTH);
+                    let len = len.and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+                    if len.is_some_and(|v| v > limit) {
+                        return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response());
+                    }
+                    let body = match flag & (db::ENTRY_CALENDAR | db::ENTRY_ADDRESSBOOK) {
+                        0 => req.into_body(),
+                        _ => {
+                            let body =
+                                axum::body::to_bytes(req.into_body(), caldav::MAX_OBJECT_SIZE);
+                            let Ok(body) = body.await else {
+                                return Ok(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+                            };
+                            if let Some(res) = caldav::check_object(flag, &body) {
+                                return Ok(res);
+                            }
+                            Body::from(body)
+                        }
+                    };
+                    let key = data_key.as_ref().map(crypt::Key::new);
+                    let Some((hashes, size)) = blob::write(body, limit, key.as_ref()).await? else {
+                        return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response());
+                    };
+                    let etag = HeaderValue::try_from(blob::etag(&hashes))?;
+                    let keep = history::max_versions().await;
+                    db::set_file(eid, hashes, time, size, 0, keep).await;
+                    let status = match old {
+                        Some(_) => StatusCode::NO_CONTENT,
+                        None => StatusCode::CREATED,
+                    };
+                    Ok((status, [(ETAG, etag)]).into_response())
                 }
-                "MKCOL" => {
-                    db::set_entry(eid, Bytes::new(), time, 0, db::ENTRY_DIR).await;
+                "MKCOL" | "MKCALENDAR" => {
+                    let is_calendar = method == "MKCALENDAR";
+                    let body = axum::body::to_bytes(req.into_body(), MAX_BODY_SIZE).await?;
+                    let body = std::str::from_utf8(&body)?;
+                    let Some((flag, props)) = caldav::parse_mkcol(is_calendar, body) else {
+                        return Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+                    };
+                    db::set_entry(eid.to_owned(), Bytes::new(), time, 0, flag).await;
+                    if !props.is_empty() {
+                        db::set_props(eid, props).await;
+                    }
+                    Ok(StatusCode::CREATED.into_response())
                 }
                 _ => unreachable!(),
             }
-            Ok(StatusCode::CREATED.into_response())
         }
         "DELETE" => {
             let (_, _, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
             if flag & db::ENTRY_READ_ONLY != 0 {
                 return Err(anyhow::anyhow!("read only"));
             }
-            db::del_entry_recursive(eid.to_owned()).await; // TODO
-            Ok(StatusCode::OK.into_response())
+            let (parent, _) = eid.rsplit_once('/').e()?;
+            let targets = [(&eid[..], true), (parent, false)];
+            if let Some(res) = lock::check(prefix, req.headers(), &eid, &targets).await {
+                return Ok(res);
+            }
+            let trash = history::trash_days().await != 0;
+            db::del_entry_recursive(eid.to_owned(), trash).await;
+            Ok(StatusCode::NO_CONTENT.into_response())
         }
         "COPY" | "MOVE" => {
-            let (time, size, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
-            if flag & db::ENTRY_READ_ONLY != 0 {
-                return Err(anyhow::anyhow!("read only"));
+            let is_move = method == "MOVE";
+            let (_, _, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
+            if is_move && flag & db::ENTRY_READ_ONLY != 0 {
+                return Ok(StatusCode::FORBIDDEN.into_response());
             }
-            #[allow(clippy::unnecessary_to_owned)] // false positive
-            let dest = Uri::from_maybe_shared(req.headers().get("destination").e()?.to_owned())?;
-            let dest = dest.path().trim_start_matches(prefix);
-            let dest_eid = uid + ":" + dest.trim_end_matches('/');
-            if flag & db::ENTRY_DIR == 0 {
-                let data = db::get_entry_data(eid.to_owned()).await.unwrap();
-                db::set_entry(dest_eid, Bytes::from(data), time, size, flag).await;
-                if method == "MOVE" {
-                    db::del_entry_recursive(eid).await; // TODO: opti
+            let deep = match req.headers().get("depth").map(|v| v.as_bytes()) {
+                Some(b"infinity") | None => true,
+                Some(b"0") if !is_move => false,
+                _ => return Ok(StatusCode::BAD_REQUEST.into_response()),
+            };
+            let dest = req.headers().get("destination").e()?.as_bytes();
+            let Some(dest) = path::strip_url(prefix, dest) else {
+                return Ok(StatusCode::BAD_GATEWAY.into_response()); // not on this server
+            };
+            let Some(dest) = path::decode(dest) else {
+                return Ok(StatusCode::BAD_REQUEST.into_response());
+            };
+            let dest_eid = uid + ":" + &dest;
+            if lock::is_within(&dest_eid, &eid) || (is_move && lock::is_within(&eid, &dest_eid)) {
+                return Ok(StatusCode::FORBIDDEN.into_response());
+            }
+            let no_overwrite = req.headers().get("overwrite").is_some_and(|v| v == "F");
+            let dest_meta = db::get_entry_meta(dest_eid.to_owned()).await;
+            if dest_meta.is_some() && no_overwrite {
+                return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+            }
+            let (dest_parent, _) = dest_eid.rsplit_once('/').e()?;
+            match db::get_entry_meta(dest_parent.to_owned()).await {
+                Some((_, _, flag)) if flag & db::ENTRY_DIR != 0 => {
+                    if flag & db::ENTRY_READ_ONLY != 0 {
+                        return Ok(StatusCode::FORBIDDEN.into_response());
+                    }
+                }
+                _ => return Ok(StatusCode::CONFLICT.into_response()),
+            }
+            let (parent, _) = eid.rsplit_once('/').e()?;
+            let mut targets = vec![(&dest_eid[..], true), (dest_parent, false)];
+            if is_move {
+                targets.extend([(&eid[..], true), (parent, false)]);
+            }
+            if let Some(res) = lock::check(prefix, req.headers(), &eid, &targets).await {
+                return Ok(res);
+            }
+            // all or nothing, the read only entries inside fail the whole request
+            let depth = if deep { u32::MAX as _ } else { 0 };
+            let tree = db::list_entry_tree(eid.to_owned(), depth).await;
+            let mut failed = Vec::new();
+            if is_move {
+                let v = tree.iter().filter(|v| v.3 & db::ENTRY_READ_ONLY != 0);
+                failed.extend(v.map(|v| (v.0.to_owned(), v.3)));
+            }
+            let mut replaced = 0;
+            if dest_meta.is_some() {
+                let dest_tree = db::list_entry_tree(dest_eid.to_owned(), u32::MAX as _).await;
+                replaced = dest_tree.iter().map(|v| v.2).sum();
+                let v = dest_tree
+                    .into_iter()
+                    .filter(|v| v.3 & db::ENTRY_READ_ONLY != 0);
+                failed.extend(v.map(|v| (v.0, v.3)));
+            }
+            if failed.iter().any(|v| v.0 == dest_eid) {
+                return Ok(StatusCode::FORBIDDEN.into_response());
+            }
+            if !failed.is_empty() {
+                let mut body = String::new();
+                body += r#"<?xml version="1.0" encoding="utf-8" ?><D:multistatus xmlns:D="DAV:">"#;
+                for (eid, flag) in failed {
+                    body += "<D:response><D:href>";
+                    body += prefix;
+                    path::encode(&mut body, eid.split_once(':').e()?.1);
+                    if flag & db::ENTRY_DIR != 0 {
+                        body += "/";
+                    }
+                    body += "</D:href><D:status>HTTP/1.1 403 Forbidden</D:status></D:response>";
                 }
-            } else {
-                return Err(anyhow::anyhow!("is dir, todo")); // TODO
+                body += "</D:multistatus>";
+                let headers = [(CONTENT_TYPE, "application/xml; charset=utf-8")];
+                return Ok((StatusCode::MULTI_STATUS, headers, body).into_response());
+            }
+            let trash = history::trash_days().await != 0;
+            if !is_move {
+                let (uid, _) = eid.split_once(':').e()?;
+                let size: u64 = tree.iter().map(|v| v.2).sum();
+                // the replaced dest is freed, unless it goes to the trash and still counts
+                let freed = if trash { 0 } else { replaced };
+                let used = db::get_usage(uid.to_owned()).await.saturating_sub(freed);
+                if used + size > props::quota(uid).await {
+                    return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response());
+                }
+            }
+            db::copy_entry_tree(eid, dest_eid, deep, is_move, trash).await;
+            match dest_meta {
+                Some(_) => Ok(StatusCode::NO_CONTENT.into_response()),
+                None => Ok(StatusCode::CREATED.into_response()),
             }
-            Ok(StatusCode::OK.into_response())
         }
         "GET" | "HEAD" => {
             let (time, size, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
+            let key = data_key.as_ref().map(crypt::Key::new);
             if flag & db::ENTRY_DIR != 0 {
-                return Err(anyhow::anyhow!("is dir"));
+                let is_head = method == "HEAD";
+                return archive::get(eid, req.uri().query(), is_head, key).await;
             }
             if flag & db::ENTRY_HREF != 0 {
                 let v = HeaderValue::try_from(db::get_entry_data(eid).await.e()?)?;
                 return Ok((StatusCode::TEMPORARY_REDIRECT, [(LOCATION, v)]).into_response());
             }
-            if let Some(v) = req.headers().get(IF_MODIFIED_SINCE) {
-                let t = httpdate::parse_http_date(v.to_str()?)?;
-                let t = t.duration_since(UNIX_EPOCH)?.as_secs();
-                if t >= time {
-                    return Ok(StatusCode::NOT_MODIFIED.into_response());
-                }
-            }
-            let mut res = match method {
-                "GET" => axum::body::Body::from(db::get_entry_data(eid).await.e()?),
-                "HEAD" => axum::body::Body::empty(),
-                _ => unreachable!(),
-            }
-            .into_response();
-            let stamp = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(time));
-            res.headers_mut().insert(LAST_MODIFIED, stamp.try_into()?);
-            res.headers_mut().insert(CONTENT_LENGTH, size.into());
-            if flag & db::ENTRY_GZIP != 0 {
-                let v = HeaderValue::from_static("gzip");
-                res.headers_mut().insert(CONTENT_ENCODING, v);
-            }
-            if flag & db::ENTRY_STABLE != 0 {
-                let v = HeaderValue::from_static("max-age=600,stale-while-revalidate=31536000");
-                res.headers_mut().insert(CACHE_CONTROL, v);
-            }
-            Ok(res)
+            let meta = (time, size, flag);
+            Ok(serve::get(eid, req.headers(), method == "HEAD", meta, key).await)
         }
         "PROPFIND" => {
-            let mut body = String::new();
-            body += r#"<?xml version="1.0" encoding="utf-8" ?><D:multistatus xmlns:D="DAV:">"#;
-            let (time, size, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
-            let mut entries = Vec::new();
-            entries.push((eid.to_owned(), time, size, flag));
-            // depth > 1 is ignored, without depth (like quota-available-bytes) is unsupported
-            if flag & db::ENTRY_DIR != 0 && req.headers().get("depth").e()? != "0" {
-                entries.append(&mut db::list_entry_meta(eid.to_owned(), false).await);
-            }
-            for (eid, time, size, flag) in entries {
-                let (uid, pathname) = eid.split_once(':').e()?;
-                body += "<D:response><D:href>";
-                body += prefix;
-                body += pathname;
-                if flag & db::ENTRY_DIR == 0 {
-                    body += "</D:href><D:propstat><D:prop><D:displayname>";
-                    body += pathname.rsplit_once('/').unwrap_or_default().1;
-                    body += "</D:displayname><D:getcontentlength>";
-                    body += &size.to_string();
-                    body += "</D:getcontentlength><D:getlastmodified>";
-                    body += &httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(time));
-                    body += r#"</D:getlastmodified></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"#;
-                } else {
-                    body += "/";
-                    body += "</D:href><D:propstat><D:prop><D:displayname>";
-                    body += pathname.rsplit_once('/').unwrap_or_default().1;
-                    body += "</D:displayname><D:getlastmodified>";
-                    body += &httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(time));
-                    body += r#"</D:getlastmodified><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"#;
-                }
+            let depth = match req.headers().get("depth").map(|v| v.as_bytes()) {
+                Some(b"0") => 0,
+                Some(b"1") => 1,
+                _ => u32::MAX as _, // infinity
+            };
+            let body = axum::body::to_bytes(req.into_body(), MAX_BODY_SIZE).await?;
+            props::propfind(prefix, eid, depth, std::str::from_utf8(&body)?).await
+        }
+        "REPORT" => {
+            let depth = match req.headers().get("depth").map(|v| v.as_bytes()) {
+                Some(b"1") => 1,
+                Some(b"infinity") => u32::MAX as _,
+                _ => 0,
+            };
+            let body = axum::body::to_bytes(req.into_body(), MAX_BODY_SIZE).await?;
+            let key = data_key.as_ref().map(crypt::Key::new);
+            caldav::report(prefix, eid, depth, std::str::from_utf8(&body)?, key).await
+        }
+        "PROPPATCH" => {
+            if let Some(res) = lock::check(prefix, req.headers(), &eid, &[(&eid, false)]).await {
+                return Ok(res);
             }
-            body += r#"</D:multistatus>"#;
-            Ok((
-                StatusCode::MULTI_STATUS,
-                [(CONTENT_TYPE, "text/xml; charset=utf-8")],
-                body,
-            )
-                .into_response())
+            let body = axum::body::to_bytes(req.into_body(), MAX_BODY_SIZE).await?;
+            props::proppatch(prefix, eid, std::str::from_utf8(&body)?).await
         }
+        "LOCK" => {
+            let headers = req.headers().clone();
+            let body = axum::body::to_bytes(req.into_body(), MAX_BODY_SIZE).await?;
+            lock::lock(prefix, eid, &headers, std::str::from_utf8(&body)?).await
+        }
+        "UNLOCK" => lock::unlock(eid, req.headers()).await,
         _ => Err(anyhow::anyhow!("unimplemented method")),
     }
 }
@@ -290,36 +1591,156 @@ async fn api_handler(mut req: Request) -> anyhow::Result<Response> {
         let v = req.headers_mut().remove(k);
         v.and_then(|v| Some(v.to_str().ok()?.to_owned())).e()
     };
-    match get_field("op_")?.as_str() {
-        "signup" => {
-            return Err(anyhow::anyhow!("not allowed"));
-            let uid = get_field("uid_")?;
-            let valid_uid_char = |&c: &u8| c.is_ascii_alphanumeric() || c == b'-' || c == b'_';
-            if !uid.as_bytes().iter().all(valid_uid_char) {
-                return Err(anyhow::anyhow!("uid contains invalid chars"));
-            }
-            let auth = get_field("auth_")?;
-            db::set_user(uid.to_owned(), auth.to_owned()).await; // TODO: reject overwrite
-            let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
-            db::set_entry(uid.to_owned() + ":", Bytes::new(), time, 0, db::ENTRY_DIR).await;
+    // the users are created by admin, and the account can't be managed with app passwords
+    let op = get_field("op_")?;
+    let auth = get_field("auth_")?;
+    let is_account_op = !matches!(
+        op.as_str(),
+        "apply_flag_recursive"
+            | "search"
+            | "zip"
+            | "list_upload_parts"
+            | "upload_part"
+            | "finish_upload"
+    );
+    let (uid, data_key) = user::authenticate(auth.as_bytes(), !is_account_op)
+        .await
+        .e()?;
+    match op.as_str() {
+        "change_password" => {
+            user::set_password(uid, &get_field("password_")?, data_key).await?;
+        }
+        "create_app_password" => {
+            let name = get_field("name_")?;
+            let password = user::create_app_password(uid, name, data_key).await;
+            return Ok(password.into_response());
+        }
+        "enable_encryption" => {
+            let (_, password) = user::parse_basic(auth.as_bytes()).e()?;
+            crypt::enable(uid, password).await?;
+        }
+        "disable_encryption" => {
+            crypt::disable(uid, data_key).await?;
+        }
+        "delete_app_password" => {
+            if !user::delete_app_password(uid, get_field("name_")?).await {
+                return Err(anyhow::anyhow!("not found"));
+            }
+        }
+        "create_share" => {
+            let pathname = path::decode(&get_field("path_")?).e()?;
+            let upload = get_field("scope_")? == "upload";
+            let password = get_field("password_").ok();
+            let expire = get_field("expire_").ok().map(|v| v.parse()).transpose()?;
+            let limit = get_field("limit_").ok().map(|v| v.parse()).transpose()?;
+            let eid = uid + ":" + &pathname;
+            let token = share::create(eid, upload, password, expire, limit).await?;
+            return Ok(token.into_response());
+        }
+        "list_shares" => {
+            let headers = [(CONTENT_TYPE, "application/json")];
+            return Ok((headers, share::list(uid).await).into_response());
+        }
+        "delete_share" => {
+            if !db::del_share(uid, get_field("token_")?).await {
+                return Err(anyhow::anyhow!("not found"));
+            }
+        }
+        "list_versions" => {
+            let eid = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            let headers = [(CONTENT_TYPE, "application/json")];
+            return Ok((headers, history::list_versions(eid).await).into_response());
+        }
+        "restore_version" => {
+            let eid = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            history::restore_version(eid, get_field("ver_")?.parse()?).await?;
+        }
+        "delete_versions" => {
+            let eid = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            let vid = get_field("ver_").ok().map(|v| v.parse()).transpose()?;
+            if db::del_versions(eid, vid).await == 0 {
+                return Err(anyhow::anyhow!("not found"));
+            }
+        }
+        "list_trash" => {
+            let headers = [(CONTENT_TYPE, "application/json")];
+            return Ok((headers, history::list_trash(uid).await).into_response());
+        }
+        "restore_trash" => {
+            history::restore_trash(uid, get_field("id_")?.parse()?).await?;
+        }
+        "purge_trash" => {
+            let tid = get_field("id_")?.parse()?;
+            if db::purge_trash(vec![(uid, tid)]).await == 0 {
+                return Err(anyhow::anyhow!("not found"));
+            }
+        }
+        "empty_trash" => {
+            let list = db::list_trash(uid.to_owned()).await;
+            let items = list.into_iter().map(|v| (uid.to_owned(), v.0)).collect();
+            db::purge_trash(items).await;
+        }
+        "search" => {
+            let eid = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            let query = get_field("query_")?; // encoded, as the header is ascii
+            let query = percent_encoding::percent_decode_str(&query).decode_utf8()?;
+            let list = db::search_entry_tree(eid, query.into_owned(), 500).await;
+            let list = list.into_iter().map(|(eid, time, size, flag)| {
+                let mut pathname = String::new();
+                path::encode(&mut pathname, eid.split_once(':').unwrap().1);
+                let is_dir = flag & db::ENTRY_DIR != 0;
+                serde_json::json!({ "path": pathname, "dir": is_dir, "size": size, "time": time })
+            });
+            let headers = [(CONTENT_TYPE, "application/json")];
+            let list = serde_json::Value::from_iter(list).to_string();
+            return Ok((headers, list).into_response());
+        }
+        "zip" => {
+            // the json array of the encoded paths under the dir, empty path for the whole dir
+            let dir = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            let names: Vec<String> = serde_json::from_str(&get_field("names_")?)?;
+            let mut entries = Vec::new();
+            for name in names {
+                let eid = dir.to_owned() + &path::decode(&name).e()?;
+                entries.extend(db::list_entry_tree(eid, u32::MAX as _).await);
+            }
+            let key = data_key.as_ref().map(crypt::Key::new);
+            let body = archive::build(archive::Format::Zip, dir, entries, true, key);
+            return Ok(([(CONTENT_TYPE, "application/zip")], body).into_response());
+        }
+        "list_upload_parts" => {
+            let eid = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            let headers = [(CONTENT_TYPE, "application/json")];
+            let list = upload::list_parts(eid, get_field("id_")?).await;
+            return Ok((headers, list).into_response());
+        }
+        "upload_part" => {
+            let eid = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            let (id, idx) = (get_field("id_")?, get_field("idx_")?.parse()?);
+            let key = data_key.as_ref().map(crypt::Key::new);
+            upload::put_part(eid, id, idx, req.into_body(), key.as_ref()).await?;
+        }
+        "finish_upload" => {
+            let eid = uid + ":" + &path::decode(&get_field("path_")?).e()?;
+            let (id, size) = (get_field("id_")?, get_field("size_")?.parse()?);
+            upload::finish(eid, id, size).await?;
         }
         "apply_flag_recursive" => {
             let eid = get_field("eid_")?;
+            let (eid_uid, pathname) = eid.split_once(':').e()?;
+            let eid = eid_uid.to_owned() + ":" + &path::decode(pathname).e()?;
             let not = get_field("not_").is_ok();
             let apply_dir = get_field("apply_dir_").is_ok(); // apply flag on dir, or only non-dir
             let trigger_flag: u64 = get_field("flag_")?.parse()?;
-            let auth = get_field("auth_")?;
-            let eid_uid_prefix = db::get_user_uid(auth).await.e()? + ":";
+            let eid_uid_prefix = uid + ":";
             if !eid.starts_with(&eid_uid_prefix) {
                 return Err(anyhow::anyhow!("auth failed"));
             }
-            let mut list = Vec::new();
-            let (_, _, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
-            list.push((eid.to_owned(), flag));
-            for (eid, _, _, flag) in db::list_entry_meta(eid, true).await {
-                list.push((eid, flag));
+            let list = db::list_entry_tree(eid, u32::MAX as _).await;
+            if list.is_empty() {
+                return Err(anyhow::anyhow!("not found"));
             }
-            for (eid, flag) in list {
+            for (eid, _, _, flag, _) in list {
                 if flag & db::ENTRY_DIR == 0 || apply_dir {
                     let new_flag = match not {
                         true => flag & !trigger_flag,
@@ -334,15 +1755,27 @@ async fn api_handler(mut req: Request) -> anyhow::Result<Response> {
     Ok(StatusCode::OK.into_response())
 }
 
-pub fn service() -> Router {
-    crate::utils::block_on(db::init());
-    const DAV_PATH_PREFIX: &str = "/dav";
+/// Returns `(uid, bytes)` of each user.
+pub async fn storage_usage() -> Vec<(String, u64)> {
+    if !crate::units::is_enabled("dav") {
+        return Vec::new(); // the table may not exist
+    }
+    db::list_usage().await
+}
+
+static SCRIPT: &Asset = asset!("page.js");
+
+static PAGE: LazyLock<Asset> = LazyLock::new(|| {
+    let page = template!("page.html").render(&serde_json::json!({ "script": SCRIPT.url() }));
+    Asset::new("page.html", page.into_bytes())
+});
+
+const DAV_PATH_PREFIX: &str = "/dav";
+
+fn service() -> Router {
     let any_router = axum::routing::any(|req: Request| async {
         if req.uri().path() == DAV_PATH_PREFIX && req.method() == "GET" {
-            let mut r = Html((include_src!("page.html") as [_; 1])[0]).into_response();
-            let v = HeaderValue::from_static("max-age=600,stale-while-revalidate=31536000");
-            r.headers_mut().insert(CACHE_CONTROL, v);
-            r
+            PAGE.serve(req.headers())
         } else if req.uri().path() == DAV_PATH_PREFIX && req.method() == "POST" {
             let r = api_handler(req).await; // use care!() for debugging
             r.unwrap_or_else(|_| StatusCode::BAD_REQUEST.into_response()) // in order to simplify implementation, return 400 for any error
@@ -351,8 +1784,69 @@ pub fn service() -> Router {
             r.unwrap_or_else(|_| StatusCode::NOT_FOUND.into_response()) // 404 here because 400 caused some client to prompt error
         }
     });
+    let share_router = axum::routing::any(|req: Request| async {
+        let r = share::handler(req).await;
+        r.unwrap_or_else(|_| StatusCode::NOT_FOUND.into_response())
+    });
+    // RFC 6764, the clients find the server by the domain
+    let well_known =
+        axum::routing::any(|| async { (StatusCode::MOVED_PERMANENTLY, [(LOCATION, "/dav/")]) });
     Router::new()
+        .route("/.well-known/caldav", well_known.clone())
+        .route("/.well-known/carddav", well_known)
         .route("/dav", any_router.clone())
         .route("/dav/", any_router.clone())
         .route("/dav/*path", any_router)
+        .route("/dav-share/:token", share_router.clone())
+        .route("/dav-share/:token/*path", share_router)
+}
+
+async fn gc() -> anyhow::Result<()> {
+    history::expire().await; // first, so the chunks are freed in this run
+    upload::expire().await;
+    blob::gc().await
+}
+
+fn job() -> Job {
+    Job {
+        name: "dav_gc",
+        crons: crons!("04:30:00"),
+        zone: &8,
+        catch_up: CatchUp::Skip,
+        jitter: Duration::ZERO,
+        timeout: Duration::from_secs(600),
+        retries: 0,
+        retry_interval: Duration::ZERO,
+        run: || Box::pin(gc()),
+    }
+}
+
+pub struct Dav;
+
+impl crate::units::Unit for Dav {
+    fn name(&self) -> &'static str {
+        "dav"
+    }
+    fn migrate(&self) -> crate::units::BoxFuture<()> {
+        Box::pin(db::init())
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn jobs(&self) -> Vec<Job> {
+        vec![job()]
+    }
+    fn assets(&self) -> Vec<&'static Asset> {
+        vec![SCRIPT]
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &[
+            "dav_user (uid:password)",
+            "dav_user_delete (uid)",
+            "dav_user_quota (uid:bytes, empty bytes for dav_quota)",
+            "dav_quota (bytes per user, default 1 GiB)",
+            "dav_versions (count per file, default 10)",
+            "dav_trash_days (days, default 30)",
+        ]
+    }
 }
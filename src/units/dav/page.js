@@ -0,0 +1,606 @@
+"use strict";
+
+// url = "https://127.0.0.1:9304/dav#/nextchat/href.txt"
+
+const setStage = (stage) => {
+  for (const v of document.documentElement.attributes)
+    if (v.name.startsWith("stage_"))
+      document.documentElement.removeAttribute(v.name);
+  document.documentElement.setAttribute(stage, "");
+};
+
+const readableSize = (v /* bytes */) => {
+  let unit = "B";
+  if (v >= 2 ** 40 * 1e3) throw "too big";
+  else if (v >= 2 ** 30 * 1e3) (unit = "TiB"), (v /= 2 ** 40);
+  else if (v >= 2 ** 20 * 1e3) (unit = "GiB"), (v /= 2 ** 30);
+  else if (v >= 2 ** 10 * 1e3) (unit = "MiB"), (v /= 2 ** 20);
+  else if (v >= 2 ** 0 * 1e3) (unit = "KiB"), (v /= 2 ** 10);
+  v = ("" + v).slice(0, 4);
+  if (v.endsWith(".")) v = v.slice(0, 3);
+  return `${v} ${unit}`;
+};
+
+const timeStamp = (t) => {
+  const zp = (n) => ("" + n).padStart(2, "0");
+  const d = `${t.getFullYear()}.${zp(t.getMonth() + 1)}.${zp(t.getDate())}`;
+  return d + ` ${zp(t.getHours())}:${zp(t.getMinutes())}`;
+};
+
+const curPath = () => location.pathname + location.hash.slice(1);
+
+// the names are decoded in server, so encode each segment but keep the slashes
+const encodePath = (v) => v.split("/").map(encodeURIComponent).join("/");
+
+const escapeHtml = (v) => v.replace(/[<>&"']/g, (c) => `&#${c.charCodeAt(0)};`);
+
+
+$login.onclick = async () => {
+  localStorage.davAuth = "Basic " + btoa($uid.value + ":" + $upw.value);
+  location.hash = "#/";
+  onhashchange();
+};
+
+$upw.onkeypress = ({ key }) => {
+  if (key === "Enter") $login.click();
+};
+
+$logout.onclick = async () => {
+  delete localStorage.davAuth;
+  location.hash = "";
+  location.reload();
+};
+
+$passwd.onclick = async () => {
+  const password = prompt("new password");
+  if (!password) return;
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: {
+      op_: "change_password",
+      auth_: localStorage.davAuth,
+      password_: password,
+    },
+  });
+  if (!r.ok) throw alert(r.status);
+  const uid = atob(localStorage.davAuth.slice("Basic ".length)).split(":")[0];
+  localStorage.davAuth = "Basic " + btoa(uid + ":" + password);
+  alert("password changed");
+};
+
+// the app passwords are for webdav clients, which can't manage the account
+$appPasswd.onclick = async () => {
+  const name = prompt("app name, prefix with '-' to delete", "phone");
+  if (!name) return;
+  const del = name.startsWith("-");
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: {
+      op_: del ? "delete_app_password" : "create_app_password",
+      auth_: localStorage.davAuth,
+      name_: del ? name.slice(1) : name,
+    },
+  });
+  if (!r.ok) throw alert(r.status);
+  alert(del ? "deleted" : `password for ${name}: ${await r.text()}`);
+};
+
+$encrypt.onclick = async () => {
+  const hint = "encrypt the content? app passwords and shares will be removed";
+  const enable = confirm(hint + "\n\ncancel to choose decrypting");
+  if (!enable && !confirm("decrypt the content?")) return;
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: {
+      op_: enable ? "enable_encryption" : "disable_encryption",
+      auth_: localStorage.davAuth,
+    },
+  });
+  if (!r.ok) throw alert(r.status);
+  alert(enable ? "encrypted" : "decrypted");
+};
+
+// the media are previewed, other files are edited as text
+const MEDIA = {
+  img: /\.(png|jpe?g|gif|webp|avif|svg|bmp|ico)$/i,
+  audio: /\.(mp3|m4a|aac|ogg|oga|opus|flac|wav)$/i,
+  video: /\.(mp4|m4v|webm|ogv|mov)$/i,
+};
+
+const mediaTag = (path) => Object.keys(MEDIA).find((k) => MEDIA[k].test(path));
+
+onhashchange = async () => {
+  if (curPath().endsWith("/")) {
+    await asList();
+    setStage("stage_list_");
+  } else if (mediaTag(curPath())) {
+    await asView();
+    setStage("stage_view_");
+  } else {
+    await asEdit();
+    setStage("stage_edit_");
+  }
+};
+
+const asEdit = async () => {
+  const r = await fetch(curPath(), {
+    method: "GET",
+    headers: { authorization: localStorage.davAuth },
+  });
+  if (!r.ok) throw alert(r.status);
+  $edit.value = await r.text();
+};
+
+// the media elements can't send the auth header, so load as blob
+const asView = async () => {
+  const r = await fetch(curPath(), {
+    method: "GET",
+    headers: { authorization: localStorage.davAuth },
+  });
+  if (!r.ok) throw alert(r.status);
+  URL.revokeObjectURL($view.firstElementChild?.src);
+  const el = document.createElement(mediaTag(curPath()));
+  el.src = URL.createObjectURL(await r.blob());
+  el.controls = true;
+  $view.replaceChildren(el);
+};
+
+$back.onclick = () => {
+  location.hash = location.hash.replace(/[^/]*$/, "");
+  onhashchange();
+};
+
+const listRow = (href, name, dir, size, time) => `
+      <tr>
+        <td><input type=checkbox></td>
+        <td data-href="${href}">${escapeHtml(name) + (dir ? "/" : "")}</td>
+        <td>${dir ? "" : readableSize(size)}</td>
+        <td>${timeStamp(time)}</td>
+      </tr>
+    `;
+
+const checkedHrefs = () =>
+  [...$list.querySelectorAll("input[type=checkbox]:checked")].map(
+    (el) => el.parentElement.nextElementSibling.dataset.href
+  );
+
+const asList = async () => {
+  const r = await fetch(curPath(), {
+    method: "PROPFIND",
+    headers: {
+      authorization: localStorage.davAuth,
+      depth: "1",
+      "content-type": "text/xml; charset=utf-8",
+    },
+    body: `<?xml version="1.0" encoding="utf-8" ?><D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>`,
+  });
+  if (!r.ok) throw alert(r.status);
+  const resDoc = new DOMParser().parseFromString(await r.text(), "text/xml");
+  let innerHTML = "";
+  if (curPath() !== location.pathname + "/") {
+    const parent = curPath().replace(/[^/]+\/$/, "");
+    innerHTML += `<tr><td></td><td data-href="${parent}">../</td><td></td><td></td></tr>`;
+  }
+  const select = (entry, k) => entry.querySelector(k)?.textContent;
+  const entries = [...resDoc.children[0].children].slice(1).sort((a, b) => {
+    const an = select(a, "displayname");
+    const ad = typeof select(a, "collection") === "string";
+    const bn = select(b, "displayname");
+    const bd = typeof select(b, "collection") === "string";
+    return bd - ad || (an < bn ? -1 : bn < an ? 1 : 0);
+  });
+  for (const entry of entries) {
+    const href = select(entry, "href");
+    const displayname = select(entry, "displayname");
+    const getlastmodified = select(entry, "getlastmodified");
+    const getcontentlength = select(entry, "getcontentlength");
+    const collection = typeof select(entry, "collection") === "string";
+    const time = new Date(getlastmodified);
+    innerHTML += listRow(href, displayname, collection, getcontentlength, time);
+  }
+  $list.innerHTML = innerHTML;
+  $checkAll.checked = false;
+};
+
+$checkAll.onchange = () => {
+  for (const el of $list.querySelectorAll("input[type=checkbox]"))
+    el.checked = $checkAll.checked;
+};
+
+$list.onclick = async (e) => {
+  const href = e?.target?.dataset?.href;
+  if (!href) return;
+  location.hash = "#" + href.slice(location.pathname.length);
+  onhashchange();
+};
+
+$delete.onclick = async () => {
+  for (const el of $list.querySelectorAll("input[type=checkbox]:checked")) {
+    const href = el?.parentElement?.nextElementSibling?.dataset?.href;
+    if (!href) continue;
+    const r = await fetch(href, {
+      method: "DELETE",
+      headers: { authorization: localStorage.davAuth },
+    });
+    if (!r.ok) throw alert(r.status);
+  }
+  onhashchange();
+};
+
+const moveTo = async (href, dest) => {
+  const r = await fetch(href, {
+    method: "MOVE",
+    headers: {
+      authorization: localStorage.davAuth,
+      destination: location.origin + dest,
+      overwrite: "F",
+    },
+  });
+  if (!r.ok) throw alert(r.status);
+};
+
+// rename the first checked entry in place
+$rename.onclick = async () => {
+  const [href] = checkedHrefs();
+  if (!href) return alert("check an entry");
+  const [, dir, name, slash] = href.match(/^(.*\/)([^/]+)(\/?)$/);
+  const newName = prompt("new name", decodeURIComponent(name));
+  if (!newName || newName.includes("/")) return;
+  await moveTo(href, dir + encodeURIComponent(newName) + slash);
+  onhashchange();
+};
+
+$move.onclick = async () => {
+  const hrefs = checkedHrefs();
+  if (!hrefs.length) return alert("check the entries");
+  const cur = decodeURIComponent(curPath().slice(location.pathname.length));
+  const target = prompt("target dir", cur);
+  if (!target) return;
+  const dir = location.pathname + encodePath(`/${target}/`.replace(/\/+/g, "/"));
+  for (const href of hrefs) await moveTo(href, dir + href.match(/[^/]+\/?$/)[0]);
+  onhashchange();
+};
+
+// download the checked entries, or the current dir, buffered as the auth header needs fetch
+$zip.onclick = async () => {
+  const dir = curPath();
+  const names = checkedHrefs().map((v) => v.slice(dir.length));
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: {
+      op_: "zip",
+      auth_: localStorage.davAuth,
+      path_: dir.slice(location.pathname.length),
+      names_: JSON.stringify(names.length ? names : [""]),
+    },
+  });
+  if (!r.ok) throw alert(r.status);
+  const a = document.createElement("a");
+  a.href = URL.createObjectURL(await r.blob());
+  a.download = (decodeURIComponent(dir.match(/([^/]*)\/$/)[1]) || "dav") + ".zip";
+  a.click();
+  setTimeout(() => URL.revokeObjectURL(a.href), 60e3);
+};
+
+// list the entries under the current dir whose names contain the query
+$search.onclick = async () => {
+  const query = prompt("search in current dir");
+  if (!query) return;
+  const dir = curPath();
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: {
+      op_: "search",
+      auth_: localStorage.davAuth,
+      path_: dir.slice(location.pathname.length),
+      query_: encodeURIComponent(query),
+    },
+  });
+  if (!r.ok) throw alert(r.status);
+  let innerHTML = "";
+  for (const v of await r.json()) {
+    const href = location.pathname + v.path + (v.dir ? "/" : "");
+    const name = decodeURIComponent(href.slice(dir.length).replace(/\/$/, ""));
+    innerHTML += listRow(href, name, v.dir, v.size, new Date(v.time * 1000));
+  }
+  $list.innerHTML = innerHTML || `<tr><td></td><td>not found</td></tr>`;
+  $checkAll.checked = false;
+};
+
+// share the first checked entry, or the current dir
+$share.onclick = async () => {
+  const checked = $list.querySelector("input[type=checkbox]:checked");
+  const href = checked?.parentElement?.nextElementSibling?.dataset?.href;
+  const path = (href ?? curPath()).slice(location.pathname.length);
+  const upload = confirm("upload only? cancel for read only");
+  const headers = {
+    op_: "create_share",
+    auth_: localStorage.davAuth,
+    path_: path.replace(/\/$/, ""),
+    scope_: upload ? "upload" : "read",
+  };
+  const password = prompt("password, empty for none");
+  if (password) headers.password_ = password;
+  const days = prompt("expire in days, empty for never");
+  if (days) headers.expire_ = "" + Math.round(days * 86400);
+  const limit = !upload && prompt("download limit, empty for unlimited");
+  if (limit) headers.limit_ = limit;
+  const r = await fetch("/dav", { method: "POST", headers });
+  if (!r.ok) throw alert(r.status);
+  const dir = path === "" || path.endsWith("/") ? "/" : "";
+  prompt("share link", `${location.origin}/dav-share/${await r.text()}${dir}`);
+};
+
+$shares.onclick = async () => {
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: { op_: "list_shares", auth_: localStorage.davAuth },
+  });
+  if (!r.ok) throw alert(r.status);
+  const list = (await r.json()).map((v) => {
+    const expire = v.expire ? new Date(v.expire * 1000) : null;
+    return [
+      v.token,
+      decodeURIComponent(v.path) || "/",
+      v.upload ? "upload" : "read",
+      v.password ? "password" : "",
+      expire ? "until " + timeStamp(expire) : "",
+      v.remain ?? "",
+    ].join(" ");
+  });
+  const token = prompt(list.join("\n") + "\n\ntoken to delete", "");
+  if (!token) return;
+  const r2 = await fetch("/dav", {
+    method: "POST",
+    headers: { op_: "delete_share", auth_: localStorage.davAuth, token_: token },
+  });
+  if (!r2.ok) throw alert(r2.status);
+  alert("deleted");
+};
+
+// the versions of the first checked file
+$versions.onclick = async () => {
+  const checked = $list.querySelector("input[type=checkbox]:checked");
+  const href = checked?.parentElement?.nextElementSibling?.dataset?.href;
+  if (!href || href.endsWith("/")) return alert("check a file");
+  const path = href.slice(location.pathname.length);
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: { op_: "list_versions", auth_: localStorage.davAuth, path_: path },
+  });
+  if (!r.ok) throw alert(r.status);
+  const list = (await r.json()).map((v) => {
+    const time = timeStamp(new Date(v.time * 1000));
+    return `${v.ver} ${time} ${readableSize(v.size)}`;
+  });
+  const ver = prompt(list.join("\n") + "\n\nversion to restore", "");
+  if (!ver) return;
+  const r2 = await fetch("/dav", {
+    method: "POST",
+    headers: {
+      op_: "restore_version",
+      auth_: localStorage.davAuth,
+      path_: path,
+      ver_: ver,
+    },
+  });
+  if (!r2.ok) throw alert(r2.status);
+  onhashchange();
+};
+
+$trash.onclick = async () => {
+  const r = await fetch("/dav", {
+    method: "POST",
+    headers: { op_: "list_trash", auth_: localStorage.davAuth },
+  });
+  if (!r.ok) throw alert(r.status);
+  const list = (await r.json()).map((v) => {
+    const path = decodeURIComponent(v.path) + (v.dir ? "/" : "");
+    const time = timeStamp(new Date(v.time * 1000));
+    return `${v.id} ${path} ${readableSize(v.size)} ${time}`;
+  });
+  const hint = "\n\nid to restore, -id to purge, * to empty";
+  const input = prompt(list.join("\n") + hint, "")?.trim();
+  if (!input) return;
+  const headers = { auth_: localStorage.davAuth };
+  if (input === "*") headers.op_ = "empty_trash";
+  else if (input.startsWith("-"))
+    (headers.op_ = "purge_trash"), (headers.id_ = input.slice(1));
+  else (headers.op_ = "restore_trash"), (headers.id_ = input);
+  const r2 = await fetch("/dav", { method: "POST", headers });
+  if (!r2.ok) throw alert(r2.status);
+  onhashchange();
+};
+
+ondragenter = (e) => {
+  if (!e.fromElement) $putBox.style.display = "grid";
+};
+
+ondragleave = (e) => {
+  if (!e.fromElement) $putBox.style.display = "";
+};
+
+ondragover = (e) => e.preventDefault(); // to allow dropping
+
+ondrop = async (e) => {
+  e.preventDefault();
+  $putBox.style.display = "";
+  if (!curPath().endsWith("/")) return;
+  await putTree(await droppedFiles(e.dataTransfer.items));
+};
+
+// the files in the dropped entries, with the paths relative to the current dir
+const droppedFiles = async (items) => {
+  const entries = [...items].map((v) => v.webkitGetAsEntry()); // only valid in the event
+  const files = [];
+  const walk = async (entry) => {
+    if (entry.isFile) {
+      const file = await new Promise((res, rej) => entry.file(res, rej));
+      files.push({ file, path: entry.fullPath.slice(1) });
+    } else if (entry.isDirectory) {
+      const reader = entry.createReader();
+      const read = () => new Promise((res, rej) => reader.readEntries(res, rej));
+      for (let batch; (batch = await read()).length; ) // in batches until empty
+        for (const v of batch) await walk(v);
+    }
+  };
+  for (const entry of entries) if (entry) await walk(entry);
+  return files;
+};
+
+$upload.onclick = () => {
+  $putBox.style.display = $putBox.style.display ? "" : "grid";
+};
+
+const CHUNK_SIZE = 1024 * 1024; // the same as the server, so each part is a chunk
+
+// the large files are sent in parts, the received parts are skipped when uploading the same file again
+const putParts = async (file, href) => {
+  const api = (headers, body) =>
+    fetch("/dav", {
+      method: "POST",
+      headers: {
+        auth_: localStorage.davAuth,
+        path_: href.slice(location.pathname.length),
+        id_: `${file.size}-${file.lastModified}`,
+        ...headers,
+      },
+      body,
+    });
+  const r = await api({ op_: "list_upload_parts" });
+  if (!r.ok) return r;
+  const received = new Set(await r.json());
+  for (let idx = 0; idx * CHUNK_SIZE < file.size; idx++) {
+    if (received.has(idx)) continue;
+    const part = file.slice(idx * CHUNK_SIZE, (idx + 1) * CHUNK_SIZE);
+    for (let retry = 1; ; retry++) {
+      const r = await api({ op_: "upload_part", idx_: "" + idx }, part).catch(() => {
+        if (retry > 3) throw alert("network error, upload again to continue");
+      });
+      if (r) {
+        if (!r.ok) return r;
+        break;
+      }
+      await new Promise((res) => setTimeout(res, 1000 * retry));
+    }
+  }
+  return api({ op_: "finish_upload", size_: "" + file.size });
+};
+
+const upload = async (files, pathname) => {
+  console.time("upload");
+  let finished = 0;
+  $putBox.style.setProperty("--bg-text", `"\\a 0 / ${files.length}"`);
+  let debounce = Date.now();
+  const pool = Array.from(Array(2), (_, i) => Promise.resolve(i)); // dual thread is enough, quad is too much
+  for (const file of files) {
+    const i = await Promise.race(pool);
+    const put =
+      file.size > CHUNK_SIZE
+        ? putParts(file, pathname(file))
+        : fetch(pathname(file), {
+            method: "PUT",
+            headers: { authorization: localStorage.davAuth },
+            body: file,
+          });
+    pool[i] = put.then((r) => {
+      if (!r.ok) throw alert(r.status);
+      finished++;
+      if (Date.now() - debounce > 200) {
+        debounce = Date.now();
+        const bgText = `"\\a ${finished} / ${files.length}"`;
+        $putBox.style.setProperty("--bg-text", bgText);
+      }
+      return i;
+    });
+  }
+  await Promise.all(pool);
+  $putBox.style.removeProperty("--bg-text");
+  $putBox.style.display = "";
+  console.timeEnd("upload");
+};
+
+// upload by the paths relative to the current dir, the missing dirs are created first
+const putTree = async (list) => {
+  $putBox.style.display = "grid";
+  $putBox.style.setProperty("--bg-text", `"\\a Preparing ...`);
+  const createdDir = new Set();
+  for (const { path: p } of list) {
+    for (let i = 0; (i = p.indexOf("/", i + 1)), i != -1; ) {
+      const d = p.slice(0, i);
+      if (createdDir.has(d)) continue;
+      createdDir.add(d);
+      const r = await fetch(curPath() + encodePath(d), {
+        method: "MKCOL",
+        headers: { authorization: localStorage.davAuth },
+      });
+      if (!r.ok && r.status !== 405) throw alert(r.status); // 405 if exists
+    }
+  }
+  const paths = new Map(list.map((v) => [v.file, v.path]));
+  const files = list.map((v) => v.file);
+  await upload(files, (file) => curPath() + encodePath(paths.get(file)));
+  onhashchange();
+};
+
+$putFile.onchange = async (e) => {
+  await putTree([...$putFile.files].map((file) => ({ file, path: file.name })));
+  $putFile.value = null;
+};
+
+$putDir.onchange = async (e) => {
+  const files = [...$putDir.files].sort((a, b) => {
+    a = a.webkitRelativePath.split("/");
+    b = b.webkitRelativePath.split("/");
+    while (a.length > 1 && b.length > 1) {
+      if (a[0] !== b[0]) return a[0] < b[0] ? -1 : 1;
+      a.shift(), b.shift();
+    }
+    return a.length - b.length;
+  });
+  await putTree(files.map((file) => ({ file, path: file.webkitRelativePath })));
+  $putDir.value = null;
+};
+
+$create.onclick = async () => {
+  if (!curPath().endsWith("/")) throw alert("current must be dir");
+  const hint = "file name, dir name with '/', or prefix 'calendar:' or 'contacts:' for dir";
+  const input = prompt(hint, "new-file.txt");
+  if (!input) return;
+  const [, kind, entryName] = input.match(/^(?:(calendar|contacts):)?(.*)$/);
+  let method = entryName.endsWith("/") ? "MKCOL" : "PUT";
+  let body;
+  if (kind === "calendar") {
+    method = "MKCALENDAR";
+  } else if (kind === "contacts") {
+    method = "MKCOL";
+    const type = `<collection/><addressbook xmlns="urn:ietf:params:xml:ns:carddav"/>`;
+    body = `<mkcol xmlns="DAV:"><set><prop><resourcetype>${type}</resourcetype></prop></set></mkcol>`;
+  }
+  const r = await fetch(curPath() + encodePath(entryName), {
+    method,
+    headers: { authorization: localStorage.davAuth },
+    body,
+  });
+  if (!r.ok) throw alert(r.status);
+  onhashchange();
+};
+
+$save.onclick = async () => {
+  const r = await fetch(curPath(), {
+    method: "PUT",
+    headers: { authorization: localStorage.davAuth },
+    body: $edit.value,
+  });
+  if (!r.ok) throw alert(r.status);
+  const originText = $save.textContent;
+  $save.textContent = "Saved successfully";
+  setTimeout(() => void ($save.textContent = originText), 1000);
+};
+
+if (localStorage.davAuth) {
+  location.hash = location.hash || "#/";
+  onhashchange();
+} else {
+  setStage("stage_auth_");
+}
@@ -0,0 +1,440 @@
+//! PROPFIND and PROPPATCH. The live properties are computed, others are dead properties stored as xml.
+
+use super::caldav::{self, CALDAV, CALENDARSERVER, CARDDAV};
+use super::lock::{self, Lock};
+use super::xml::{self, Element, DAV};
+use super::{blob, db, path};
+use axum::http::{header::*, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Bytes of each user by default, the `PUT` beyond is rejected.
+pub const USER_QUOTA: u64 = 1024 * 1024 * 1024;
+
+/// Set for the user by admin, or the `dav_quota` config.
+pub async fn quota(uid: &str) -> u64 {
+    match db::get_user_quota(uid.to_owned()).await {
+        Some(v) => v,
+        None => super::config("dav_quota", USER_QUOTA).await,
+    }
+}
+
+/// Live properties, the ones after `ALLPROP_LEN` are only returned by name. RFC 4331, RFC 5397, RFC 6578
+const LIVE: [(&str, &str); 23] = [
+    (DAV, "creationdate"),
+    (DAV, "displayname"),
+    (DAV, "getcontentlength"),
+    (DAV, "getcontenttype"),
+    (DAV, "getetag"),
+    (DAV, "getlastmodified"),
+    (DAV, "lockdiscovery"),
+    (DAV, "resourcetype"),
+    (DAV, "supportedlock"),
+    (DAV, "quota-available-bytes"),
+    (DAV, "quota-used-bytes"),
+    (DAV, "current-user-principal"),
+    (DAV, "principal-URL"),
+    (DAV, "supported-report-set"),
+    (DAV, "sync-token"),
+    (CALDAV, "calendar-home-set"),
+    (CALDAV, "supported-calendar-component-set"),
+    (CALDAV, "supported-calendar-data"),
+    (CALDAV, "calendar-data"),
+    (CARDDAV, "addressbook-home-set"),
+    (CARDDAV, "supported-address-data"),
+    (CARDDAV, "address-data"),
+    (CALENDARSERVER, "getctag"),
+];
+const ALLPROP_LEN: usize = 9;
+
+pub fn is_live(ns: &str, name: &str) -> bool {
+    LIVE.contains(&(ns, name))
+}
+
+pub const MULTISTATUS_START: &str =
+    r#"<?xml version="1.0" encoding="utf-8" ?><D:multistatus xmlns:D="DAV:">"#;
+
+pub enum Find {
+    /// With the `include` names.
+    All(Vec<(String, String)>),
+    Names,
+    Props(Vec<(String, String)>),
+}
+
+/// Shared by all entries in a response.
+pub struct Ctx<'a> {
+    prefix: &'a str,
+    used: u64,
+    quota: u64,
+    locks: Vec<Lock>,
+}
+
+impl<'a> Ctx<'a> {
+    pub async fn new(prefix: &'a str, uid: &str) -> Ctx<'a> {
+        Self {
+            prefix,
+            used: db::get_usage(uid.to_owned()).await,
+            quota: quota(uid).await,
+            locks: db::list_locks(uid.to_owned()).await,
+        }
+    }
+}
+
+struct Entry<'a> {
+    eid: &'a str,
+    pathname: &'a str,
+    time: u64,
+    size: u64,
+    flag: u64,
+    ctime: u64,
+    /// `None` for dirs.
+    etag: Option<String>,
+    /// Only for calendars and address books.
+    sync_token: Option<u64>,
+    /// The object in `REPORT`.
+    content: Option<&'a str>,
+}
+
+pub fn names(prop: &Element) -> Vec<(String, String)> {
+    prop.elements()
+        .map(|v| (v.ns.clone(), v.name.clone()))
+        .collect()
+}
+
+pub fn bad_request() -> Response {
+    StatusCode::BAD_REQUEST.into_response()
+}
+
+pub fn multistatus(body: String) -> Response {
+    let headers = [(CONTENT_TYPE, "application/xml; charset=utf-8")];
+    (StatusCode::MULTI_STATUS, headers, body).into_response()
+}
+
+fn rfc3339(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (y, m, d) = crate::tz::civil_from_days(days as _);
+    let (hh, mm, ss) = (secs / 3600, secs / 60 % 60, secs % 60);
+    format!("{y:04}-{m:02}-{d:02}T{hh:02}:{mm:02}:{ss:02}Z")
+}
+
+/// The value of a live property, `None` if not defined for this entry.
+fn live(ns: &str, name: &str, entry: &Entry, ctx: &Ctx) -> Option<String> {
+    let is_dir = entry.flag & db::ENTRY_DIR != 0;
+    let is_root = entry.pathname.is_empty();
+    let (is_calendar, is_addressbook) = (
+        entry.flag & db::ENTRY_CALENDAR != 0,
+        entry.flag & db::ENTRY_ADDRESSBOOK != 0,
+    );
+    let http_date = |t| httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(t));
+    let mut o = String::new();
+    match (ns, name) {
+        (DAV, "creationdate") => o = rfc3339(entry.ctime),
+        (DAV, "displayname") => xml::escape(&mut o, entry.pathname.rsplit('/').next().unwrap()),
+        (DAV, "getcontentlength") if !is_dir => o = entry.size.to_string(),
+        (DAV, "getcontenttype") if !is_dir => {
+            o += crate::assets::content_type(entry.pathname);
+        }
+        (DAV, "getetag") => xml::escape(&mut o, entry.etag.as_deref()?),
+        (DAV, "getlastmodified") => o = http_date(entry.time),
+        (DAV, "lockdiscovery") => {
+            for lock in ctx.locks.iter().filter(|v| v.covers(entry.eid)) {
+                lock.write_active(&mut o, ctx.prefix);
+            }
+        }
+        (DAV, "supportedlock") => o += lock::SUPPORTED_LOCK,
+        (DAV, "resourcetype") if is_dir => {
+            o += "<D:collection/>";
+            if is_calendar {
+                o += &format!("<calendar xmlns=\"{CALDAV}\"/>");
+            }
+            if is_addressbook {
+                o += &format!("<addressbook xmlns=\"{CARDDAV}\"/>");
+            }
+        }
+        (DAV, "resourcetype") => {}
+        (DAV, "quota-available-bytes") => o = ctx.quota.saturating_sub(ctx.used).to_string(),
+        (DAV, "quota-used-bytes") => o = ctx.used.to_string(),
+        // the user's root is the principal, and the home of calendars and address books
+        (DAV, "current-user-principal") => o = format!("<D:href>{}/</D:href>", ctx.prefix),
+        (DAV, "principal-URL")
+        | (CALDAV, "calendar-home-set")
+        | (CARDDAV, "addressbook-home-set")
+            if is_root =>
+        {
+            o = format!("<D:href>{}/</D:href>", ctx.prefix)
+        }
+        (DAV, "supported-report-set") if is_dir => {
+            let mut reports = vec![(DAV, "sync-collection")];
+            if is_calendar {
+                reports.extend([(CALDAV, "calendar-query"), (CALDAV, "calendar-multiget")]);
+            }
+            if is_addressbook {
+                reports.extend([
+                    (CARDDAV, "addressbook-query"),
+                    (CARDDAV, "addressbook-multiget"),
+                ]);
+            }
+            for (ns, name) in reports {
+                o += "<D:supported-report><D:report>";
+                write_prop(&mut o, ns, name, None);
+                o += "</D:report></D:supported-report>";
+            }
+        }
+        (DAV, "sync-token") | (CALENDARSERVER, "getctag") => {
+            o = caldav::sync_token(entry.sync_token?);
+        }
+        (CALDAV, "supported-calendar-component-set") if is_calendar => {
+            for comp in caldav::COMPONENTS {
+                o += &format!("<comp name=\"{comp}\"/>");
+            }
+        }
+        (CALDAV, "supported-calendar-data") if is_calendar => {
+            o += r#"<calendar-data content-type="text/calendar" version="2.0"/>"#;
+        }
+        (CARDDAV, "supported-address-data") if is_addressbook => {
+            o += r#"<address-data-type content-type="text/vcard" version="3.0"/>"#;
+            o += r#"<address-data-type content-type="text/vcard" version="4.0"/>"#;
+        }
+        (CALDAV, "calendar-data") | (CARDDAV, "address-data") => {
+            xml::escape(&mut o, entry.content?);
+        }
+        _ => return None,
+    }
+    Some(o)
+}
+
+/// Write `<name>value</name>`, or `<name/>` if the value is `None`.
+pub fn write_prop(o: &mut String, ns: &str, name: &str, value: Option<&str>) {
+    let tag = match ns {
+        DAV => format!("D:{name}"),
+        _ => {
+            let mut tag = format!("{name} xmlns=\"");
+            xml::escape(&mut tag, ns);
+            tag + "\""
+        }
+    };
+    match value {
+        Some(v) => {
+            let end = tag.split(' ').next().unwrap();
+            *o += &format!("<{tag}>{v}</{end}>");
+        }
+        None => *o += &format!("<{tag}/>"),
+    }
+}
+
+fn write_propstat(o: &mut String, props: &str, status: &str) {
+    *o += "<D:propstat><D:prop>";
+    *o += props;
+    *o += "</D:prop><D:status>HTTP/1.1 ";
+    *o += status;
+    *o += "</D:status></D:propstat>";
+}
+
+pub fn write_href(o: &mut String, prefix: &str, pathname: &str, is_dir: bool) {
+    *o += "<D:href>";
+    *o += prefix;
+    path::encode(o, pathname);
+    if is_dir {
+        *o += "/";
+    }
+    *o += "</D:href>";
+}
+
+/// Write the `<D:response>` of the entry, the `content` is the object text for `REPORT`.
+pub async fn write_response(
+    o: &mut String,
+    ctx: &Ctx<'_>,
+    (eid, time, size, flag, ctime): (String, u64, u64, u64, u64),
+    find: &Find,
+    content: Option<&str>,
+) {
+    let (_, pathname) = eid.split_once(':').unwrap();
+    let etag = match flag & db::ENTRY_DIR {
+        0 => Some(blob::etag(&db::list_blocks(eid.to_owned()).await)),
+        _ => None,
+    };
+    let sync_token = match flag & (db::ENTRY_CALENDAR | db::ENTRY_ADDRESSBOOK) {
+        0 => None,
+        _ => Some(db::get_sync_token(eid.to_owned()).await),
+    };
+    let entry = Entry {
+        eid: &eid,
+        pathname,
+        time,
+        size,
+        flag,
+        ctime,
+        etag,
+        sync_token,
+        content,
+    };
+    let dead = match find {
+        Find::Props(v) if v.iter().all(|v| LIVE.contains(&(&v.0, &v.1))) => Vec::new(),
+        _ => db::list_props(eid.to_owned()).await,
+    };
+    let (mut found, mut missing) = (String::new(), String::new());
+    match find {
+        Find::Names => {
+            for (ns, name) in LIVE {
+                if live(ns, name, &entry, ctx).is_some() {
+                    write_prop(&mut found, ns, name, None);
+                }
+            }
+            for (ns, name, _) in &dead {
+                write_prop(&mut found, ns, name, None);
+            }
+        }
+        Find::All(include) => {
+            for (ns, name) in &LIVE[..ALLPROP_LEN] {
+                if let Some(v) = live(ns, name, &entry, ctx) {
+                    write_prop(&mut found, ns, name, Some(&v));
+                }
+            }
+            for (ns, name) in include {
+                if LIVE[ALLPROP_LEN..].contains(&(ns, name)) {
+                    let v = live(ns, name, &entry, ctx);
+                    write_prop(&mut found, ns, name, v.as_deref());
+                }
+            }
+            for (ns, name, value) in &dead {
+                write_prop(&mut found, ns, name, Some(value));
+            }
+        }
+        Find::Props(props) => {
+            for (ns, name) in props {
+                let value = match LIVE.contains(&(ns, name)) {
+                    true => live(ns, name, &entry, ctx),
+                    false => dead
+                        .iter()
+                        .find(|v| &v.0 == ns && &v.1 == name)
+                        .map(|v| v.2.clone()),
+                };
+                match value {
+                    Some(v) => write_prop(&mut found, ns, name, Some(&v)),
+                    None => write_prop(&mut missing, ns, name, None),
+                }
+            }
+        }
+    }
+    *o += "<D:response>";
+    write_href(o, ctx.prefix, pathname, flag & db::ENTRY_DIR != 0);
+    if !found.is_empty() || missing.is_empty() {
+        write_propstat(o, &found, "200 OK");
+    }
+    if !missing.is_empty() {
+        write_propstat(o, &missing, "404 Not Found");
+    }
+    *o += "</D:response>";
+}
+
+/// The `depth` is `u32::MAX` for infinity. The entry itself is always the first response.
+pub async fn propfind(
+    prefix: &str,
+    eid: String,
+    depth: u64,
+    body: &str,
+) -> anyhow::Result<Response> {
+    let find = if body.trim().is_empty() {
+        Find::All(Vec::new())
+    } else {
+        let Ok(root) = xml::parse(body) else {
+            return Ok(bad_request());
+        };
+        if !root.is(DAV, "propfind") {
+            return Ok(bad_request());
+        }
+        if root.child(DAV, "propname").is_some() {
+            Find::Names
+        } else if let Some(prop) = root.child(DAV, "prop") {
+            Find::Props(names(prop))
+        } else if root.child(DAV, "allprop").is_some() {
+            Find::All(root.child(DAV, "include").map(names).unwrap_or_default())
+        } else {
+            return Ok(bad_request());
+        }
+    };
+    let (uid, _) = eid.split_once(':').unwrap();
+    let ctx = Ctx::new(prefix, uid).await;
+    let entries = db::list_entry_tree(eid.to_owned(), depth).await;
+    if entries.first().map(|v| &v.0) != Some(&eid) {
+        return Err(anyhow::anyhow!("not found"));
+    }
+    let mut o = String::new();
+    o += MULTISTATUS_START;
+    for row in entries {
+        write_response(&mut o, &ctx, row, &find, None).await;
+    }
+    o += "</D:multistatus>";
+    Ok(multistatus(o))
+}
+
+/// Apply all the `set` and `remove` in order, or nothing if any of them fails.
+pub async fn proppatch(prefix: &str, eid: String, body: &str) -> anyhow::Result<Response> {
+    let Ok(root) = xml::parse(body) else {
+        return Ok(bad_request());
+    };
+    if !root.is(DAV, "propertyupdate") {
+        return Ok(bad_request());
+    }
+    let mut ops = Vec::new();
+    for op in root.elements() {
+        let set = match (op.ns.as_str(), op.name.as_str()) {
+            (DAV, "set") => true,
+            (DAV, "remove") => false,
+            _ => continue,
+        };
+        for prop in op.elements().filter(|v| v.is(DAV, "prop")) {
+            for v in prop.elements() {
+                let value = set.then(|| {
+                    let mut o = String::new();
+                    v.write_inner(&mut o);
+                    o
+                });
+                ops.push((v.ns.clone(), v.name.clone(), value));
+            }
+        }
+    }
+    let (_, _, flag) = db::get_entry_meta(eid.to_owned())
+        .await
+        .ok_or_else(|| anyhow::anyhow!("not found"))?;
+    if flag & db::ENTRY_READ_ONLY != 0 {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    // the live properties are all protected, and the dead ones in DAV: namespace are reserved
+    let protected = |ns: &str, name: &str| ns == DAV || is_live(ns, name);
+    let failed = ops.iter().any(|v| protected(&v.0, &v.1));
+    let mut names: Vec<(&str, &str)> = Vec::new();
+    for (ns, name, _) in &ops {
+        if !names.contains(&(ns, name)) {
+            names.push((ns, name));
+        }
+    }
+    let (mut ok, mut forbidden, mut dependency) = (String::new(), String::new(), String::new());
+    for (ns, name) in names {
+        let o = match (failed, protected(ns, name)) {
+            (false, _) => &mut ok,
+            (true, true) => &mut forbidden,
+            (true, false) => &mut dependency,
+        };
+        write_prop(o, ns, name, None);
+    }
+    let mut o = String::new();
+    o += MULTISTATUS_START;
+    o += "<D:response>";
+    let (_, pathname) = eid.split_once(':').unwrap();
+    write_href(&mut o, prefix, pathname, flag & db::ENTRY_DIR != 0);
+    if !ok.is_empty() {
+        write_propstat(&mut o, &ok, "200 OK");
+    }
+    if !forbidden.is_empty() {
+        write_propstat(&mut o, &forbidden, "403 Forbidden");
+    }
+    if !dependency.is_empty() {
+        write_propstat(&mut o, &dependency, "424 Failed Dependency");
+    }
+    o += "</D:response></D:multistatus>";
+    if !failed {
+        db::set_props(eid, ops).await;
+    }
+    Ok(multistatus(o))
+}
@@ -0,0 +1,172 @@
+//! GET and HEAD of files, with conditional requests and ranges. RFC 9110 section 13 and 14
+
+use super::blob::{self, Piece};
+use super::crypt::Key;
+use super::db;
+use axum::body::{Body, Bytes};
+use axum::http::{header::*, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// More ranges are ignored and the whole content is sent, to avoid the abuse.
+const MAX_RANGES: usize = 16;
+
+/// Whether the etag is in the list of `If-Match` or `If-None-Match`, the `*` matches any.
+fn listed(v: &HeaderValue, etag: &str, weak: bool) -> bool {
+    let Ok(v) = v.to_str() else {
+        return false;
+    };
+    v.split(',').map(str::trim).any(|v| match v {
+        "*" => true,
+        v if weak => v.trim_start_matches("W/") == etag.trim_start_matches("W/"),
+        v => !v.starts_with("W/") && v == etag,
+    })
+}
+
+fn parse_date(v: &HeaderValue) -> Option<u64> {
+    let t = httpdate::parse_http_date(v.to_str().ok()?).ok()?;
+    Some(t.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Check `If-Match` and `If-None-Match` before writing, the `etag` is `None` if not exists.
+pub fn precondition(headers: &HeaderMap, etag: Option<&str>) -> bool {
+    if let Some(v) = headers.get(IF_MATCH) {
+        if !etag.is_some_and(|etag| listed(v, etag, false)) {
+            return false;
+        }
+    }
+    if let Some(v) = headers.get(IF_NONE_MATCH) {
+        if etag.is_some_and(|etag| listed(v, etag, true)) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Returns `start..end` of each satisfiable range, or `None` to ignore the header.
+fn parse_ranges(v: &HeaderValue, size: u64) -> Option<Vec<(u64, u64)>> {
+    let v = v.to_str().ok()?.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+    for item in v.split(',').map(str::trim) {
+        let (first, last) = item.split_once('-')?;
+        let (start, end) = match (first, last) {
+            ("", suffix) => (size.saturating_sub(suffix.parse().ok()?), size),
+            (first, "") => (first.parse().ok()?, size),
+            (first, last) => {
+                let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+                if last < first {
+                    return None;
+                }
+                (first, (last + 1).min(size))
+            }
+        };
+        if start < end {
+            ranges.push((start, end));
+        }
+    }
+    (ranges.len() <= MAX_RANGES).then_some(ranges)
+}
+
+pub async fn get(
+    eid: String,
+    headers: &HeaderMap,
+    is_head: bool,
+    meta: (u64, u64, u64),
+    key: Option<Key>,
+) -> Response {
+    let (time, size, flag) = meta;
+    let hashes = db::list_blocks(eid.to_owned()).await;
+    let etag = blob::etag(&hashes);
+    let content_type = crate::assets::content_type(&eid);
+    let stamp = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(time));
+    let mut h = HeaderMap::new();
+    h.insert(ETAG, etag.parse().unwrap());
+    h.insert(LAST_MODIFIED, stamp.parse().unwrap());
+    h.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if flag & db::ENTRY_GZIP != 0 {
+        h.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+    if flag & db::ENTRY_STABLE != 0 {
+        let v = HeaderValue::from_static("max-age=600,stale-while-revalidate=31536000");
+        h.insert(CACHE_CONTROL, v);
+    }
+
+    // the order is defined in RFC 9110 section 13.2.2
+    if let Some(v) = headers.get(IF_MATCH) {
+        if !listed(v, &etag, false) {
+            return StatusCode::PRECONDITION_FAILED.into_response();
+        }
+    } else if let Some(t) = headers.get(IF_UNMODIFIED_SINCE).and_then(parse_date) {
+        if time > t {
+            return StatusCode::PRECONDITION_FAILED.into_response();
+        }
+    }
+    if let Some(v) = headers.get(IF_NONE_MATCH) {
+        if listed(v, &etag, true) {
+            return (StatusCode::NOT_MODIFIED, h).into_response();
+        }
+    } else if let Some(t) = headers.get(IF_MODIFIED_SINCE).and_then(parse_date) {
+        if t >= time {
+            return (StatusCode::NOT_MODIFIED, h).into_response();
+        }
+    }
+    // send the whole content if changed since the client got the part, strong comparison required
+    let if_range = match headers.get(IF_RANGE) {
+        Some(v) => match parse_date(v) {
+            Some(t) => t == time,
+            None => v.as_bytes() == etag.as_bytes(),
+        },
+        None => true,
+    };
+    let ranges = match headers.get(RANGE) {
+        Some(v) if if_range => parse_ranges(v, size),
+        _ => None,
+    };
+
+    let mut pieces = Vec::new();
+    let (status, len) = match ranges.as_deref() {
+        None => {
+            blob::range(&hashes, 0, size, &mut pieces);
+            h.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            (StatusCode::OK, size)
+        }
+        Some([]) => {
+            let v = format!("bytes */{size}").parse().unwrap();
+            h.insert(CONTENT_RANGE, v);
+            return (StatusCode::RANGE_NOT_SATISFIABLE, h).into_response();
+        }
+        Some(&[(start, end)]) => {
+            blob::range(&hashes, start, end, &mut pieces);
+            let v = format!("bytes {start}-{}/{size}", end - 1).parse().unwrap();
+            h.insert(CONTENT_RANGE, v);
+            h.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            (StatusCode::PARTIAL_CONTENT, end - start)
+        }
+        Some(ranges) => {
+            let boundary = format!("{:016x}", rand::random::<u64>());
+            let mut len = 0;
+            for &(start, end) in ranges {
+                let range = format!("bytes {start}-{}/{size}", end - 1);
+                let head = format!("\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {range}\r\n\r\n");
+                len += head.len() as u64 + end - start;
+                pieces.push(Piece::Bytes(Bytes::from(head)));
+                blob::range(&hashes, start, end, &mut pieces);
+            }
+            let tail = format!("\r\n--{boundary}--\r\n");
+            len += tail.len() as u64;
+            pieces.push(Piece::Bytes(Bytes::from(tail)));
+            let v = format!("multipart/byteranges; boundary={boundary}");
+            h.insert(CONTENT_TYPE, v.parse().unwrap());
+            (StatusCode::PARTIAL_CONTENT, len)
+        }
+    };
+    h.insert(CONTENT_LENGTH, len.into());
+    let body = match is_head {
+        true => Body::empty(),
+        false => blob::read(pieces, key),
+    };
+    let mut res = (status, h, body).into_response();
+    // the `into_response` of `Body::empty` may set the length to zero
+    res.headers_mut().insert(CONTENT_LENGTH, len.into());
+    res
+}
@@ -0,0 +1,253 @@
+//! Public share links. A random token maps to an entry, to read or upload without the owner's credentials.
+
+use super::{blob, db, lock, path, props, serve, user, DAV_PATH_PREFIX};
+use crate::sha256::sha256;
+use crate::template;
+use crate::utils::OptionResult;
+use axum::extract::Request;
+use axum::http::{header::*, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+const SHARE_PATH_PREFIX: &str = "/dav-share";
+
+pub struct Share {
+    /// 32 hex chars.
+    pub token: String,
+    pub eid: String,
+    /// Upload only to the dir, or read only.
+    pub upload: bool,
+    /// The password hash like `dav_users.auth`, empty if no password.
+    pub auth: String,
+    /// Seconds since unix epoch, `0` for never.
+    pub expire: u64,
+    /// Downloads left, `None` for unlimited.
+    pub remain: Option<u64>,
+}
+
+impl Share {
+    pub fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Self> {
+        let v2s = |v| String::from_utf8(v).unwrap();
+        Ok(Self {
+            token: v2s(r.get(0)?),
+            eid: v2s(r.get(1)?),
+            upload: r.get(2)?,
+            auth: v2s(r.get(3)?),
+            expire: r.get(4)?,
+            remain: r.get(5)?,
+        })
+    }
+}
+
+/// The passed `Authorization` headers by sha256 of the share's hash and the header, like the one in `user`.
+static VERIFIED: Mutex<BTreeSet<[u8; 32]>> = Mutex::new(BTreeSet::new());
+const VERIFIED_MAX: usize = 1024;
+
+/// The password is sent by Basic auth with any username, so browsers prompt for it.
+async fn authorized(share: &Share, headers: &HeaderMap) -> bool {
+    let Some(v) = headers.get(AUTHORIZATION) else {
+        return false;
+    };
+    let key = sha256(&[share.auth.as_bytes(), v.as_bytes()].concat());
+    if VERIFIED.lock().unwrap().contains(&key) {
+        return true;
+    }
+    let Some((_, password)) = user::parse_basic(v.as_bytes()) else {
+        return false;
+    };
+    let stored = share.auth.to_owned();
+    let passed = tokio::task::spawn_blocking(move || user::check(&password, &stored));
+    if !passed.await.unwrap() {
+        return false;
+    }
+    let mut verified = VERIFIED.lock().unwrap();
+    if verified.len() >= VERIFIED_MAX {
+        verified.clear();
+    }
+    verified.insert(key);
+    true
+}
+
+fn page(data: serde_json::Value) -> Response {
+    Html(template!("share.html", title = "Share").render(&data)).into_response()
+}
+
+/// Only the direct children of the shared dir can be created, never overwritten.
+async fn upload(share: &Share, pathname: &str, req: Request) -> anyhow::Result<Response> {
+    let (_, _, flag) = db::get_entry_meta(share.eid.to_owned()).await.e()?;
+    if flag & db::ENTRY_DIR == 0 {
+        return Err(anyhow::anyhow!("not dir"));
+    }
+    let name = share.eid.rsplit('/').next().unwrap();
+    match req.method().as_str() {
+        "GET" | "HEAD" if pathname.is_empty() => Ok(page(json!({ "name": name, "upload": true }))),
+        "PUT" if pathname.matches('/').count() == 1 => {
+            if flag & db::ENTRY_READ_ONLY != 0 {
+                return Ok(StatusCode::FORBIDDEN.into_response());
+            }
+            let eid = share.eid.to_owned() + pathname;
+            if db::get_entry_meta(eid.to_owned()).await.is_some() {
+                return Ok(StatusCode::CONFLICT.into_response());
+            }
+            let targets = [(&eid[..], false), (&share.eid[..], false)];
+            let res = lock::check(DAV_PATH_PREFIX, &HeaderMap::new(), &eid, &targets).await;
+            if let Some(res) = res {
+                return Ok(res);
+            }
+            let (uid, _) = eid.split_once(':').unwrap();
+            let used = db::get_usage(uid.to_owned()).await;
+            let limit = props::quota(uid).await.saturating_sub(used);
+            let len = req.headers().get(CONTENT_LENGTH);
+            let len = len.and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+            if len.is_some_and(|v| v > limit) {
+                return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response());
+            }
+            let Some((hashes, size)) = blob::write(req.into_body(), limit, None).await? else {
+                return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response());
+            };
+            let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+            db::set_file(eid, hashes, time, size, 0, 0).await;
+            Ok(StatusCode::CREATED.into_response())
+        }
+        _ => Ok(StatusCode::METHOD_NOT_ALLOWED.into_response()),
+    }
+}
+
+async fn read(share: &Share, pathname: &str, req: Request) -> anyhow::Result<Response> {
+    let eid = share.eid.to_owned() + pathname;
+    let (time, size, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
+    let is_head = match req.method().as_str() {
+        "GET" => false,
+        "HEAD" => true,
+        _ => return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response()),
+    };
+    let base = format!("{SHARE_PATH_PREFIX}/{}", share.token);
+    if flag & db::ENTRY_DIR == 0 {
+        if !is_head && !db::take_share_download(share.token.to_owned()).await {
+            return Ok(StatusCode::GONE.into_response());
+        }
+        if flag & db::ENTRY_HREF != 0 {
+            let v = HeaderValue::try_from(db::get_entry_data(eid).await.e()?)?;
+            return Ok((StatusCode::TEMPORARY_REDIRECT, [(LOCATION, v)]).into_response());
+        }
+        return Ok(serve::get(eid, req.headers(), is_head, (time, size, flag), None).await);
+    }
+    if !req.uri().path().ends_with('/') {
+        let v = HeaderValue::try_from(req.uri().path().to_owned() + "/")?;
+        return Ok((StatusCode::MOVED_PERMANENTLY, [(LOCATION, v)]).into_response());
+    }
+    let mut entries = Vec::new();
+    for (child, time, size, flag, _) in db::list_entry_tree(eid.to_owned(), 1).await {
+        let Some(child) = child.strip_prefix(&share.eid) else {
+            continue;
+        };
+        if child == pathname {
+            continue; // the dir itself
+        }
+        let is_dir = flag & db::ENTRY_DIR != 0;
+        let mut href = base.to_owned();
+        path::encode(&mut href, child);
+        let mut name = child.rsplit('/').next().unwrap().to_owned();
+        if is_dir {
+            href += "/";
+            name += "/";
+        }
+        let time = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(time));
+        let size = if is_dir {
+            String::new()
+        } else {
+            size.to_string()
+        };
+        entries.push(json!({ "href": href, "name": name, "size": size, "time": time }));
+    }
+    let parent = match pathname.rsplit_once('/') {
+        Some((parent, _)) => {
+            let mut href = base;
+            path::encode(&mut href, parent);
+            href + "/"
+        }
+        None => String::new(),
+    };
+    let name = eid.rsplit('/').next().unwrap();
+    let data = json!({ "name": name, "parent": parent, "entries": entries });
+    Ok(page(data))
+}
+
+pub async fn handler(req: Request) -> anyhow::Result<Response> {
+    let rest = req.uri().path().strip_prefix(SHARE_PATH_PREFIX).e()?;
+    let rest = rest.strip_prefix('/').e()?;
+    let (token, pathname) = rest.split_once('/').unwrap_or((rest, ""));
+    let Some(pathname) = path::decode(pathname) else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    let share = db::get_share(token.to_owned()).await.e()?;
+    let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+    if (share.expire != 0 && now >= share.expire) || share.remain == Some(0) {
+        return Ok(StatusCode::GONE.into_response());
+    }
+    if !share.auth.is_empty() && !authorized(&share, req.headers()).await {
+        let v = [(WWW_AUTHENTICATE, "Basic realm=\"share\"")];
+        return Ok((StatusCode::UNAUTHORIZED, v).into_response());
+    }
+    match share.upload {
+        true => upload(&share, &pathname, req).await,
+        false => read(&share, &pathname, req).await,
+    }
+}
+
+/// Create a share link from the fields of `api_handler`, returns the token.
+pub async fn create(
+    eid: String,
+    upload: bool,
+    password: Option<String>,
+    expire: Option<u64>,
+    limit: Option<u64>,
+) -> anyhow::Result<String> {
+    let (uid, _) = eid.split_once(':').e()?;
+    if db::get_user_enc(uid.to_owned()).await.is_some() {
+        return Err(anyhow::anyhow!(
+            "encrypted user can't share, the key is unreachable"
+        ));
+    }
+    let (_, _, flag) = db::get_entry_meta(eid.to_owned()).await.e()?;
+    if upload && flag & db::ENTRY_DIR == 0 {
+        return Err(anyhow::anyhow!("upload to file"));
+    }
+    let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let auth = match password.filter(|v| !v.is_empty()) {
+        Some(v) => user::hash(v).await,
+        None => String::new(),
+    };
+    let token = String::from_utf8(crate::utils::rand_id(&[32])).unwrap();
+    db::set_share(Share {
+        token: token.to_owned(),
+        eid,
+        upload,
+        auth,
+        expire: expire.map(|v| now + v).unwrap_or(0),
+        remain: limit,
+    })
+    .await;
+    Ok(token)
+}
+
+/// The shares of the user as json, for the page.
+pub async fn list(uid: String) -> String {
+    let mut o = Vec::new();
+    for share in db::list_shares(uid).await {
+        let mut pathname = String::new();
+        path::encode(&mut pathname, share.eid.split_once(':').unwrap().1);
+        o.push(json!({
+            "token": share.token,
+            "path": pathname,
+            "upload": share.upload,
+            "password": !share.auth.is_empty(),
+            "expire": share.expire,
+            "remain": share.remain,
+        }));
+    }
+    serde_json::Value::Array(o).to_string()
+}
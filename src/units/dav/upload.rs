@@ -0,0 +1,93 @@
+//! Resumable upload from the page. The file is sent in parts of `blob::CHUNK_SIZE`, each part is stored as one chunk
+//! at once, so an interrupted upload continues from the parts received.
+
+use super::blob::{self, CHUNK_SIZE};
+use super::crypt::Key;
+use super::{db, history, lock, props, DAV_PATH_PREFIX};
+use crate::log;
+use crate::utils::OptionResult;
+use axum::body::Body;
+use axum::http::HeaderMap;
+use std::time::UNIX_EPOCH;
+
+/// The uploads without any part received in this time are dropped, the chunks are left to gc.
+const EXPIRE: u64 = 3600 * 24;
+
+pub async fn put_part(
+    eid: String,
+    id: String,
+    idx: u64,
+    body: Body,
+    key: Option<&Key>,
+) -> anyhow::Result<()> {
+    let (hashes, size) = blob::write(body, CHUNK_SIZE as _, key).await?.e()?;
+    let [hash] = hashes[..] else {
+        return Err(anyhow::anyhow!("empty part"));
+    };
+    // the parts of all pending uploads are taken, or the uploads in parallel can pass the quota together
+    let (uid, _) = eid.split_once(':').unwrap();
+    let used = db::get_usage(uid.to_owned()).await + db::get_upload_usage(uid.to_owned()).await;
+    if used + size > props::quota(uid).await {
+        return Err(anyhow::anyhow!("insufficient storage")); // the chunk is left to gc
+    }
+    db::set_upload_part(eid, id, idx, hash, size).await;
+    Ok(())
+}
+
+/// The indexes of the received parts as json, for the page to skip them.
+pub async fn list_parts(eid: String, id: String) -> String {
+    let list = db::list_upload_parts(eid, id).await.into_iter();
+    serde_json::Value::from_iter(list.map(|v| v.0)).to_string()
+}
+
+/// Join the parts into the file of `size`, checked like the `PUT`.
+pub async fn finish(eid: String, id: String, size: u64) -> anyhow::Result<()> {
+    let parts = db::list_upload_parts(eid.to_owned(), id.to_owned()).await;
+    // all except the last are full, as `blob::range` requires
+    let last = parts.len().saturating_sub(1);
+    let complete = parts
+        .iter()
+        .enumerate()
+        .all(|(i, &(idx, _, size))| idx == i as u64 && (i == last || size == CHUNK_SIZE as u64));
+    if !complete || parts.iter().map(|v| v.2).sum::<u64>() != size {
+        return Err(anyhow::anyhow!("parts missing"));
+    }
+    let old = db::get_entry_meta(eid.to_owned()).await;
+    if old.is_some_and(|v| v.2 & (db::ENTRY_READ_ONLY | db::ENTRY_DIR) != 0) {
+        return Err(anyhow::anyhow!("read only"));
+    }
+    let (parent, _) = eid.rsplit_once('/').e()?;
+    let (_, _, flag) = db::get_entry_meta(parent.to_owned()).await.e()?;
+    if flag & db::ENTRY_READ_ONLY != 0 || flag & db::ENTRY_DIR == 0 {
+        return Err(anyhow::anyhow!("parent is not writable dir"));
+    }
+    // the objects must be checked as a whole
+    if flag & (db::ENTRY_CALENDAR | db::ENTRY_ADDRESSBOOK) != 0 {
+        return Err(anyhow::anyhow!("collection"));
+    }
+    let targets = match old {
+        Some(_) => vec![(&eid[..], false)],
+        None => vec![(&eid[..], false), (parent, false)],
+    };
+    let locked = lock::check(DAV_PATH_PREFIX, &HeaderMap::new(), &eid, &targets).await;
+    if locked.is_some() {
+        return Err(anyhow::anyhow!("locked"));
+    }
+    let (uid, _) = eid.split_once(':').unwrap();
+    let used = db::get_usage(uid.to_owned()).await;
+    if used.saturating_sub(old.map(|v| v.1).unwrap_or(0)) + size > props::quota(uid).await {
+        return Err(anyhow::anyhow!("insufficient storage"));
+    }
+    let hashes = parts.into_iter().map(|v| v.1).collect();
+    let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let keep = history::max_versions().await;
+    db::set_file(eid.to_owned(), hashes, time, size, 0, keep).await;
+    db::del_upload(eid, id).await;
+    Ok(())
+}
+
+pub async fn expire() {
+    let before = UNIX_EPOCH.elapsed().unwrap().as_secs() - EXPIRE;
+    let removed = db::expire_uploads(before).await;
+    log!(info: "dav gc removed {removed} upload parts");
+}
@@ -0,0 +1,214 @@
+//! Users and credentials. The passwords are stored as salted PBKDF2, never the `Authorization` header itself.
+
+use super::{crypt, db};
+use crate::sha256::{pbkdf2, sha256};
+use crate::utils::bytes_eq;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Stored in the hash like `pbkdf2-sha256$<rounds>$<salt hex>$<hash hex>`, so it can be raised later.
+pub const ROUNDS: u32 = 100_000;
+
+/// The verified `Authorization` headers by their sha256, to skip the slow hashing on each request.
+///
+/// Values are `(uid, is_app_password, data_key)`, cleared when any credentials changed.
+static VERIFIED: Mutex<BTreeMap<[u8; 32], Verified>> = Mutex::new(BTreeMap::new());
+type Verified = (String, bool, Option<[u8; 32]>);
+const VERIFIED_MAX: usize = 1024;
+
+fn hex(v: &[u8]) -> String {
+    v.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(v: &str) -> Option<Vec<u8>> {
+    if v.len() % 2 != 0 {
+        return None;
+    }
+    let d = |i: usize| (v.as_bytes()[i] as char).to_digit(16);
+    (0..v.len() / 2)
+        .map(|i| Some((d(i * 2)? << 4 | d(i * 2 + 1)?) as u8))
+        .collect()
+}
+
+fn base64_decode(v: &str) -> Option<Vec<u8>> {
+    let (mut o, mut acc, mut bits) = (Vec::new(), 0u32, 0);
+    for c in v.trim_end_matches('=').bytes() {
+        let d = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        (acc, bits) = ((acc << 6 | d as u32) & 0xffff, bits + 6);
+        if bits >= 8 {
+            bits -= 8;
+            o.push((acc >> bits) as u8);
+        }
+    }
+    Some(o)
+}
+
+/// Decode `Basic base64(uid:password)` into `(uid, password)`. RFC 7617
+pub fn parse_basic(auth: &[u8]) -> Option<(String, String)> {
+    let v = std::str::from_utf8(auth).ok()?.strip_prefix("Basic ")?;
+    let v = String::from_utf8(base64_decode(v.trim())?).ok()?;
+    let (uid, password) = v.split_once(':')?;
+    Some((uid.to_owned(), password.to_owned()))
+}
+
+pub async fn hash(password: String) -> String {
+    let salt: [u8; 16] = rand::random();
+    let v = tokio::task::spawn_blocking(move || pbkdf2(password.as_bytes(), &salt, ROUNDS));
+    let v = v.await.unwrap();
+    format!("pbkdf2-sha256${ROUNDS}${}${}", hex(&salt), hex(&v))
+}
+
+pub fn check(password: &str, stored: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some("pbkdf2-sha256"), Some(rounds), Some(salt), Some(v)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let (Ok(rounds), Some(salt), Some(v)) = (rounds.parse(), unhex(salt), unhex(v)) else {
+        return false;
+    };
+    let h = pbkdf2(password.as_bytes(), &salt, rounds);
+    bytes_eq(&h, &v)
+}
+
+/// Returns the uid if the `Basic` credentials match. The app passwords are accepted if `app` is true.
+///
+/// The data key is unwrapped here if the user is encrypted, so it lives only in memory.
+pub async fn authenticate(auth: &[u8], app: bool) -> Option<(String, Option<[u8; 32]>)> {
+    let key = sha256(auth);
+    if let Some((uid, is_app, data_key)) = VERIFIED.lock().unwrap().get(&key) {
+        if app || !is_app {
+            return Some((uid.to_owned(), *data_key));
+        }
+    }
+    let (uid, password) = parse_basic(auth)?;
+    let (stored, enc) = db::get_user_auth(uid.to_owned()).await?;
+    let apps = match app {
+        true => db::list_app_passwords(uid.to_owned()).await,
+        false => Vec::new(),
+    };
+    let passed = tokio::task::spawn_blocking(move || {
+        let (is_app, enc) = match check(&password, &stored) {
+            true => (false, enc),
+            false => (true, apps.into_iter().find(|v| check(&password, &v.1))?.2),
+        };
+        let data_key = match enc {
+            Some(v) => Some(crypt::unwrap(&password, &v)?),
+            None => None,
+        };
+        Some((is_app, data_key))
+    });
+    let (is_app, data_key) = passed.await.unwrap()?;
+    let mut verified = VERIFIED.lock().unwrap();
+    if verified.len() >= VERIFIED_MAX {
+        verified.clear();
+    }
+    verified.insert(key, (uid.to_owned(), is_app, data_key));
+    Some((uid, data_key))
+}
+
+pub fn forget() {
+    VERIFIED.lock().unwrap().clear();
+}
+
+/// Set the password by admin, as `uid:password`. Creates the user if not exists.
+pub async fn set_user(body: &[u8]) -> anyhow::Result<()> {
+    let v = std::str::from_utf8(body)?.trim();
+    let (uid, password) = v
+        .split_once(':')
+        .ok_or(anyhow::anyhow!("expect uid:password"))?;
+    let valid_uid_char = |&c: &u8| c.is_ascii_alphanumeric() || c == b'-' || c == b'_';
+    if uid.is_empty() || !uid.as_bytes().iter().all(valid_uid_char) {
+        return Err(anyhow::anyhow!("uid contains invalid chars"));
+    }
+    set_password(uid.to_owned(), password, None).await?;
+    if db::get_entry_meta(uid.to_owned() + ":").await.is_none() {
+        let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+        db::set_entry(
+            uid.to_owned() + ":",
+            Default::default(),
+            time,
+            0,
+            db::ENTRY_DIR,
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Set the quota by admin, like `uid:1073741824`, or `uid:` to use the `dav_quota` config.
+pub async fn set_user_quota(body: &[u8]) -> anyhow::Result<()> {
+    let v = std::str::from_utf8(body)?.trim();
+    let (uid, quota) = v
+        .split_once(':')
+        .ok_or(anyhow::anyhow!("expect uid:bytes"))?;
+    let quota = match quota.trim() {
+        "" => None,
+        v => Some(v.parse()?),
+    };
+    if !db::set_user_quota(uid.to_owned(), quota).await {
+        return Err(anyhow::anyhow!("user not found"));
+    }
+    Ok(())
+}
+
+/// Delete the user by admin, with all the entries. The chunks are removed by gc later.
+pub async fn del_user(body: &[u8]) -> anyhow::Result<()> {
+    let uid = std::str::from_utf8(body)?.trim();
+    if !db::del_user(uid.to_owned()).await {
+        return Err(anyhow::anyhow!("user not found"));
+    }
+    forget();
+    Ok(())
+}
+
+async fn wrap(password: String, data_key: Option<[u8; 32]>) -> Option<Vec<u8>> {
+    let data_key = data_key?;
+    let v = tokio::task::spawn_blocking(move || crypt::wrap(&password, &data_key));
+    Some(v.await.unwrap())
+}
+
+/// The encrypted user's data key is wrapped again by the new password, so only the user can do this.
+pub async fn set_password(
+    uid: String,
+    password: &str,
+    data_key: Option<[u8; 32]>,
+) -> anyhow::Result<()> {
+    if password.is_empty() {
+        return Err(anyhow::anyhow!("empty password"));
+    }
+    if data_key.is_none() && db::get_user_enc(uid.to_owned()).await.is_some() {
+        return Err(anyhow::anyhow!(
+            "encrypted, only the user can change password"
+        ));
+    }
+    let enc = wrap(password.to_owned(), data_key).await;
+    db::set_user(uid, hash(password.to_owned()).await, enc).await;
+    forget();
+    Ok(())
+}
+
+/// Generate a password for a WebDAV client, which can't manage the account. Replaces the same name.
+pub async fn create_app_password(uid: String, name: String, data_key: Option<[u8; 32]>) -> String {
+    let password = hex(&rand::random::<[u8; 16]>());
+    let auth = hash(password.to_owned()).await;
+    let enc = wrap(password.to_owned(), data_key).await;
+    db::set_app_password(uid, name, auth, enc).await;
+    forget();
+    password
+}
+
+pub async fn delete_app_password(uid: String, name: String) -> bool {
+    let deleted = db::del_app_password(uid, name).await;
+    forget();
+    deleted
+}
@@ -0,0 +1,275 @@
+//! Minimal XML reader for WebDAV request bodies, with namespaces. DTD is rejected, so no entity bomb.
+
+use anyhow::{anyhow, Result};
+
+pub const DAV: &str = "DAV:";
+
+/// Limit the nesting, to avoid stack overflow by malicious bodies.
+const MAX_DEPTH: usize = 32;
+
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+pub struct Element {
+    pub ns: String,
+    pub name: String,
+    /// `(name, value)` of the attributes without prefix.
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn is(&self, ns: &str, name: &str) -> bool {
+        self.ns == ns && self.name == name
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|v| match v {
+            Node::Element(v) => Some(v),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn child(&self, ns: &str, name: &str) -> Option<&Element> {
+        self.elements().find(|v| v.is(ns, name))
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|v| v.0 == name).map(|v| &v.1[..])
+    }
+
+    /// The text children joined.
+    pub fn text(&self) -> String {
+        let texts = self.children.iter().filter_map(|v| match v {
+            Node::Text(v) => Some(&v[..]),
+            Node::Element(_) => None,
+        });
+        texts.collect()
+    }
+
+    /// Write the children as XML, namespaces are declared on each element so it can be put anywhere.
+    pub fn write_inner(&self, o: &mut String) {
+        for child in &self.children {
+            match child {
+                Node::Text(v) => escape(o, v),
+                Node::Element(v) => {
+                    *o += "<";
+                    *o += &v.name;
+                    *o += " xmlns=\"";
+                    escape(o, &v.ns);
+                    *o += "\"";
+                    for (name, value) in &v.attrs {
+                        *o += &format!(" {name}=\"");
+                        escape(o, value);
+                        *o += "\"";
+                    }
+                    *o += ">";
+                    v.write_inner(o);
+                    *o += "</";
+                    *o += &v.name;
+                    *o += ">";
+                }
+            }
+        }
+    }
+}
+
+pub fn escape(o: &mut String, v: &str) {
+    crate::template::escape(o, v)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    /// `(prefix, uri)` of declared namespaces, the inner ones are at the end.
+    scopes: Vec<(String, String)>,
+}
+
+fn unescape(v: &str) -> Result<String> {
+    let mut o = String::new();
+    let mut rest = v;
+    while let Some((head, tail)) = rest.split_once('&') {
+        o += head;
+        let (entity, tail) = tail.split_once(';').ok_or_else(|| anyhow!("bad entity"))?;
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            v => {
+                let code = match v.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16)?,
+                    None => v
+                        .strip_prefix('#')
+                        .ok_or_else(|| anyhow!("bad entity"))?
+                        .parse()?,
+                };
+                char::from_u32(code).ok_or_else(|| anyhow!("bad char ref"))?
+            }
+        };
+        o.push(c);
+        rest = tail;
+    }
+    o += rest;
+    Ok(o)
+}
+
+impl<'a> Parser<'a> {
+    fn eat(&mut self, p: &str) -> bool {
+        match self.s.strip_prefix(p) {
+            Some(v) => {
+                self.s = v;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Skip until after `end`.
+    fn skip_past(&mut self, end: &str) -> Result<&'a str> {
+        let (skipped, rest) = self.s.split_once(end).ok_or_else(|| anyhow!("unclosed"))?;
+        self.s = rest;
+        Ok(skipped)
+    }
+
+    fn skip_space(&mut self) {
+        self.s = self.s.trim_start();
+    }
+
+    /// Skip comments and processing instructions, returns `false` if nothing skipped.
+    fn skip_misc(&mut self) -> Result<bool> {
+        if self.eat("<!--") {
+            self.skip_past("-->")?;
+        } else if self.eat("<?") {
+            self.skip_past("?>")?;
+        } else if self.s.starts_with("<!DOCTYPE") {
+            return Err(anyhow!("DTD is not allowed"));
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn name(&mut self) -> &'a str {
+        let end = self
+            .s
+            .find(|c: char| c.is_whitespace() || "/>=".contains(c));
+        let (name, rest) = self.s.split_at(end.unwrap_or(self.s.len()));
+        self.s = rest;
+        name
+    }
+
+    fn resolve(&self, prefix: &str) -> Result<String> {
+        let found = self.scopes.iter().rev().find(|v| v.0 == prefix);
+        match found {
+            Some(v) => Ok(v.1.clone()),
+            None if prefix.is_empty() => Ok(String::new()),
+            None => Err(anyhow!("undeclared namespace prefix {prefix}")),
+        }
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element> {
+        if depth > MAX_DEPTH {
+            return Err(anyhow!("too deep"));
+        }
+        if !self.eat("<") {
+            return Err(anyhow!("expect element"));
+        }
+        let qname = self.name();
+        let scopes_len = self.scopes.len();
+        let mut attrs = Vec::new();
+        let self_closed = loop {
+            self.skip_space();
+            if self.eat("/>") {
+                break true;
+            }
+            if self.eat(">") {
+                break false;
+            }
+            let attr = self.name();
+            self.skip_space();
+            if attr.is_empty() || !self.eat("=") {
+                return Err(anyhow!("bad attribute"));
+            }
+            self.skip_space();
+            let quote = if self.eat("\"") { "\"" } else { "'" };
+            if quote == "'" && !self.eat("'") {
+                return Err(anyhow!("bad attribute"));
+            }
+            let value = unescape(self.skip_past(quote)?)?;
+            if attr == "xmlns" {
+                self.scopes.push((String::new(), value));
+            } else if let Some(prefix) = attr.strip_prefix("xmlns:") {
+                self.scopes.push((prefix.to_owned(), value));
+            } else if !attr.contains(':') {
+                attrs.push((attr.to_owned(), value));
+            }
+        };
+        let (prefix, name) = qname.split_once(':').unwrap_or(("", qname));
+        let mut el = Element {
+            ns: self.resolve(prefix)?,
+            name: name.to_owned(),
+            attrs,
+            children: Vec::new(),
+        };
+        if !self_closed {
+            loop {
+                if self.eat("</") {
+                    if self.name() != qname {
+                        return Err(anyhow!("mismatched end tag"));
+                    }
+                    self.skip_space();
+                    if !self.eat(">") {
+                        return Err(anyhow!("bad end tag"));
+                    }
+                    break;
+                } else if self.eat("<![CDATA[") {
+                    let text = self.skip_past("]]>")?.to_owned();
+                    el.children.push(Node::Text(text));
+                } else if self.skip_misc()? {
+                    continue;
+                } else if self.s.starts_with('<') {
+                    el.children.push(Node::Element(self.element(depth + 1)?));
+                } else if self.s.is_empty() {
+                    return Err(anyhow!("unclosed element"));
+                } else {
+                    let end = self.s.find('<').unwrap_or(self.s.len());
+                    let (text, rest) = self.s.split_at(end);
+                    self.s = rest;
+                    if !text.trim().is_empty() {
+                        el.children.push(Node::Text(unescape(text)?));
+                    }
+                }
+            }
+        }
+        self.scopes.truncate(scopes_len);
+        Ok(el)
+    }
+}
+
+/// Parse the document, returns the root element.
+pub fn parse(s: &str) -> Result<Element> {
+    let mut p = Parser {
+        s: s.trim_start_matches('\u{feff}'),
+        scopes: Vec::new(),
+    };
+    loop {
+        p.skip_space();
+        if !p.skip_misc()? {
+            break;
+        }
+    }
+    let root = p.element(0)?;
+    loop {
+        p.skip_space();
+        if !p.skip_misc()? {
+            break;
+        }
+    }
+    match p.s.is_empty() {
+        true => Ok(root),
+        false => Err(anyhow!("content after root element")),
+    }
+}
@@ -3,16 +3,14 @@
 //! The prototype is https://github.com/kkocdko/user-scripts/blob/master/scripts/just-kit/health-check-in.js
 
 use crate::auth::auth_layer;
-use crate::ticker::Ticker;
 use crate::utils::{fetch, fetch_json, fetch_text, log_escape, OptionResult};
-use crate::{care, db, include_page};
+use crate::{care, db, include_page, ticker};
 use anyhow::Result;
 use axum::extract::RawQuery;
 use axum::http::header::{HeaderName, CONTENT_TYPE, USER_AGENT};
 use axum::middleware;
 use axum::response::{Html, IntoResponse, Redirect};
 use axum::routing::{MethodRouter, Router};
-use once_cell::sync::Lazy;
 use std::fmt::Write;
 mod cryptojs;
 
@@ -152,11 +150,8 @@ pub fn service() -> Router {
         )
 }
 
-static TICKER: Lazy<Ticker> = Lazy::new(|| Ticker::new_p8(&[(6, 2, 0), (8, 2, 0)]));
 pub async fn tick() {
-    if !TICKER.tick() {
-        return;
-    }
+    ticker!(return, 8, "06:02:00", "08:02:00");
 
     care!(check_in().await).ok();
     db_log_clean();
@@ -0,0 +1,215 @@
+//! Host system metrics collector, reads `/proc` and `statvfs` on Linux.
+
+use crate::units::dav;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Sample every 10 seconds, keep 1 hour.
+const INTERVAL: Duration = Duration::from_secs(10);
+const HISTORY_LEN: usize = 360;
+
+/// The dav usage scans the whole table, so it's refreshed every 30 samples.
+const DAV_USAGE_SAMPLES: usize = 30;
+
+/// Fields are `None` if unavailable, like on non-Linux systems.
+#[derive(Clone, Default)]
+pub struct Sample {
+    pub time: u64,
+    /// Percent of all cpus, since the last sample.
+    pub cpu_usage: Option<f64>,
+    pub load_avg: Option<(f64, f64, f64)>,
+    pub mem_total: Option<u64>,
+    pub mem_available: Option<u64>,
+    pub process_rss: Option<u64>,
+    pub open_fds: Option<u64>,
+    /// Of the volume holding the database file.
+    pub disk_total: Option<u64>,
+    pub disk_available: Option<u64>,
+    pub db_file_size: Option<u64>,
+    pub dav_storage: u64,
+    /// Of the volume holding the paste storage, which may be mounted elsewhere.
+    pub paste_disk_total: Option<u64>,
+    pub paste_disk_available: Option<u64>,
+    /// Bytes per second of all interfaces except loopback, since the last sample.
+    pub net_rx: Option<u64>,
+    pub net_tx: Option<u64>,
+    pub tokio_workers: usize,
+    pub tokio_alive_tasks: usize,
+    pub tokio_global_queue: usize,
+}
+
+impl Sample {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "time": self.time,
+            "cpu_usage": self.cpu_usage,
+            "load_avg": self.load_avg.map(|v| [v.0, v.1, v.2]),
+            "mem_total": self.mem_total,
+            "mem_available": self.mem_available,
+            "process_rss": self.process_rss,
+            "open_fds": self.open_fds,
+            "disk_total": self.disk_total,
+            "disk_available": self.disk_available,
+            "db_file_size": self.db_file_size,
+            "dav_storage": self.dav_storage,
+            "paste_disk_total": self.paste_disk_total,
+            "paste_disk_available": self.paste_disk_available,
+            "net_rx": self.net_rx,
+            "net_tx": self.net_tx,
+            "tokio_workers": self.tokio_workers,
+            "tokio_alive_tasks": self.tokio_alive_tasks,
+            "tokio_global_queue": self.tokio_global_queue,
+        })
+    }
+}
+
+pub static HISTORY: Mutex<VecDeque<Sample>> = Mutex::new(VecDeque::new());
+
+/// Find the line starts with `key` and parse the first number after it.
+fn proc_field(content: &str, key: &str) -> Option<u64> {
+    let line = content.lines().find(|v| v.starts_with(key))?;
+    line[key.len()..].split_whitespace().next()?.parse().ok()
+}
+
+fn load_avg() -> Option<(f64, f64, f64)> {
+    let s = std::fs::read_to_string("/proc/loadavg").ok()?;
+    let mut it = s.split_whitespace().map(|v| v.parse().ok());
+    Some((it.next()??, it.next()??, it.next()??))
+}
+
+/// Returns `(busy, total)` jiffies of all cpus.
+fn cpu_times() -> Option<(u64, u64)> {
+    let s = std::fs::read_to_string("/proc/stat").ok()?;
+    let line = s.lines().next()?.strip_prefix("cpu ")?;
+    let v: Vec<u64> = line
+        .split_whitespace()
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    let total = v.iter().sum();
+    let idle = v.get(3)? + v.get(4).unwrap_or(&0); // idle + iowait
+    Some((total - idle, total))
+}
+
+fn mem_info() -> Option<(u64, u64)> {
+    let s = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let total = proc_field(&s, "MemTotal:")? * 1024;
+    let available = proc_field(&s, "MemAvailable:")? * 1024;
+    Some((total, available))
+}
+
+fn process_rss() -> Option<u64> {
+    let s = std::fs::read_to_string("/proc/self/status").ok()?;
+    Some(proc_field(&s, "VmRSS:")? * 1024)
+}
+
+fn open_fds() -> Option<u64> {
+    Some(std::fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+}
+
+/// Returns `(rx, tx)` bytes of all interfaces except loopback.
+fn net_bytes() -> Option<(u64, u64)> {
+    let s = std::fs::read_to_string("/proc/net/dev").ok()?;
+    let mut ret = (0, 0);
+    for line in s.lines().skip(2) {
+        let (iface, v) = line.split_once(':')?;
+        if iface.trim() == "lo" {
+            continue;
+        }
+        let v: Vec<u64> = v
+            .split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        ret.0 += v.first()?;
+        ret.1 += v.get(8)?;
+    }
+    Some(ret)
+}
+
+/// Returns `(total, available)` bytes of the volume.
+#[cfg(unix)]
+fn disk_usage(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt as _;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // safety: the path is nul-terminated and the stat is written by statvfs when returns 0
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    let frsize = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * frsize, stat.f_bavail as u64 * frsize))
+}
+
+#[cfg(not(unix))]
+fn disk_usage(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+async fn collect_loop() {
+    let db_path = std::env::current_exe().unwrap().with_extension("db");
+    let paste_path = db_path.with_file_name("data").join("paste").join("storage");
+    let mut dav_storage = 0;
+    let mut last_cpu = cpu_times();
+    let mut last_net = net_bytes();
+    let mut interval = tokio::time::interval(INTERVAL);
+    for i in 0.. {
+        interval.tick().await;
+        if i % DAV_USAGE_SAMPLES == 0 {
+            dav_storage = dav::storage_usage().await.iter().map(|v| v.1).sum();
+        }
+        let cpu = cpu_times();
+        let cpu_usage = match (last_cpu, cpu) {
+            (Some((b0, t0)), Some((b1, t1))) if t1 > t0 => {
+                Some((b1 - b0) as f64 * 100.0 / (t1 - t0) as f64)
+            }
+            _ => None,
+        };
+        let net = net_bytes();
+        let secs = INTERVAL.as_secs();
+        let net_rate = match (last_net, net) {
+            (Some((r0, t0)), Some((r1, t1))) => {
+                Some((r1.saturating_sub(r0) / secs, t1.saturating_sub(t0) / secs))
+            }
+            _ => None,
+        };
+        (last_cpu, last_net) = (cpu, net);
+        let mem = mem_info();
+        let disk = disk_usage(&db_path);
+        let paste_disk = disk_usage(&paste_path);
+        let runtime = tokio::runtime::Handle::current().metrics();
+        let sample = Sample {
+            time: UNIX_EPOCH.elapsed().unwrap().as_secs(),
+            cpu_usage,
+            load_avg: load_avg(),
+            mem_total: mem.map(|v| v.0),
+            mem_available: mem.map(|v| v.1),
+            process_rss: process_rss(),
+            open_fds: open_fds(),
+            disk_total: disk.map(|v| v.0),
+            disk_available: disk.map(|v| v.1),
+            db_file_size: std::fs::metadata(&db_path).ok().map(|v| v.len()),
+            dav_storage,
+            paste_disk_total: paste_disk.map(|v| v.0),
+            paste_disk_available: paste_disk.map(|v| v.1),
+            net_rx: net_rate.map(|v| v.0),
+            net_tx: net_rate.map(|v| v.1),
+            tokio_workers: runtime.num_workers(),
+            tokio_alive_tasks: runtime.num_alive_tasks(),
+            tokio_global_queue: runtime.global_queue_depth(),
+        };
+        let mut history = HISTORY.lock().unwrap();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(sample);
+    }
+}
+
+pub fn init() {
+    tokio::spawn(collect_loop());
+}
@@ -1,104 +1,295 @@
 //! Provide server info.
 
-use crate::include_src;
-use crate::utils::{fetch_text, str2req};
-use axum::http::header::{CACHE_CONTROL, REFRESH};
+use crate::access::{BUCKETS_MS, HISTOGRAMS};
+use crate::auth::auth_layer;
+use crate::template;
+use crate::utils::LazyLock;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::middleware;
 use axum::response::{Html, IntoResponse};
 use axum::routing::{MethodRouter, Router};
+use serde_json::{json, Value};
+use std::fmt::Write as _;
 use std::sync::atomic::{AtomicI64, Ordering};
-use std::time::{Duration, Instant, UNIX_EPOCH};
+use std::time::UNIX_EPOCH;
+mod host;
+mod probe;
+pub use probe::{add_notifier, set_probes, Notifier};
 
-// static SYS_VER: LazyLock<String> = LazyLock::new(|| {
-//     if cfg!(target_os = "linux") {
-//         std::fs::read_to_string("/proc/version").unwrap()
-//     } else {
-//         "unknown".into()
-//     }
-// });
+static SYS_VER: LazyLock<String> = LazyLock::new(|| {
+    let v = std::fs::read_to_string("/proc/version").unwrap_or_else(|_| "unknown".into());
+    v.trim_end().to_owned()
+});
 
 static START_TIME: AtomicI64 = AtomicI64::new(0);
-static LAST_REFRESH: AtomicI64 = AtomicI64::new(0);
-static LATENCY_BAIDU: AtomicI64 = AtomicI64::new(0);
-static LATENCY_ALIYUN: AtomicI64 = AtomicI64::new(0);
-
-async fn refresh(uri: &str, data: &AtomicI64) {
-    let instant = Instant::now();
-    let req = str2req(uri);
-    match tokio::time::timeout(Duration::from_secs(3), fetch_text(req)).await {
-        Ok(Ok(_)) => data.store(instant.elapsed().as_millis() as _, Ordering::SeqCst),
-        Ok(Err(_)) => data.store(-9, Ordering::SeqCst), // network error
-        Err(_) => data.store(-7, Ordering::SeqCst),     // timeout
+
+/// Render values like `▂▃▂▁▇×▂`, the `×` means failed. Input is the newest first, output is the newest at right.
+fn sparkline(log: &[(u64, bool)], max: u64) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = max.max(1);
+    let bar = |&(v, ok): &(u64, bool)| match ok {
+        true => BARS[(v.min(max) * (BARS.len() as u64 - 1) / max) as usize],
+        false => '×',
     };
+    log.iter().rev().map(bar).collect()
 }
 
-async fn get_handler() -> impl IntoResponse {
-    const PAGE: [&str; 2] = include_src!("page.html");
+fn readable_size(v: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut v = v as f64;
+    let mut i = 0;
+    while v >= 1024.0 && i < UNITS.len() - 1 {
+        v /= 1024.0;
+        i += 1;
+    }
+    format!("{v:.1} {}", UNITS[i])
+}
 
+/// The data behind both the html page and the json api, so the two views can't drift.
+async fn model() -> Value {
     let now = UNIX_EPOCH.elapsed().unwrap().as_secs() as i64;
 
+    let host = {
+        let history = host::HISTORY.lock().unwrap();
+        let cpu_history: Vec<_> = history.iter().rev().take(60).map(|v| v.cpu_usage).collect();
+        match history.back() {
+            Some(cur) => json!({ "current": cur.to_json(), "cpu_history": cpu_history }),
+            None => Value::Null,
+        }
+    };
+
+    let mut units = Vec::new();
+    for unit in crate::units::enabled() {
+        let health = unit.health().await;
+        let msg = health.err().map(|e| e.to_string());
+        units.push(json!({ "name": unit.name(), "ok": msg.is_none(), "msg": msg }));
+    }
+
+    let since = now as u64 - 3600 * 24;
+    let mut probes = Vec::new();
+    let probe_list = probe::PROBES.lock().unwrap().clone();
+    for probe in probe_list {
+        let (total, ok) = probe::db::count_log(probe.name.to_owned(), since).await;
+        let log = probe::db::list_log(probe.name.to_owned(), since, 32).await;
+        let history: Vec<_> = log
+            .iter()
+            .map(|&(latency, ok)| json!({ "latency": latency, "ok": ok }))
+            .collect();
+        probes.push(json!({
+            "name": probe.name,
+            "kind": probe.kind,
+            "target": probe.target,
+            "uptime_24h": (total != 0).then(|| ok as f64 * 100.0 / total as f64),
+            "history": history,
+        }));
+    }
+
+    let routes: Vec<_> = HISTOGRAMS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(route, h)| {
+            json!({
+                "route": route,
+                "count": h.count,
+                "p50": h.quantile_ms(0.5),
+                "p99": h.quantile_ms(0.99),
+                "counts": h.counts,
+            })
+        })
+        .collect();
+
+    json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "sqlite_version": rusqlite::version(),
+        "os": *SYS_VER,
+        "uptime": now - START_TIME.load(Ordering::SeqCst),
+        "host": host,
+        "units": units,
+        "probes": probes,
+        "buckets_ms": BUCKETS_MS,
+        "routes": routes,
+    })
+}
+
+fn write_host(o: &mut String, host: &Value) {
+    let Some(cur) = host.get("current") else {
+        *o += "host : collecting\n";
+        return;
+    };
+    let na = || "n/a".to_owned();
+    let size = |k: &str| cur[k].as_u64().map_or_else(na, readable_size);
+    let cpu = cur["cpu_usage"]
+        .as_f64()
+        .map_or_else(na, |v| format!("{v:.1}%"));
+    let load = match cur["load_avg"].as_array() {
+        Some(v) => format!("{} {} {}", v[0], v[1], v[2]),
+        None => na(),
+    };
+    let cpu_log: Vec<_> = (host["cpu_history"].as_array().unwrap().iter())
+        .map(|v| (v.as_f64().unwrap_or(0.0) as u64, !v.is_null()))
+        .collect();
+    writeln!(
+        o,
+        "cpu : {cpu} | load {load} | {}",
+        sparkline(&cpu_log, 100)
+    )
+    .unwrap();
+    let used = |total: &str, available: &str| {
+        let v = cur[total].as_u64().zip(cur[available].as_u64());
+        v.map_or_else(na, |v| readable_size(v.0 - v.1))
+    };
+    let (mem_used, mem_total) = (used("mem_total", "mem_available"), size("mem_total"));
+    let rss = size("process_rss");
+    let fds = cur["open_fds"].as_u64().map_or_else(na, |v| v.to_string());
+    writeln!(
+        o,
+        "memory : {mem_used} / {mem_total} | rss {rss} | fds {fds}"
+    )
+    .unwrap();
+    let (disk_used, disk_total) = (used("disk_total", "disk_available"), size("disk_total"));
+    let (db, dav) = (size("db_file_size"), size("dav_storage"));
+    writeln!(o, "disk : {disk_used} / {disk_total} | db {db} | dav {dav}").unwrap();
+    if !cur["paste_disk_total"].is_null() {
+        let paste_used = used("paste_disk_total", "paste_disk_available");
+        let paste_total = size("paste_disk_total");
+        writeln!(o, "paste disk : {paste_used} / {paste_total}").unwrap();
+    }
+    let (rx, tx) = (size("net_rx"), size("net_tx"));
+    writeln!(o, "network : rx {rx}/s | tx {tx}/s").unwrap();
+    let workers = &cur["tokio_workers"];
+    let (tasks, queue) = (&cur["tokio_alive_tasks"], &cur["tokio_global_queue"]);
+    writeln!(
+        o,
+        "tokio : workers {workers} | tasks {tasks} | queue {queue}"
+    )
+    .unwrap();
+}
+
+fn render(m: &Value) -> String {
+    let s = |v: &Value| v.as_str().unwrap().to_owned();
+
     let mut o = String::new();
 
-    o += PAGE[0];
+    writeln!(&mut o, "{} version : {}", s(&m["name"]), s(&m["version"])).unwrap();
+    writeln!(&mut o, "sqlite version : {}", s(&m["sqlite_version"])).unwrap();
+    writeln!(&mut o, "os : {}", s(&m["os"])).unwrap();
+    writeln!(&mut o, "uptime : {} s", m["uptime"]).unwrap();
 
-    o += concat!(
-        env!("CARGO_PKG_NAME"),
-        " version : ",
-        env!("CARGO_PKG_VERSION"),
-        "\nsqlite version : ",
-    );
-    o += rusqlite::version();
-    o += "\n";
+    write_host(&mut o, &m["host"]);
 
-    // o += "os : ";
-    // o += &SYS_VER;
-    // o += "\n";
-
-    o += "uptime : ";
-    o += &(now - START_TIME.load(Ordering::SeqCst)).to_string();
-    o += " s\n";
-
-    if now - LAST_REFRESH.load(Ordering::SeqCst) > 5 {
-        tokio::spawn(async move {
-            LAST_REFRESH.store(now, Ordering::SeqCst);
-            tokio::join!(
-                refresh("http://baidu.com/404", &LATENCY_BAIDU),
-                refresh("http://aliyun.com/404", &LATENCY_ALIYUN),
-            );
-        });
-        return ([(REFRESH, "1")], Html(o + PAGE[1]));
+    o += "units :";
+    let units = m["units"].as_array().unwrap();
+    for unit in units.iter().filter(|v| v["ok"].as_bool().unwrap()) {
+        o += " ";
+        o += unit["name"].as_str().unwrap();
+    }
+    o += "\n";
+    for unit in units.iter().filter(|v| !v["ok"].as_bool().unwrap()) {
+        let (name, msg) = (s(&unit["name"]), s(&unit["msg"]));
+        writeln!(&mut o, "unit {name} : {msg}").unwrap();
     }
 
-    o += "server <-> baidu : ";
-    match LATENCY_BAIDU.load(Ordering::SeqCst) {
-        -9 => o += "network error\n",
-        -7 => o += "timeout\n",
-        v => {
-            o += &v.to_string();
-            o += " ms\n";
+    for probe in m["probes"].as_array().unwrap() {
+        let log: Vec<_> = (probe["history"].as_array().unwrap().iter())
+            .map(|v| (v["latency"].as_u64().unwrap(), v["ok"].as_bool().unwrap()))
+            .collect();
+        write!(&mut o, "server <-> {} : ", s(&probe["name"])).unwrap();
+        match log.first() {
+            None => o += "pending",
+            Some((_, false)) => o += "down",
+            Some((latency, true)) => write!(&mut o, "{latency} ms").unwrap(),
         }
+        if let Some(uptime) = probe["uptime_24h"].as_f64() {
+            let max = log.iter().map(|v| v.0).max().unwrap_or(0);
+            write!(&mut o, " | {uptime:.2}% 24h | {}", sparkline(&log, max)).unwrap();
+        }
+        o += "\n";
     }
 
-    o += "server <-> aliyun : ";
-    match LATENCY_ALIYUN.load(Ordering::SeqCst) {
-        -9 => o += "network error\n",
-        -7 => o += "timeout\n",
-        v => {
-            o += &v.to_string();
-            o += " ms\n";
+    o += "\nroute latency histogram (ms) :\n";
+    write!(
+        &mut o,
+        "{:<16}{:>8}{:>6}{:>6}",
+        "route", "count", "p50", "p99"
+    )
+    .unwrap();
+    for le in m["buckets_ms"].as_array().unwrap() {
+        write!(&mut o, "{:>6}", format!("<{le}")).unwrap();
+    }
+    o += "   inf\n";
+    for h in m["routes"].as_array().unwrap() {
+        let q = |v: &Value| v.as_u64().map_or("inf".to_owned(), |v| v.to_string());
+        write!(
+            &mut o,
+            "{:<16}{:>8}{:>6}{:>6}",
+            s(&h["route"]),
+            h["count"],
+            q(&h["p50"]),
+            q(&h["p99"])
+        )
+        .unwrap();
+        for c in h["counts"].as_array().unwrap() {
+            write!(&mut o, "{c:>6}").unwrap();
         }
+        o += "\n";
     }
 
-    o += PAGE[1];
+    template!("page.html", title = "Info").render(&json!({ "text": o }))
+}
+
+async fn host_handler() -> impl IntoResponse {
+    let history = host::HISTORY.lock().unwrap();
+    let samples: Vec<_> = history.iter().map(host::Sample::to_json).collect();
+    let headers = [
+        (CACHE_CONTROL, "no-store"),
+        (CONTENT_TYPE, "application/json"),
+    ];
+    (headers, Value::from(samples).to_string())
+}
+
+async fn get_handler() -> impl IntoResponse {
+    ([(CACHE_CONTROL, "no-store")], Html(render(&model().await)))
+}
 
-    ([(CACHE_CONTROL, "no-store")], Html(o))
+async fn api_handler() -> impl IntoResponse {
+    let headers = [
+        (CACHE_CONTROL, "no-store"),
+        (CONTENT_TYPE, "application/json"),
+    ];
+    (headers, model().await.to_string())
 }
 
-pub fn service() -> Router {
+fn service() -> Router {
+    probe::init();
+    host::init();
     START_TIME.store(
         UNIX_EPOCH.elapsed().unwrap().as_secs() as _,
         Ordering::SeqCst,
     );
+    // the host metrics and probe targets are not for the public
     Router::new()
         .route("/info", MethodRouter::new().get(get_handler))
+        .route("/info/api", MethodRouter::new().get(api_handler))
+        .route("/info/host", MethodRouter::new().get(host_handler))
+        .route_layer(middleware::from_fn(auth_layer))
         .route("/info/p", MethodRouter::new().get("pong")) // the "/ping" cause error?
 }
+
+pub struct Info;
+
+impl crate::units::Unit for Info {
+    fn name(&self) -> &'static str {
+        "info"
+    }
+    fn migrate(&self) -> crate::units::BoxFuture<()> {
+        Box::pin(probe::migrate())
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["info_probes (json array)", "info_notify_webhook"]
+    }
+}
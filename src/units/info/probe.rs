@@ -0,0 +1,349 @@
+//! Health probes with history, and notifiers for state changes.
+
+use crate::units::admin;
+use crate::utils::{escape_check_html, OptionResult, CLIENT};
+use crate::{care, log};
+use anyhow::Result;
+use axum::body::Body;
+use axum::http::header::HOST;
+use axum::http::{Request, Uri};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Keep the history in database for 7 days.
+const HISTORY_TTL: u64 = 3600 * 24 * 7;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+pub mod db {
+    use crate::database::DB;
+    use crate::strip_str;
+    /// Returns true if the tables are newly created.
+    pub async fn init() -> bool {
+        DB.call(|db| {
+            let created = db.prepare("SELECT 1 FROM info_probes LIMIT 0").is_err();
+            // info_probes: name = "baidu", kind = "http" | "tcp" | "tls", target = "http://baidu.com/404" | "baidu.com:443", interval (seconds) = 60
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS info_probes (name BLOB PRIMARY KEY, kind BLOB, target BLOB, interval INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            // info_probe_log: time (seconds), latency (milliseconds, 0 if failed), ok = 0 | 1
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS info_probe_log (name BLOB, time INTEGER, latency INTEGER, ok INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            let sql = strip_str! {"
+                CREATE INDEX IF NOT EXISTS info_probe_log_name_time ON info_probe_log (name, time)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
+            created
+        })
+        .await
+    }
+    pub async fn list_probes() -> Vec<(String, String, String, u64)> {
+        DB.call(|db| {
+            let sql = strip_str! {"
+                SELECT name, kind, target, interval FROM info_probes
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            let v2s = |v| String::from_utf8(v).unwrap();
+            stmd.query_map((), |r| {
+                Ok((v2s(r.get(0)?), v2s(r.get(1)?), v2s(r.get(2)?), r.get(3)?))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+        })
+        .await
+    }
+    pub async fn set_probes(probes: Vec<(String, String, String, u64)>) {
+        DB.call(move |db| {
+            let tx = db.transaction().unwrap();
+            tx.execute("DELETE FROM info_probes", ()).unwrap();
+            for (name, kind, target, interval) in probes {
+                let sql = strip_str! {"
+                    INSERT INTO info_probes VALUES (?, ?, ?, ?)
+                "};
+                let mut stmd = tx.prepare_cached(sql).unwrap();
+                stmd.execute((
+                    name.as_bytes(),
+                    kind.as_bytes(),
+                    target.as_bytes(),
+                    interval,
+                ))
+                .unwrap();
+            }
+            tx.commit().unwrap();
+        })
+        .await
+    }
+    pub async fn add_log(name: String, time: u64, latency: u64, ok: bool) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                INSERT INTO info_probe_log VALUES (?, ?, ?, ?)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((name.as_bytes(), time, latency, ok)).unwrap();
+        })
+        .await
+    }
+    /// Returns `(latency, ok)` of recent records, the newest first.
+    pub async fn list_log(name: String, since: u64, limit: u64) -> Vec<(u64, bool)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT latency, ok FROM info_probe_log WHERE name = ? AND time >= ? ORDER BY time DESC LIMIT ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_map((name.as_bytes(), since, limit), |r| Ok((r.get(0)?, r.get(1)?)))
+                .unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        })
+        .await
+    }
+    /// Returns `(total, ok)` count of records.
+    pub async fn count_log(name: String, since: u64) -> (u64, u64) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT count(*), ifnull(sum(ok), 0) FROM info_probe_log WHERE name = ? AND time >= ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((name.as_bytes(), since), |r| Ok((r.get(0)?, r.get(1)?)))
+                .unwrap()
+        })
+        .await
+    }
+    pub async fn clean_log(before: u64) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM info_probe_log WHERE time < ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((before,)).unwrap();
+        })
+        .await
+    }
+}
+
+#[derive(Clone)]
+pub struct Probe {
+    pub name: String,
+    pub kind: String,
+    pub target: String,
+    pub interval: u64,
+}
+
+/// The in-memory copy of `info_probes` table.
+pub static PROBES: Mutex<Vec<Probe>> = Mutex::new(Vec::new());
+
+/// Last known state of each probe, `true` means up.
+static STATES: Mutex<Option<HashMap<String, bool>>> = Mutex::new(None);
+
+/// A receiver of alert messages.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, msg: String) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+}
+
+static NOTIFIERS: Mutex<Vec<Arc<dyn Notifier>>> = Mutex::new(Vec::new());
+
+/// Register a notifier, it will receive all alerts since now.
+pub fn add_notifier(notifier: impl Notifier + 'static) {
+    NOTIFIERS.lock().unwrap().push(Arc::new(notifier));
+}
+
+/// Write alerts to log.
+struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, msg: String) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> {
+        log!(warn: "info probe alert: {msg}");
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// POST alerts as plain text to the uri in `info_notify_webhook`.
+struct WebhookNotifier;
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, msg: String) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> {
+        Box::pin(async move {
+            let Some(uri) = admin::db::get("info_notify_webhook".to_owned()).await else {
+                return Ok(()); // not configured
+            };
+            let uri = Uri::try_from(Vec::from(uri))?;
+            if !matches!(uri.scheme_str(), Some("http" | "https")) {
+                return Err(anyhow::anyhow!("scheme must be http or https"));
+            }
+            let req = Request::post(&uri)
+                .header(HOST, uri.host().e()?)
+                .body(Body::from(msg))?;
+            CLIENT.fetch(req, None).await?;
+            Ok(())
+        })
+    }
+}
+
+async fn notify_all(msg: String) {
+    let notifiers = NOTIFIERS.lock().unwrap().clone();
+    for notifier in notifiers {
+        care!(notifier.notify(msg.clone()).await).ok();
+    }
+}
+
+/// The GET request of an http target, which must be http or https with host, as the client panics on others.
+fn http_request(target: &str) -> Result<Request<Body>> {
+    let uri = Uri::try_from(target)?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) {
+        return Err(anyhow::anyhow!("scheme must be http or https"));
+    }
+    let host = uri.host().e()?.to_owned();
+    Ok(Request::get(uri).header(HOST, host).body(Body::empty())?)
+}
+
+/// Check the target like `host:port` of tcp and tls.
+fn host_port(target: &str) -> Result<()> {
+    let (host, port) = target.rsplit_once(':').e()?;
+    if host.is_empty() || port.parse::<u16>().is_err() {
+        return Err(anyhow::anyhow!("target must be host:port"));
+    }
+    Ok(())
+}
+
+async fn check(probe: &Probe) -> Result<()> {
+    match probe.kind.as_str() {
+        "http" => {
+            let res = CLIENT.fetch(http_request(&probe.target)?, None).await?;
+            if res.status().is_server_error() {
+                return Err(anyhow::anyhow!("status {}", res.status()));
+            }
+        }
+        "tcp" => {
+            tokio::net::TcpStream::connect(&probe.target).await?;
+        }
+        "tls" => {
+            let host = probe.target.rsplit_once(':').e()?.0;
+            CLIENT.handshake(host, &probe.target).await?;
+        }
+        _ => return Err(anyhow::anyhow!("unknown probe kind")),
+    }
+    Ok(())
+}
+
+async fn run_probe(probe: Probe, now: u64) {
+    let instant = Instant::now();
+    let result = match tokio::time::timeout(TIMEOUT, check(&probe)).await {
+        Ok(v) => v,
+        Err(_) => Err(anyhow::anyhow!("timeout")),
+    };
+    let ok = result.is_ok();
+    let latency = if ok {
+        instant.elapsed().as_millis() as u64
+    } else {
+        0
+    };
+    db::add_log(probe.name.to_owned(), now, latency, ok).await;
+    let prev = {
+        let mut states = STATES.lock().unwrap();
+        let states = states.get_or_insert_with(HashMap::new);
+        states.insert(probe.name.to_owned(), ok)
+    };
+    if prev.is_some_and(|v| v != ok) {
+        let msg = match result {
+            Ok(_) => format!("probe {} is up, latency = {latency} ms", probe.name),
+            Err(e) => format!("probe {} is down, error = {e}", probe.name),
+        };
+        notify_all(msg).await;
+    }
+}
+
+async fn probe_loop() {
+    let mut last_runs = HashMap::new();
+    let mut last_clean = 0;
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+        let probes = PROBES.lock().unwrap().clone();
+        for probe in probes {
+            let last_run = last_runs.entry(probe.name.to_owned()).or_insert(0);
+            if now < *last_run + probe.interval {
+                continue;
+            }
+            *last_run = now;
+            tokio::spawn(run_probe(probe, now));
+        }
+        if now - last_clean > 3600 {
+            last_clean = now;
+            db::clean_log(now - HISTORY_TTL).await;
+        }
+    }
+}
+
+/// Replace all probes by a json array like `[{"name":"baidu","kind":"http","target":"http://baidu.com/404","interval":60}]`.
+pub async fn set_probes(json: &[u8]) -> Result<()> {
+    let mut probes = Vec::new();
+    for v in serde_json::from_slice::<Vec<serde_json::Value>>(json)? {
+        let field = |k| v.get(k).and_then(|v| v.as_str()).map(str::to_owned).e();
+        let (name, kind, target) = (field("name")?, field("kind")?, field("target")?);
+        let interval = v.get("interval").and_then(|v| v.as_u64()).e()?.max(1);
+        match kind.as_str() {
+            "http" => {
+                http_request(&target)?;
+            }
+            "tcp" | "tls" => host_port(&target)?,
+            _ => return Err(anyhow::anyhow!("unknown probe kind")),
+        }
+        if !escape_check_html(name.as_bytes()) || !escape_check_html(target.as_bytes()) {
+            return Err(anyhow::anyhow!("name or target contains html chars"));
+        }
+        probes.push((name, kind, target, interval));
+    }
+    db::set_probes(probes).await;
+    load_probes().await;
+    Ok(())
+}
+
+async fn load_probes() {
+    let probes = db::list_probes().await;
+    let probes = probes
+        .into_iter()
+        .map(|(name, kind, target, interval)| Probe {
+            name,
+            kind,
+            target,
+            interval,
+        });
+    *PROBES.lock().unwrap() = probes.collect();
+}
+
+pub async fn migrate() {
+    // seed only once, the list may be cleared by admin on purpose
+    if db::init().await {
+        let defaults = [
+            ("baidu", "http", "http://baidu.com/404", 60),
+            ("aliyun", "http", "http://aliyun.com/404", 60),
+        ];
+        let defaults = defaults.iter().map(|&(name, kind, target, interval)| {
+            (
+                name.to_owned(),
+                kind.to_owned(),
+                target.to_owned(),
+                interval,
+            )
+        });
+        db::set_probes(defaults.collect()).await;
+    }
+    load_probes().await;
+}
+
+pub fn init() {
+    add_notifier(LogNotifier);
+    add_notifier(WebhookNotifier);
+    tokio::spawn(probe_loop());
+}
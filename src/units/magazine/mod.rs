@@ -1,34 +1,31 @@
 //! Collections of my favorite news source.
 
+use crate::scheduler::{CatchUp, Job};
 use crate::utils::{fetch_text, str2req, LazyLock, OptionResult};
-use crate::{care, include_src, log, ticker};
+use crate::{care, crons, log, template};
 use anyhow::Result;
 use axum::body::Bytes;
 use axum::http::header::*;
 use axum::response::Html;
 use axum::routing::{MethodRouter, Router};
-use std::fmt::Write as _;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-fn generate(mut i: &str, o: &mut String) -> Result<()> {
-    // let mut limit = 20;
+/// Parse rss items into `{ title, content, link }`, the content is html with only `<br>` tags,
+/// the link is empty if not `http(s)`.
+fn parse(mut i: &str, items: &mut Vec<Value>) -> Result<()> {
     while let Some(mut p) = i.split_once("<item>") {
-        // limit -= 1;
-
-        *o += "\n<details>\n";
-
         // title
         i = p.1.split_once("<![CDATA[").e()?.1;
         p = i.split_once("]]>").e()?;
-        *o += "<summary>";
-        *o += p.0;
-        *o += "</summary>\n";
+        let title = p.0;
 
         // content
         i = p.1.split_once("<![CDATA[").e()?.1;
         p = i.split_once("]]>").e()?;
-        *o += "<section>";
+        let mut content = String::new();
         let break_marks = [
             "br>", "p>", "p ", "/p>", "div>", "div ", "/div>", "li>", "li ", "/li>",
         ];
@@ -36,51 +33,63 @@ fn generate(mut i: &str, o: &mut String) -> Result<()> {
             p.0 = v.1.split_once('>').e()?.1;
             let c = v.0.trim();
             if !c.is_empty() {
-                *o += c;
+                content += c;
             }
-            if !o.ends_with("<br>") {
+            if !content.ends_with("<br>") {
                 for mark in break_marks {
                     if v.1.starts_with(mark) {
-                        *o += "<br>";
+                        content += "<br>";
                         break;
                     }
                 }
             }
         }
-        *o += "</section>\n";
 
         // link
         i = p.1.split_once("<link>").e()?.1;
         p = i.split_once("</link>").e()?;
-        *o += "<a href=\"";
-        *o += p.0;
-        *o += "\">[ Original Link ]</a>\n";
-
-        *o += "</details>\n";
+        // the link goes into `href`, other schemes like `javascript:` are dropped
+        let link = p.0.trim();
+        let link = match link.starts_with("https://") || link.starts_with("http://") {
+            true => link,
+            false => "",
+        };
+        items.push(json!({ "title": title, "content": content, "link": link }));
         i = p.1;
-
-        // if limit == 0 {
-        //     break;
-        // }
     }
-    *o += "\n<br>\n";
     Ok(())
 }
 
-type Res = ([(HeaderName, HeaderValue); 2], Html<Bytes>);
+/// Render the model, which is also the json api response, so the two views can't drift.
+fn render(m: &Value) -> String {
+    let date = m["time"].as_u64().map(|v| {
+        let time = UNIX_EPOCH + Duration::from_secs(v + 3600 * 8);
+        httpdate::HttpDate::from(time).to_string()
+    });
+    let data = json!({ "date": date, "sources": m["sources"] });
+    template!("page.html", title = "Magazine").render(&data)
+}
+
+struct Cache {
+    headers: [(HeaderName, HeaderValue); 2],
+    html: Bytes, // `bytes::Bytes` is cheaper than `Vec<u8>` on clone
+    json: Bytes,
+}
 
-const PAGE: [&str; 2] = include_src!("page.html");
+/// The time of last successful refresh, `0` if never.
+static REFRESHED: AtomicU64 = AtomicU64::new(0);
 
-static CACHE: LazyLock<Mutex<Res>> = LazyLock::new(|| {
-    let body = format!("{}<h2>Magazine is generating ...</h2>{}", PAGE[0], PAGE[1]);
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(|| {
+    let m = json!({ "time": null, "sources": [] });
     // with small data, Mutex seems faster than RwLock
-    Mutex::new((
-        [
+    Mutex::new(Cache {
+        headers: [
             (CACHE_CONTROL, HeaderValue::from_static("no-store")),
             (REFRESH, HeaderValue::from_static("2")),
         ],
-        Html(Bytes::from(body)),
-    ))
+        html: Bytes::from(render(&m)),
+        json: Bytes::from(m.to_string()),
+    })
 });
 
 async fn refresh() -> Result<()> {
@@ -101,37 +110,39 @@ async fn refresh() -> Result<()> {
         log!(erro: "magazine fetch failed, p = {p}");
         Err(())
     }
+    let paths = [
+        "/bbc?limit=5",
+        "/hackernews?limit=5", // &mode=fulltext
+        "/zhihu/daily?limit=7",
+        "/oschina/news/industry?limit=7",
+        "/1point3acres/post/hot3?limit=7",
+        "/rustcc/jobs?limit=4",
+    ];
     // tokio::task::JoinSet
     let r = tokio::join!(
-        rss("/bbc?limit=5"),
-        rss("/hackernews?limit=5"), // &mode=fulltext
-        rss("/zhihu/daily?limit=7"),
-        rss("/oschina/news/industry?limit=7"),
-        rss("/1point3acres/post/hot3?limit=7"),
-        rss("/rustcc/jobs?limit=4"),
+        rss(paths[0]),
+        rss(paths[1]),
+        rss(paths[2]),
+        rss(paths[3]),
+        rss(paths[4]),
+        rss(paths[5]),
     );
-    let mut o = String::new();
-    o += PAGE[0];
-    write!(
-        &mut o,
-        "{}",
-        httpdate::HttpDate::from(SystemTime::now() + Duration::from_secs(3600 * 8))
-    )
-    .unwrap();
-    o += "+0800\n<br><br>";
-    r.0.map(|v| generate(&v, &mut o)).ok();
-    r.1.map(|v| generate(&v, &mut o)).ok();
-    r.2.map(|v| generate(&v, &mut o)).ok();
-    r.3.map(|v| generate(&v, &mut o)).ok();
-    r.4.map(|v| generate(&v, &mut o)).ok();
-    r.5.map(|v| generate(&v, &mut o)).ok();
-    o += PAGE[1];
-    // use std::io::Write as _;
-    // let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
-    // enc.write_all(o.as_bytes()).unwrap();
-    // enc.finish().unwrap()
-    *CACHE.lock().unwrap() = (
-        [
+    let r = [r.0, r.1, r.2, r.3, r.4, r.5];
+    let (mut sources, mut empty) = (Vec::new(), 0);
+    for (path, r) in paths.into_iter().zip(r) {
+        let mut items = Vec::new();
+        r.map(|v| parse(&v, &mut items)).ok(); // keep the items parsed before error
+        empty += items.is_empty() as usize;
+        sources.push(json!({ "path": path, "items": items }));
+    }
+    // keep the last cache, and let the scheduler retry
+    if empty == paths.len() {
+        return Err(anyhow::anyhow!("all sources failed"));
+    }
+    let time = UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let m = json!({ "time": time, "sources": sources });
+    *CACHE.lock().unwrap() = Cache {
+        headers: [
             (
                 EXPIRES,
                 HeaderValue::from_maybe_shared(Bytes::from(expires)).unwrap(),
@@ -140,27 +151,70 @@ async fn refresh() -> Result<()> {
                 HeaderName::from_static("server-timing"),
                 HeaderValue::from_static("missedCache"),
             ),
-            // (CONTENT_ENCODING, HeaderValue::from_static("gzip")),
         ],
-        Html(Bytes::from(o)), // `bytes::Bytes` is cheaper than `Vec<u8>` on clone
-    );
+        html: Bytes::from(render(&m)),
+        json: Bytes::from(m.to_string()),
+    };
+    REFRESHED.store(time, Ordering::SeqCst);
     Ok(())
 }
 
-pub fn service() -> Router {
+fn service() -> Router {
     tokio::spawn(async {
         care!(refresh().await).ok();
     });
-    Router::new().route(
-        "/magazine",
-        MethodRouter::new().get(|| async {
-            CACHE.lock().unwrap().to_owned() // just clone some AtomicPtr inner
-        }),
-    )
+    Router::new()
+        .route(
+            "/magazine",
+            MethodRouter::new().get(|| async {
+                let cache = CACHE.lock().unwrap();
+                (cache.headers.clone(), Html(cache.html.clone())) // just clone some AtomicPtr inner
+            }),
+        )
+        .route(
+            "/magazine/api",
+            MethodRouter::new().get(|| async {
+                let cache = CACHE.lock().unwrap();
+                let content_type = [(CONTENT_TYPE, "application/json")];
+                (cache.headers.clone(), content_type, cache.json.clone())
+            }),
+        )
+}
+fn job() -> Job {
+    Job {
+        name: "magazine",
+        crons: crons!("XX:04:00"),
+        zone: &8,
+        catch_up: CatchUp::Skip, // refreshed on startup
+        jitter: Duration::ZERO,
+        timeout: Duration::from_secs(45),
+        retries: 1,
+        retry_interval: Duration::from_secs(30),
+        run: || Box::pin(refresh()),
+    }
 }
 
-pub async fn tick() {
-    ticker!(return, 8, "XX:04:00");
+pub struct Magazine;
 
-    care!(refresh().await).ok();
+impl crate::units::Unit for Magazine {
+    fn name(&self) -> &'static str {
+        "magazine"
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn jobs(&self) -> Vec<Job> {
+        vec![job()]
+    }
+    fn health(&self) -> crate::units::BoxFuture<Result<()>> {
+        let refreshed = REFRESHED.load(Ordering::SeqCst);
+        let elapsed = UNIX_EPOCH.elapsed().unwrap().as_secs() - refreshed;
+        Box::pin(async move {
+            match refreshed {
+                0 => Err(anyhow::anyhow!("not refreshed yet")),
+                _ if elapsed > 3600 * 2 => Err(anyhow::anyhow!("not refreshed for {elapsed} s")),
+                _ => Ok(()),
+            }
+        })
+    }
 }
@@ -1,27 +1,32 @@
 //! WebRTC meeting, supports real-time cloud record.
 
 use super::chat::ChatServer;
-use crate::include_src;
+use crate::assets::Asset;
 use crate::utils::LazyLock as Lazy;
-use axum::http::header::{HeaderValue, CACHE_CONTROL};
-use axum::response::Html;
+use crate::{asset, template};
+use axum::http::HeaderMap;
 use axum::routing::{MethodRouter, Router};
 
 static CHAT_SERVER: Lazy<ChatServer> = Lazy::new(Default::default);
 
-pub fn service() -> Router {
+pub fn stats() -> (usize, u64) {
+    CHAT_SERVER.stats()
+}
+
+static SCRIPT: &Asset = asset!("page.mjs");
+
+static PAGE: Lazy<Asset> = Lazy::new(|| {
+    let page = template!("page.html").render(&serde_json::json!({ "script": SCRIPT.url() }));
+    Asset::new("page.html", page.into_bytes())
+});
+
+fn service() -> Router {
     // db::init();
     // ~/misc/apps/miniserve --header Cache-Control:no-store -p 9453 $(dirname $0)
     Router::new()
         .route(
             "/meet",
-            MethodRouter::new().get((
-                #[cfg(debug_assertions)]
-                [(CACHE_CONTROL, HeaderValue::from_static("no-store"))],
-                #[cfg(not(debug_assertions))]
-                [(CACHE_CONTROL, HeaderValue::from_static("max-age=300"))],
-                Html((include_src!("page.html") as [_; 1])[0]),
-            )),
+            MethodRouter::new().get(|headers: HeaderMap| async move { PAGE.serve(&headers) }),
         )
         .route("/meet/post/:room", CHAT_SERVER.post_router())
         .route("/meet/sse/:room", CHAT_SERVER.sse_router())
@@ -113,3 +118,17 @@ mod db {
 )
 .route_layer(middleware::from_fn(auth_layer))
 */
+
+pub struct Meet;
+
+impl crate::units::Unit for Meet {
+    fn name(&self) -> &'static str {
+        "meet"
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn assets(&self) -> Vec<&'static Asset> {
+        vec![SCRIPT]
+    }
+}
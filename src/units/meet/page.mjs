@@ -0,0 +1,88 @@
+const sdpTransform = (sdpStr) => {
+  // https://stackoverflow.com/questions/29302617/control-video-send-framerate-on-the-fly-in-webrtc
+  // https://stackoverflow.com/a/57674478/11338291
+  const [MAX, MIN, START] = [12000, 10000, 11000]; // bitrate
+  const ret = sdpStr.split("\r\n").map((e) => {
+    if (/^a=fmtp:\d*/.test(e)) {
+      e += `;x-google-max-bitrate=${MAX};x-google-min-bitrate=${MIN};x-google-start-bitrate=${START}`;
+    } else if (/^a=mid:(1|video)/.test(e)) {
+      e += `\r\nb=AS:${MAX}`;
+    }
+    return e;
+  });
+  return ret.join("\r\n");
+};
+for (const device of await navigator.mediaDevices.enumerateDevices())
+  ({ audioinput: $audio, videoinput: $video }[
+    device.kind
+  ]?.insertAdjacentHTML(
+    "beforeend",
+    `<option value="({deviceId:'${device.deviceId}'})">${device.label}</option>`
+  ));
+let stream, pc, sse;
+$audio.onchange = $video.onchange = async () => {
+  // https://stackoverflow.com/a/73550841/11338291
+  stream?.getTracks().forEach((t) => (t.stop(), stream.removeTrack(t)));
+  // width: { max: 1280 }, height: { max: 720 }, facingMode: { ideal: "environment" },
+  // https://developer.mozilla.org/en-US/docs/Web/API/MediaTrackConstraints
+  const streamCfg = { audio: eval($audio.value), video: eval($video.value) };
+  stream = await navigator.mediaDevices.getUserMedia(streamCfg);
+  $local.srcObject = stream;
+};
+$audio.onchange();
+$more.onchange = () => {
+  if ($more.value === "record" && confirm()) {
+  }
+  const modify = (el, f) => (el.selectedOptions[0].value = f(el.value));
+  if ($more.value === "audio_cfg") modify($audio, (v) => prompt("audio", v));
+  if ($more.value === "video_cfg") modify($video, (v) => prompt("video", v));
+  if ($more.value === "video_w1920")
+    modify($video, (v) => v.replace("({", "({width:1920,"));
+  if ($more.value === "video_r30")
+    modify($video, (v) => v.replace("({", "({frameRate:30,"));
+  $more.value = "";
+  $audio.onchange();
+};
+if (!location.hash)
+  location.hash = prompt("Room ID", (Math.random() * 1e5).toFixed());
+const [room, id] = [location.hash.slice(1), crypto.randomUUID()];
+[$local.className, $remote.className] = ["", "off"];
+const post = (o) =>
+  fetch(`/meet/post/${room}`, { method: "POST", body: JSON.stringify(o) });
+$trigger.onclick = async () => {
+  if ($trigger.textContent === "Stop") {
+    $trigger.textContent = "Connect";
+    sse.close(), pc.close();
+    [$local.className, $remote.className] = ["", "off"];
+  } else if ($trigger.textContent === "Connect") {
+    $trigger.textContent = "Call";
+    pc = new RTCPeerConnection();
+    pc.onicecandidate = (e) => post([id, "candidate", e.candidate]);
+    pc.ontrack = (e) => {
+      [$local.className, $remote.className] = ["sub", ""];
+      $remote.srcObject = e.streams[0];
+    };
+    for (const t of stream.getTracks()) pc.addTrack(t, stream);
+    // pc.addTrack(stream.getVideoTracks()[0], stream);
+    sse = new EventSource(`/meet/sse/${room}`);
+    sse.onmessage = async (e) => {
+      const [from, type, data] = JSON.parse(e.data);
+      if (from === id) return;
+      if (type === "candidate" && data) pc.addIceCandidate(data); // TODO: why error?
+      if (type === "offer" && confirm(`User ${from} call you, answer?`)) {
+        $trigger.textContent = "Stop";
+        await pc.setRemoteDescription(data);
+        const answer = await pc.createAnswer();
+        answer.sdp = sdpTransform(answer.sdp);
+        post([id, "answer", answer]);
+        pc.setLocalDescription(answer);
+      }
+      if (type === "answer") pc.setRemoteDescription(data);
+    };
+  } else if ($trigger.textContent === "Call") {
+    $trigger.textContent = "Stop";
+    const offer = await pc.createOffer();
+    post([id, "offer", offer]);
+    pc.setLocalDescription(offer);
+  }
+};
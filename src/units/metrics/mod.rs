@@ -0,0 +1,220 @@
+//! Prometheus metrics exporter, in text exposition format.
+
+use crate::access::{BUCKETS_MS, HISTOGRAMS};
+use crate::database::DB;
+use crate::units::{admin, chat, dav, meet};
+use crate::utils::bytes_eq;
+use anyhow::Result;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{MethodRouter, Router};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Default)]
+pub struct TickStats {
+    pub runs: u64,
+    /// Runs that returned error or did not finish, like cancelled by timeout.
+    pub failures: u64,
+    pub last_us: u64,
+    pub sum_us: u64,
+}
+
+/// Statistics of scheduled jobs, key is the job name.
+pub static TICKS: Mutex<BTreeMap<&'static str, TickStats>> = Mutex::new(BTreeMap::new());
+
+/// Run a job future and record the duration, count as failure if returned error or dropped before finished.
+pub async fn timed<F: Future<Output = Result<()>>>(name: &'static str, fut: F) -> Result<()> {
+    struct Guard(&'static str, Instant, bool);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let us = self.1.elapsed().as_micros() as u64;
+            let mut ticks = TICKS.lock().unwrap();
+            let stats = ticks.entry(self.0).or_default();
+            stats.runs += 1;
+            stats.failures += !self.2 as u64;
+            stats.last_us = us;
+            stats.sum_us += us;
+        }
+    }
+    let mut guard = Guard(name, Instant::now(), false);
+    let ret = fut.await;
+    guard.2 = ret.is_ok();
+    ret
+}
+
+/// https://prometheus.io/docs/instrumenting/exposition_formats/#text-format-details
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(o: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(o, "# HELP {name} {help}\n# TYPE {name} {kind}").unwrap();
+}
+
+fn sample(o: &mut String, name: &str, labels: &[(&str, &str)], v: impl std::fmt::Display) {
+    *o += name;
+    for (i, (k, v)) in labels.iter().enumerate() {
+        *o += if i == 0 { "{" } else { "," };
+        write!(o, "{k}=\"{}\"", escape_label(v)).unwrap();
+    }
+    if !labels.is_empty() {
+        *o += "}";
+    }
+    writeln!(o, " {v}").unwrap();
+}
+
+fn render_sync(o: &mut String) {
+    let name = "ksite_http_request_duration_seconds";
+    header(o, name, "histogram", "HTTP request latency by route.");
+    for (route, h) in HISTOGRAMS.lock().unwrap().iter() {
+        let mut acc = 0;
+        for (le, c) in BUCKETS_MS.iter().zip(h.counts) {
+            acc += c;
+            let le = (*le as f64 / 1e3).to_string();
+            sample(
+                o,
+                &(name.to_owned() + "_bucket"),
+                &[("route", route), ("le", &le)],
+                acc,
+            );
+        }
+        let bucket = name.to_owned() + "_bucket";
+        sample(o, &bucket, &[("route", route), ("le", "+Inf")], h.count);
+        let sum = h.sum_us as f64 / 1e6;
+        sample(o, &(name.to_owned() + "_sum"), &[("route", route)], sum);
+        sample(
+            o,
+            &(name.to_owned() + "_count"),
+            &[("route", route)],
+            h.count,
+        );
+    }
+
+    let name = "ksite_connections_active";
+    header(o, name, "gauge", "Connections being served.");
+    sample(
+        o,
+        name,
+        &[],
+        tls_http::ACTIVE_CONNECTIONS.load(Ordering::Relaxed),
+    );
+    let name = "ksite_tls_handshake_failures_total";
+    header(o, name, "counter", "Failed TLS handshakes.");
+    sample(
+        o,
+        name,
+        &[],
+        tls_http::TLS_HANDSHAKE_FAILURES.load(Ordering::Relaxed),
+    );
+
+    let name = "ksite_db_queue_depth";
+    header(o, name, "gauge", "Database calls waiting or running.");
+    sample(o, name, &[], DB.stats.pending.load(Ordering::Relaxed));
+    let name = "ksite_db_call_duration_seconds";
+    header(
+        o,
+        name,
+        "summary",
+        "Database call latency, including queueing.",
+    );
+    let sum = DB.stats.latency_us.load(Ordering::Relaxed) as f64 / 1e6;
+    sample(o, &(name.to_owned() + "_sum"), &[], sum);
+    let count = DB.stats.calls.load(Ordering::Relaxed);
+    sample(o, &(name.to_owned() + "_count"), &[], count);
+
+    let ticks = TICKS.lock().unwrap();
+    let name = "ksite_tick_duration_seconds";
+    header(o, name, "summary", "Scheduled job duration.");
+    for (task, stats) in ticks.iter() {
+        let sum = stats.sum_us as f64 / 1e6;
+        sample(o, &(name.to_owned() + "_sum"), &[("task", task)], sum);
+        sample(
+            o,
+            &(name.to_owned() + "_count"),
+            &[("task", task)],
+            stats.runs,
+        );
+    }
+    let name = "ksite_tick_last_duration_seconds";
+    header(o, name, "gauge", "Scheduled job last run duration.");
+    for (task, stats) in ticks.iter() {
+        sample(o, name, &[("task", task)], stats.last_us as f64 / 1e6);
+    }
+    let name = "ksite_tick_failures_total";
+    header(
+        o,
+        name,
+        "counter",
+        "Scheduled job runs failed or not finished.",
+    );
+    for (task, stats) in ticks.iter() {
+        sample(o, name, &[("task", task)], stats.failures);
+    }
+    drop(ticks);
+
+    header(o, "ksite_chat_rooms", "gauge", "Active chat rooms.");
+    header(
+        o,
+        "ksite_chat_subscribers",
+        "gauge",
+        "Connected chat subscribers.",
+    );
+    for (unit, (rooms, subscribers)) in [("chat", chat::stats()), ("meet", meet::stats())] {
+        sample(o, "ksite_chat_rooms", &[("unit", unit)], rooms);
+        sample(o, "ksite_chat_subscribers", &[("unit", unit)], subscribers);
+    }
+}
+
+async fn render() -> String {
+    let mut o = String::new();
+    render_sync(&mut o);
+    let name = "ksite_dav_storage_bytes";
+    header(&mut o, name, "gauge", "Dav storage usage by user.");
+    for (uid, bytes) in dav::storage_usage().await {
+        sample(&mut o, name, &[("user", &uid)], bytes);
+    }
+    o
+}
+
+async fn get_handler(headers: HeaderMap) -> Response {
+    // scrape config: `authorization: { credentials: "<metrics_token>" }`
+    let Some(token) = admin::db::get("metrics_token".to_owned()).await else {
+        let msg = "please set metrics_token in database.";
+        return (StatusCode::NOT_FOUND, msg).into_response();
+    };
+    let authed = headers.get(AUTHORIZATION).is_some_and(|v| {
+        let v = v.as_bytes();
+        v.starts_with(b"Bearer ") && bytes_eq(&v[b"Bearer ".len()..], &token)
+    });
+    if !authed {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let content_type = "text/plain; version=0.0.4; charset=utf-8";
+    ([(CONTENT_TYPE, content_type)], render().await).into_response()
+}
+
+fn service() -> Router {
+    Router::new().route("/metrics", MethodRouter::new().get(get_handler))
+}
+
+pub struct Metrics;
+
+impl crate::units::Unit for Metrics {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["metrics_token"]
+    }
+}
@@ -1,17 +1,21 @@
 //! Lazy mirror for caching linux distros' packages.
 
+use crate::scheduler::{CatchUp, Job};
 use crate::utils::LazyLock as Lazy;
 use crate::utils::{str2req, with_retry, CLIENT};
-use crate::{care, include_src, log, ticker};
+use crate::{care, crons, log, template};
 use axum::body::HttpBody;
 use axum::extract::Path;
+use axum::http::header::CONTENT_TYPE;
 use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse, Response};
 use axum::routing::{MethodRouter, Router};
+use serde_json::{json, Value};
 use std::fmt::Write as _;
 use std::future::poll_fn;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
@@ -19,59 +23,72 @@ use tokio::sync::mpsc;
 mod db {
     use crate::database::DB;
     use crate::strip_str;
-    pub fn init() {
-        let db = DB.lock().unwrap();
-        let sql = strip_str! {"
-            CREATE TABLE IF NOT EXISTS mirror (path BLOB PRIMARY KEY, time INTEGER, finished INTEGER)
-        "};
-        let mut stmd = db.prepare(sql).unwrap();
-        stmd.execute(()).unwrap();
-    }
-    pub fn get(path: &str) -> Option<(u64, bool)> {
-        let db = DB.lock().unwrap();
-        let sql = strip_str! {"
-            SELECT rowid, finished FROM mirror WHERE path = ?
-        "};
-        let mut stmd = db.prepare_cached(sql).unwrap();
-        stmd.query_row((path.as_bytes(),), |r| Ok((r.get(0)?, r.get(1)?)))
-            .ok()
-    }
-    pub fn add(path: &str) {
-        let db = DB.lock().unwrap();
-        let sql = strip_str! {"
-            INSERT INTO mirror VALUES (?, strftime('%s', 'now'), 0)
-        "};
-        let mut stmd = db.prepare_cached(sql).unwrap();
-        stmd.execute((path.as_bytes(),)).unwrap();
-    }
-    pub fn set_finished(path: &str) {
-        let db = DB.lock().unwrap();
-        let sql = strip_str! {"
-            UPDATE mirror SET finished = 1 WHERE path = ?
-        "};
-        let mut stmd = db.prepare_cached(sql).unwrap();
-        stmd.execute((path.as_bytes(),)).unwrap();
-    }
-    pub fn del(path: &str) {
-        let db = DB.lock().unwrap();
-        let sql = strip_str! {"
-            DELETE FROM mirror WHERE path = ?
-        "};
-        let mut stmd = db.prepare_cached(sql).unwrap();
-        stmd.execute((path.as_bytes(),)).unwrap();
-    }
-    pub fn list() -> Vec<(u64, String, u64)> {
-        let db = DB.lock().unwrap();
-        let sql = strip_str! {"
-            SELECT rowid, path, time FROM mirror
-        "};
-        let mut stmd = db.prepare_cached(sql).unwrap();
-        stmd.query_map((), |r| {
-            Ok((r.get(0)?, String::from_utf8(r.get(1)?).unwrap(), r.get(1)?))
+    pub async fn init() {
+        DB.call(|db| {
+            let sql = strip_str! {"
+                CREATE TABLE IF NOT EXISTS mirror (path BLOB PRIMARY KEY, time INTEGER, finished INTEGER)
+            "};
+            let mut stmd = db.prepare(sql).unwrap();
+            stmd.execute(()).unwrap();
         })
-        .unwrap()
-        .map(|v| v.unwrap())
-        .collect()
+        .await
+    }
+    pub async fn get(path: String) -> Option<(u64, bool)> {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                SELECT rowid, finished FROM mirror WHERE path = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_row((path.as_bytes(),), |r| Ok((r.get(0)?, r.get(1)?)))
+                .ok()
+        })
+        .await
+    }
+    pub async fn add(path: String) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                INSERT INTO mirror VALUES (?, strftime('%s', 'now'), 0)
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((path.as_bytes(),)).unwrap();
+        })
+        .await
+    }
+    pub async fn set_finished(path: String) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                UPDATE mirror SET finished = 1 WHERE path = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((path.as_bytes(),)).unwrap();
+        })
+        .await
+    }
+    pub async fn del(path: String) {
+        DB.call(move |db| {
+            let sql = strip_str! {"
+                DELETE FROM mirror WHERE path = ?
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.execute((path.as_bytes(),)).unwrap();
+        })
+        .await
+    }
+    /// Returns `(rowid, path, time)` of all entries.
+    pub async fn list() -> Vec<(u64, String, u64)> {
+        DB.call(|db| {
+            let sql = strip_str! {"
+                SELECT rowid, path, time FROM mirror
+            "};
+            let mut stmd = db.prepare_cached(sql).unwrap();
+            stmd.query_map((), |r| {
+                Ok((r.get(0)?, String::from_utf8(r.get(1)?).unwrap(), r.get(2)?))
+            })
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+        })
+        .await
     }
 }
 
@@ -93,7 +110,7 @@ fn gen_file_path(rowid: u64) -> PathBuf {
 async fn handle(req_path: &str, target: String) -> Response {
     let fetch_target =
         || async { care!(with_retry(|| CLIENT.fetch(str2req(&target), None), 3, 500).await) };
-    let db_get_result = db::get(req_path);
+    let db_get_result = db::get(req_path.to_owned()).await;
     if let Some((rowid, true)) = db_get_result {
         let file = File::open(gen_file_path(rowid)).await.unwrap();
         let reader_stream = tokio_util::io::ReaderStream::new(file);
@@ -106,12 +123,12 @@ async fn handle(req_path: &str, target: String) -> Response {
             Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
         };
     }
-    db::add(req_path); // insert first to avoid condition race
+    db::add(req_path.to_owned()).await; // insert first to avoid condition race
     let mut body = match fetch_target().await {
         Ok(v) => v.into_body(),
         Err(e) => {
             log!(erro: "{e:?}");
-            db::del(req_path);
+            db::del(req_path.to_owned()).await;
             return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
     };
@@ -119,7 +136,7 @@ async fn handle(req_path: &str, target: String) -> Response {
     let req_path = req_path.to_owned();
     // even if the connection closed, the store process still running
     tokio::spawn(async move {
-        let rowid = db::get(&req_path).unwrap().0;
+        let rowid = db::get(req_path.to_owned()).await.unwrap().0;
         let file_path = gen_file_path(rowid);
         let mut file = File::create(&file_path).await.unwrap();
         while let Some(result) = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
@@ -139,37 +156,51 @@ async fn handle(req_path: &str, target: String) -> Response {
                     file.set_len(0).await.unwrap();
                     drop(file);
                     tokio::fs::remove_file(&file_path).await.unwrap();
-                    db::del(&req_path);
+                    db::del(req_path).await;
                     return;
                 }
             }
         }
-        db::set_finished(&req_path);
+        db::set_finished(req_path).await;
     });
     let rx_stream = tokio_stream::wrappers::ReceiverStream::new(rx);
     axum::body::Body::from_stream(rx_stream).into_response()
 }
 
-pub fn service() -> Router {
-    db::init();
+/// The data behind both the html page and the json api, entries are sorted by size descending.
+async fn model() -> Value {
+    let mut list = Vec::new();
+    for (rowid, path, time) in db::list().await {
+        let size = tokio::fs::metadata(gen_file_path(rowid)).await;
+        let size = size.map(|v| v.len()).unwrap_or(0); // the file may be creating
+        list.push((size, path, time));
+    }
+    list.sort_by_key(|v| std::cmp::Reverse(v.0));
+    let entries: Vec<_> = list
+        .into_iter()
+        .map(|(size, path, time)| json!({ "path": path, "size": size, "time": time }))
+        .collect();
+    json!({ "entries": entries })
+}
+
+fn render(m: &Value) -> String {
+    let entries: Vec<_> = (m["entries"].as_array().unwrap().iter())
+        .map(|v| json!({ "size": format!("{: >12}", v["size"]), "path": v["path"] }))
+        .collect();
+    template!("page.html", title = "Mirror").render(&json!({ "entries": entries }))
+}
+
+fn service() -> Router {
     Router::new()
         .route(
             "/mirror",
+            MethodRouter::new().get(|| async { Html(render(&model().await)) }),
+        )
+        .route(
+            "/mirror/api",
             MethodRouter::new().get(|| async {
-                const PAGE: [&str; 2] = include_src!("page.html");
-                let mut ret = String::new();
-                ret += PAGE[0];
-                let mut list = db::list();
-                for (number, _, _) in &mut list {
-                    *number = std::fs::metadata(gen_file_path(*number)).unwrap().len();
-                }
-                list.sort_by_key(|v| v.0);
-                for (size, path, _) in list.into_iter().rev() {
-                    // TODO: escape
-                    writeln!(&mut ret, "{size: >12} {path}").unwrap();
-                }
-                ret += PAGE[1];
-                Html(ret)
+                let content_type = [(CONTENT_TYPE, "application/json")];
+                (content_type, model().await.to_string())
             }),
         )
         .route(
@@ -200,18 +231,49 @@ pub fn service() -> Router {
         )
 }
 
-pub async fn tick() {
-    ticker!(return, 8, "XX:14:00");
-
-    tokio::task::spawn_blocking(|| {
-        let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
-        for (id, req_path, time) in db::list() {
-            if now - time < 3600 * 24 {
-                continue;
-            }
-            let file_path = gen_file_path(id);
-            std::fs::remove_file(file_path).unwrap();
-            db::del(&req_path);
+async fn clean_expired() -> anyhow::Result<()> {
+    let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    for (id, req_path, time) in db::list().await {
+        if now - time < 3600 * 24 {
+            continue;
         }
-    });
+        let file_path = gen_file_path(id);
+        tokio::fs::remove_file(file_path).await.ok(); // may be removed by failed download
+        db::del(req_path).await;
+    }
+    Ok(())
+}
+
+fn job() -> Job {
+    Job {
+        name: "mirror",
+        crons: crons!("XX:14:00"),
+        zone: &8,
+        catch_up: CatchUp::Once,
+        jitter: Duration::ZERO,
+        timeout: Duration::from_secs(45),
+        retries: 0,
+        retry_interval: Duration::ZERO,
+        run: || Box::pin(clean_expired()),
+    }
+}
+
+pub struct Mirror;
+
+impl crate::units::Unit for Mirror {
+    fn name(&self) -> &'static str {
+        "mirror"
+    }
+    fn default_enabled(&self) -> bool {
+        false
+    }
+    fn migrate(&self) -> crate::units::BoxFuture<()> {
+        Box::pin(db::init())
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn jobs(&self) -> Vec<Job> {
+        vec![job()]
+    }
 }
@@ -6,7 +6,101 @@ pub mod dav;
 pub mod info;
 pub mod magazine;
 pub mod meet;
-// pub mod mirror;
+pub mod metrics;
+pub mod mirror;
 pub mod qqbot;
 pub mod v2exdaily;
 // pub mod health;
+
+use crate::assets::Asset;
+use crate::scheduler::Job;
+use anyhow::Result;
+use axum::Router;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// A feature of the site. Nothing of a disabled unit is called, so it costs nothing.
+pub trait Unit: Sync {
+    fn name(&self) -> &'static str;
+    /// Whether enabled if not mentioned in the `units` config.
+    fn default_enabled(&self) -> bool {
+        true
+    }
+    /// Create or upgrade database tables, runs before `routes`.
+    fn migrate(&self) -> BoxFuture<()> {
+        Box::pin(async {})
+    }
+    /// Build the router, and start background tasks if any. Called once.
+    fn routes(&self) -> Router {
+        Router::new()
+    }
+    fn jobs(&self) -> Vec<Job> {
+        Vec::new()
+    }
+    /// Served under hashed urls by `assets::service`.
+    fn assets(&self) -> Vec<&'static Asset> {
+        Vec::new()
+    }
+    /// Keys in `admin` table, with an optional hint after a space like `"v2ex_cookies (json array)"`.
+    fn config_keys(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Returns the reason if something is wrong.
+    fn health(&self) -> BoxFuture<Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The stale units like `health` and `proxy` need porting before being listed here.
+pub static ALL: [&dyn Unit; 11] = [
+    &admin::Admin,
+    &chat::Chat,
+    &copilotgpt::CopilotGpt,
+    &dav::Dav,
+    &info::Info,
+    &magazine::Magazine,
+    &meet::Meet,
+    &metrics::Metrics,
+    &mirror::Mirror,
+    &qqbot::QqBot,
+    &v2exdaily::V2exDaily,
+];
+
+static ENABLED: OnceLock<Vec<&'static dyn Unit>> = OnceLock::new();
+
+/// Enabled units, empty before `load`.
+pub fn enabled() -> &'static [&'static dyn Unit] {
+    ENABLED.get().map_or(&[], |v| v)
+}
+
+pub fn is_enabled(name: &str) -> bool {
+    enabled().iter().any(|v| v.name() == name)
+}
+
+/// Parse the `units` config like `{"mirror":true,"qqbot":false}`, rejects unknown names.
+pub fn parse_config(json: &[u8]) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let config: serde_json::Map<_, _> = serde_json::from_slice(json)?;
+    for (k, v) in &config {
+        if !ALL.iter().any(|u| u.name() == k) || !v.is_boolean() {
+            return Err(anyhow::anyhow!("unknown unit or not boolean, name = {k}"));
+        }
+    }
+    Ok(config)
+}
+
+/// Decide which units are enabled by the `units` config, takes effect after restart.
+pub async fn load() -> &'static [&'static dyn Unit] {
+    admin::db::init().await;
+    let config = admin::db::get("units".to_owned()).await;
+    let config = config
+        .and_then(|v| parse_config(&v).ok())
+        .unwrap_or_default();
+    let enabled = ALL.iter().copied().filter(|u| {
+        let v = config.get(u.name()).and_then(|v| v.as_bool());
+        u.name() == "admin" || v.unwrap_or(u.default_enabled()) // admin is required to change config
+    });
+    ENABLED.get_or_init(|| enabled.collect())
+}
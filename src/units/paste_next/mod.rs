@@ -69,8 +69,7 @@ server: compare(token, target = hash(time + id)), ret(result)
 */
 use self::consts::*;
 use self::misc::*;
-use crate::include_page;
-use crate::ticker::Ticker;
+use crate::{include_page, ticker};
 use axum::body::{Body, Bytes, HttpBody};
 use axum::extract::FromRequest;
 use axum::http::header::{HeaderMap, HeaderValue, CONTENT_LENGTH};
@@ -905,11 +904,8 @@ pub fn service() -> Router {
     )
 }
 
-static TICKER: Lazy<Ticker> = Lazy::new(|| Ticker::new_p8(&[(-1, 0, 0), (-1, 30, 0)]));
 pub async fn tick() {
-    if !TICKER.tick() {
-        return;
-    }
+    ticker!(return, 8, "XX:00:00", "XX:30:00");
     token::renew_tick();
 }
 
@@ -1,9 +1,10 @@
 //! QQ robot for fun.
 
 use crate::auth::auth_layer;
+use crate::scheduler::{CatchUp, Job};
 use crate::units::admin;
 use crate::utils::{block_on, fetch_json, fetch_text, str2req, LazyLock, OptionResult};
-use crate::{care, log, ticker};
+use crate::{care, crons, log};
 use anyhow::Result;
 use axum::body::Bytes;
 use axum::http::header::*;
@@ -15,7 +16,9 @@ use rand::{thread_rng, Rng as _};
 use ricq::handler::QEvent;
 use ricq::msg::{MessageChain, MessageElem};
 use ricq::{Client, LoginResponse, Protocol, QRCodeState};
+use std::future::Future;
 use std::io::Write as _;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, UNIX_EPOCH};
 
@@ -276,8 +279,18 @@ async fn notify(msg: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn service() -> Router {
+/// Forward info probe alerts to the notify groups.
+struct QqbotNotifier;
+
+impl crate::units::info::Notifier for QqbotNotifier {
+    fn notify(&self, msg: String) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> {
+        Box::pin(async move { notify(&msg).await })
+    }
+}
+
+fn service() -> Router {
     CLIENT.get_status(); // init client
+    crate::units::info::add_notifier(QqbotNotifier);
     Router::new()
         .route(
             "/qqbot",
@@ -309,8 +322,7 @@ pub fn service() -> Router {
     // });
 }
 
-pub async fn tick() {
-    ticker!(return, 8, "XX:08:00", "XX:38:00");
+async fn check_releases() -> Result<()> {
     async fn update_notify(
         last_ver: &'static Mutex<String>,
         fetch_uri: &'static str,
@@ -365,7 +377,7 @@ pub async fn tick() {
         }};
     }
     #[rustfmt::skip]
-    tokio::join!(
+    let r = tokio::join!(
     update_notify(
         make_ver_store!(),
         "https://github.com/golang/go/tags",
@@ -399,4 +411,36 @@ pub async fn tick() {
     );
     // joinset     6059432 bytes
     // join macro  6043496 bytes
+    r.0.and(r.1).and(r.2).and(r.3).and(r.4)
+}
+
+fn job() -> Job {
+    Job {
+        name: "qqbot",
+        crons: crons!("XX:08:00", "XX:38:00"),
+        zone: &8,
+        catch_up: CatchUp::Skip,
+        jitter: Duration::ZERO,
+        timeout: Duration::from_secs(45),
+        retries: 0,
+        retry_interval: Duration::ZERO,
+        run: || Box::pin(check_releases()),
+    }
+}
+
+pub struct QqBot;
+
+impl crate::units::Unit for QqBot {
+    fn name(&self) -> &'static str {
+        "qqbot"
+    }
+    fn routes(&self) -> Router {
+        service()
+    }
+    fn jobs(&self) -> Vec<Job> {
+        vec![job()]
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["qqbot_device", "qqbot_token", "qqbot_notify_groups"]
+    }
 }
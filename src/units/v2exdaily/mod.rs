@@ -1,8 +1,9 @@
 //! Do v2ex.com daily sign-in.
 
+use crate::scheduler::{CatchUp, Job};
 use crate::units::admin;
 use crate::utils::{with_retry, LazyLock, OptionResult, CLIENT_NO_SNI};
-use crate::{care, include_src, log, ticker};
+use crate::{care, crons, include_src, log};
 use anyhow::Result;
 use axum::body::{Body, Bytes};
 use axum::http::header::{HeaderName, HeaderValue};
@@ -45,11 +46,49 @@ async fn do_mission(cookie: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn tick() {
-    ticker!(return, 8, "08:14:00");
-    let cookies = care!(admin::db::get("v2ex_cookies".to_owned()).await.e(), return); // open F12, copy as nodejs fetch
-    let cookies = care!(serde_json::from_slice::<Vec<String>>(&cookies), return);
+async fn sign_in_all() -> Result<()> {
+    let cookies = admin::db::get("v2ex_cookies".to_owned()).await; // open F12, copy as nodejs fetch
+    let cookies = cookies.ok_or_else(|| anyhow::anyhow!("v2ex_cookies is not set"))?;
+    let cookies = serde_json::from_slice::<Vec<String>>(&cookies)?;
+    let mut ret = Ok(());
     for cookie in cookies {
-        care!(with_retry(|| do_mission(&cookie), 3, 2000).await, continue);
+        ret = care!(with_retry(|| do_mission(&cookie), 3, 2000).await).and(ret);
+    }
+    ret
+}
+
+fn job() -> Job {
+    Job {
+        name: "v2exdaily",
+        crons: crons!("08:14:00"),
+        zone: &8,
+        catch_up: CatchUp::Once, // the mission is daily
+        jitter: Duration::from_secs(60),
+        timeout: Duration::from_secs(120),
+        retries: 0, // already retried for each account
+        retry_interval: Duration::ZERO,
+        run: || Box::pin(sign_in_all()),
+    }
+}
+
+pub struct V2exDaily;
+
+impl crate::units::Unit for V2exDaily {
+    fn name(&self) -> &'static str {
+        "v2exdaily"
+    }
+    fn jobs(&self) -> Vec<Job> {
+        vec![job()]
+    }
+    fn config_keys(&self) -> &'static [&'static str] {
+        &["v2ex_cookies (json array)"]
+    }
+    fn health(&self) -> crate::units::BoxFuture<Result<()>> {
+        Box::pin(async {
+            match admin::db::get("v2ex_cookies".to_owned()).await {
+                Some(_) => Ok(()),
+                None => Err(anyhow::anyhow!("v2ex_cookies is not set")),
+            }
+        })
     }
 }
@@ -4,6 +4,7 @@ use axum::body::{Body, Bytes};
 use axum::http::header::HOST;
 use axum::http::Request;
 use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 
@@ -32,6 +33,18 @@ impl<T> std::ops::Deref for LazyLock<T> {
 pub struct Mono<T> {
     #[allow(clippy::type_complexity)]
     tx: tokio::sync::mpsc::Sender<Box<dyn FnOnce(&mut T) + Send>>,
+    pub stats: MonoStats,
+}
+
+/// Statistics of `Mono::call`, the latency includes the time waiting in queue.
+#[derive(Default)]
+pub struct MonoStats {
+    /// Calls waiting in queue or running.
+    pub pending: AtomicU64,
+    /// Finished calls.
+    pub calls: AtomicU64,
+    /// Sum of finished calls' latency, in microseconds.
+    pub latency_us: AtomicU64,
 }
 
 impl<T: Send + 'static> Mono<T> {
@@ -43,10 +56,23 @@ impl<T: Send + 'static> Mono<T> {
                 f(&mut v);
             }
         });
-        Self { tx }
+        Self {
+            tx,
+            stats: Default::default(),
+        }
     }
 
     pub async fn call<R: Send + 'static>(&self, f: impl FnOnce(&mut T) -> R + Send + 'static) -> R {
+        let instant = Instant::now();
+        // decrease on drop, the future may be dropped before finished, like the timeout of request
+        struct Pending<'a>(&'a AtomicU64);
+        impl Drop for Pending<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        self.stats.pending.fetch_add(1, Ordering::Relaxed);
+        let pending = Pending(&self.stats.pending);
         let mutex = Arc::new(tokio::sync::Mutex::const_new(None));
         let mut guard = mutex.clone().lock_owned().await;
         self.tx
@@ -54,6 +80,12 @@ impl<T: Send + 'static> Mono<T> {
             .await
             .unwrap();
         let mut guard = mutex.lock().await;
+        let latency_us = instant.elapsed().as_micros() as u64;
+        drop(pending);
+        self.stats.calls.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .latency_us
+            .fetch_add(latency_us, Ordering::Relaxed);
         guard.take().unwrap()
     }
 }
@@ -268,6 +300,11 @@ impl GcraRateLimiter {
     }
 }
 
+/// Compare in constant time for the same length, for the secrets like tokens and hashes.
+pub fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
 /// This function supports non-standard UUID, which is required in GitHub Copilot
 pub fn rand_id(sections: &[usize]) -> Vec<u8> {
     debug_assert!(!sections.is_empty() && sections.iter().all(|&v| v != 0));
@@ -290,6 +327,20 @@ pub fn rand_id(sections: &[usize]) -> Vec<u8> {
 /// Operations about const string.
 ///
 pub mod str_const_ops_ {
+    pub const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
     const fn kmp<const A: usize, const B: usize>(s: &[u8], p: [u8; A]) -> [usize; B] {
         let mut next = [0; A];
         let mut i = 1;